@@ -0,0 +1,73 @@
+//! A 3x5 pixel font for labelling images, covering letters, digits and the punctuation common in
+//! file names. Lowercase is drawn as uppercase and anything else as a space.
+
+/// Height of a glyph in pixels.
+pub const GLYPH_HEIGHT: usize = 5;
+/// How far apart glyphs are drawn: 3 pixels wide and a pixel of space.
+pub const ADVANCE: usize = 4;
+
+/// Each glyph is 5 rows of 3 bits, top row first and leftmost pixel the highest bit.
+const GLYPHS: [(char, u16); 39] = [
+    ('A', 0b111_101_111_101_101),
+    ('B', 0b110_101_110_101_110),
+    ('C', 0b111_100_100_100_111),
+    ('D', 0b110_101_101_101_110),
+    ('E', 0b111_100_110_100_111),
+    ('F', 0b111_100_110_100_100),
+    ('G', 0b111_100_101_101_111),
+    ('H', 0b101_101_111_101_101),
+    ('I', 0b111_010_010_010_111),
+    ('J', 0b001_001_001_101_111),
+    ('K', 0b101_101_110_101_101),
+    ('L', 0b100_100_100_100_111),
+    ('M', 0b101_111_111_101_101),
+    ('N', 0b110_101_101_101_101),
+    ('O', 0b111_101_101_101_111),
+    ('P', 0b111_101_111_100_100),
+    ('Q', 0b111_101_101_111_001),
+    ('R', 0b110_101_110_101_101),
+    ('S', 0b111_100_111_001_111),
+    ('T', 0b111_010_010_010_010),
+    ('U', 0b101_101_101_101_111),
+    ('V', 0b101_101_101_101_010),
+    ('W', 0b101_101_111_111_101),
+    ('X', 0b101_101_010_101_101),
+    ('Y', 0b101_101_010_010_010),
+    ('Z', 0b111_001_010_100_111),
+    ('0', 0b111_101_101_101_111),
+    ('1', 0b010_110_010_010_111),
+    ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111),
+    ('4', 0b101_101_111_001_001),
+    ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111),
+    ('7', 0b111_001_001_001_001),
+    ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111),
+    ('-', 0b000_000_111_000_000),
+    ('_', 0b000_000_000_000_111),
+    ('.', 0b000_000_000_000_010),
+];
+
+/// Draw `text` in `color` into RGBA `pixels` that are `width` wide, with its top left at `x`,
+/// `y`. Characters that would cross `max_x` are left off.
+pub fn draw_text(pixels: &mut [u8], width: usize, (x, y): (usize, usize), max_x: usize, text: &str, color: [u8; 4]) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * ADVANCE;
+        if left + ADVANCE - 1 > max_x {
+            break;
+        }
+        let Some(&(_, bits)) = GLYPHS.iter().find(|(glyph, _)| *glyph == c.to_ascii_uppercase()) else {
+            continue;
+        };
+
+        for row in 0..GLYPH_HEIGHT {
+            for column in 0..3 {
+                if bits >> ((GLYPH_HEIGHT - 1 - row) * 3 + 2 - column) & 1 == 1 {
+                    let at = ((y + row) * width + left + column) * 4;
+                    pixels[at..at + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
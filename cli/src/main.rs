@@ -0,0 +1,913 @@
+use std::{env::args, fs::{self, File}, io::IsTerminal, path::{Path, PathBuf}, process::exit};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+mod font;
+
+pub use pie_format::{PixelFormat, DecodedPIE, EncodedPIE, Palette, read, write, encode, decode, write_animation, decode_metadata, set_metadata, lint as lint_bytes, fix, Severity, migrate as migrate_bytes, PaletteBundle, decode_pivot, decode_animation, DecodeError, decode_png, decode_indexed_png, compare};
+
+/// Enter a `tracing` span for the rest of the scope when the `tracing` feature is enabled.
+macro_rules! span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($($args)*).entered();
+    };
+}
+
+const USAGE: &str = "Usage:
+    pie_format <image.png|dir> [-e] [--palette <palette.pip|act>]
+    pie_format palette <image.png|pie|pip|act> -o <out.pip|act>
+    pie_format encode-shared <dir> --palette-out <shared.pip|act> [--quantize <colors>]
+    pie_format icon <file.pie> [--sizes 16,32,48] -o <out.ico|cur>
+    pie_format diff <a.pie> <b.pie> [--palette <file>] [--heatmap <out.png>]
+    pie_format montage <file.pie|dir>... [--columns 8] [--padding 2] [--labels] [--palette <file>] -o <sheet.png>
+    pie_format thumb <file.pie|dir> [--size 64] [--crop] [--palette <file>] --out <dir>
+    pie_format animate <frames.png>... [--fps <fps>] -o <out.pie>
+    pie_format stats <dir> [--csv <report.csv>]
+    pie_format meta <file.pie> [--json]
+    pie_format meta get <file.pie> <key>
+    pie_format meta set <file.pie> <key> <value>
+    pie_format lint <file.pie>... [--fix]
+    pie_format migrate <file.pie>... --to <version>
+    pie_format bundle <palettes.piep> [<name>=<image.png|pie|pip|act>...]
+
+Options:
+    -v, -vv      Log what is happening to stderr, more v for more detail
+    -q, --quiet  No progress bar or summary for directories
+    --threads N  Threads to encode with, or set PIE_THREADS, defaults to one per core";
+
+/// A PNG -> PIE CLI converter. Takes one argument `-e` that is whether to embed the Palette data
+/// into the image.
+/// For custom palette ordering, you will want to create your own functions using [`encode`] and [`decode`].
+fn main() {
+    let mut args: Vec<String> = args().collect();
+    let verbosity: usize = args.iter().filter(|arg| is_verbose_flag(arg)).map(|arg| arg.len() - 1).sum();
+    args.retain(|arg| !is_verbose_flag(arg));
+    init_logging(verbosity);
+
+    let quiet = args.iter().any(|arg| arg == "-q" || arg == "--quiet");
+    args.retain(|arg| arg != "-q" && arg != "--quiet");
+
+    let threads = match args.iter().position(|arg| arg == "--threads") {
+        Some(at) => {
+            let value = args.get(at + 1).cloned();
+            args.drain(at..(at + 2).min(args.len()));
+            Some(value)
+        }
+        None => None,
+    };
+    if let Err(message) = init_threads(threads) {
+        eprintln!("error: {}", message);
+        exit(1);
+    }
+
+    let result = match args.get(1).map(String::as_str) {
+        None | Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some("animate") => animate(&args[2..]),
+        Some("stats") => stats(&args[2..], quiet),
+        Some("meta") => meta(&args[2..]),
+        Some("lint") => lint(&args[2..]),
+        Some("migrate") => migrate(&args[2..]),
+        Some("bundle") => bundle(&args[2..]),
+        Some("palette") => palette(&args[2..]),
+        Some("encode-shared") => encode_shared(&args[2..], quiet),
+        Some("icon") => icon(&args[2..]),
+        Some("thumb") => thumb(&args[2..], quiet),
+        Some("montage") => montage(&args[2..]),
+        Some("diff") => diff(&args[2..]),
+        Some(_) => convert(&args[1..], quiet),
+    };
+
+    if let Err(message) = result {
+        eprintln!("error: {}", message);
+        exit(1);
+    }
+}
+
+fn is_verbose_flag(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v')
+}
+
+/// Size the thread pool from `--threads`, if it was passed, or else the `PIE_THREADS` variable.
+#[cfg(feature = "rayon")]
+fn init_threads(flag: Option<Option<String>>) -> Result<(), String> {
+    let threads = match flag {
+        Some(value) => value.ok_or("--threads expects a positive number")?,
+        None => match std::env::var("PIE_THREADS") {
+            Ok(value) => value,
+            Err(_) => return Ok(()),
+        },
+    };
+    let threads = threads.parse::<usize>().ok().filter(|threads| *threads > 0).ok_or_else(|| format!("{} is not a positive number of threads", threads))?;
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().map_err(|error| error.to_string())
+}
+
+#[cfg(not(feature = "rayon"))]
+fn init_threads(flag: Option<Option<String>>) -> Result<(), String> {
+    if flag.is_some() {
+        eprintln!("warning: built without the rayon feature, everything runs on one thread");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "tracing")]
+fn init_logging(verbosity: usize) {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let level = match verbosity {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    // From -vv, print how long each span took as it closes.
+    let span_events = if verbosity > 1 { FmtSpan::CLOSE } else { FmtSpan::NONE };
+    tracing_subscriber::fmt().with_max_level(level).with_span_events(span_events).with_writer(std::io::stderr).init();
+}
+
+#[cfg(not(feature = "tracing"))]
+fn init_logging(verbosity: usize) {
+    if verbosity > 0 {
+        eprintln!("warning: built without the tracing feature, -v does nothing");
+    }
+}
+
+/// Tracks a batch of files, drawing a progress bar when stdout is a terminal and summarising
+/// the files, bytes and failures at the end.
+struct Batch {
+    bar: Option<ProgressBar>,
+    quiet: bool,
+    files: usize,
+    bytes_in: u64,
+    bytes_out: u64,
+    failures: usize,
+}
+
+impl Batch {
+    fn new(len: usize, quiet: bool) -> Batch {
+        let bar = (!quiet && std::io::stdout().is_terminal()).then(|| {
+            let bar = ProgressBar::new(len as u64);
+            bar.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}").unwrap());
+            bar
+        });
+
+        Batch { bar, quiet, files: 0, bytes_in: 0, bytes_out: 0, failures: 0 }
+    }
+
+    /// Run the work for one file, which returns how many bytes it read and wrote. Failures are
+    /// reported and counted rather than stopping the batch.
+    fn file(&mut self, name: &str, work: impl FnOnce() -> Result<(u64, u64), String>) {
+        span!("file", path = %name);
+        if let Some(bar) = &self.bar {
+            bar.set_message(name.to_string());
+        }
+
+        match work() {
+            Ok((bytes_in, bytes_out)) => {
+                self.files += 1;
+                self.bytes_in += bytes_in;
+                self.bytes_out += bytes_out;
+            }
+            Err(message) => {
+                self.failures += 1;
+                match &self.bar {
+                    Some(bar) => bar.suspend(|| eprintln!("error: {}", message)),
+                    None => eprintln!("error: {}", message),
+                }
+            }
+        }
+
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+
+        if !self.quiet {
+            eprintln!("{} files, {} bytes in, {} bytes out, {} failed", self.files, self.bytes_in, self.bytes_out, self.failures);
+        }
+
+        match self.failures {
+            0 => Ok(()),
+            failures => Err(format!("{} of {} files failed", failures, failures + self.files)),
+        }
+    }
+}
+
+/// Convert a PNG, or every PNG under a directory, to PIE files next to them.
+fn convert(args: &[String], quiet: bool) -> Result<(), String> {
+    let mut embed_palette = false;
+    let mut palette = None;
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => embed_palette = true,
+            "--palette" => {
+                let file = args.next().ok_or("--palette expects a palette file")?;
+                palette = Some(load_palette(file)?);
+            }
+            _ => path = Some(Path::new(arg)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("no image given\n{}", USAGE))?;
+    let palette = palette.as_ref();
+
+    if !path.is_dir() {
+        let out_path = convert_file(path, embed_palette, palette)?.0;
+        println!("wrote: {:?}", out_path.to_str().unwrap());
+        return Ok(());
+    }
+
+    let files = files_with_extension(path, "png")?;
+    let mut batch = Batch::new(files.len(), quiet);
+    for file in &files {
+        batch.file(&file.to_string_lossy(), || convert_file(file, embed_palette, palette).map(|(_, bytes_in, bytes_out)| (bytes_in, bytes_out)));
+    }
+    batch.finish()
+}
+
+/// Convert one PNG to a PIE file next to it, returning the new path and the sizes of both files.
+fn convert_file(path: &Path, embed_palette: bool, palette: Option<&Palette>) -> Result<(PathBuf, u64, u64), String> {
+    span!("convert", path = %path.display());
+    let name = path.to_string_lossy();
+    let mut out_path = path.to_path_buf();
+    out_path.set_extension("pie");
+
+    // Indexed PNGs keep their own palette and indices unless converted against another palette.
+    let indexed = if palette.is_none() { load_indexed_png(&name)? } else { None };
+    let encoded = match indexed {
+        Some(mut encoded) => {
+            encoded.palette = encoded.palette.filter(|_| embed_palette);
+            Ok(encoded)
+        }
+        None => {
+            let (width, height, bytes) = load_png(&name)?;
+            encode_pixels(width, height, &bytes, embed_palette, palette)
+        }
+    };
+    let encoded = encoded.map_err(|error| format!("could not encode {}: {}", name, error))?;
+    fs::write(&out_path, encoded.to_bytes()).map_err(|error| format!("could not write {}: {}", out_path.display(), error))?;
+
+    Ok((out_path.clone(), file_size(path)?, file_size(&out_path)?))
+}
+
+/// Encode with the work spread over the thread pool when built with the rayon feature.
+fn encode_pixels(width: u16, height: u16, pixels: &[u8], embed_palette: bool, palette: Option<&Palette>) -> Result<EncodedPIE, pie_format::EncodeError> {
+    #[cfg(feature = "rayon")]
+    return pie_format::encode_parallel(width, height, pixels, embed_palette, palette);
+    #[cfg(not(feature = "rayon"))]
+    return encode(width, height, pixels, embed_palette, palette);
+}
+
+/// Convert every PNG under a directory against one palette of all their colours, written to its
+/// own file rather than embedded. With `--quantize`, the palette is reduced to that many colours
+/// and images are remapped onto it, otherwise more than 256 colours is an error.
+fn encode_shared(args: &[String], quiet: bool) -> Result<(), String> {
+    let mut dir = None;
+    let mut palette_path = None;
+    let mut max_colors = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palette-out" => palette_path = Some(args.next().ok_or("--palette-out expects a path")?),
+            "--quantize" => max_colors = Some(args.next().and_then(|colors| colors.parse::<usize>().ok()).filter(|colors| (1..=256).contains(colors)).ok_or("--quantize expects a number of colours from 1 to 256")?),
+            _ => dir = Some(Path::new(arg)),
+        }
+    }
+    let dir = dir.ok_or_else(|| format!("encode-shared expects a directory\n{}", USAGE))?;
+    let palette_path = palette_path.ok_or("missing palette path, pass --palette-out <shared.pip>")?;
+
+    span!("encode_shared", path = %dir.display());
+    let files = files_with_extension(dir, "png")?;
+    let mut images = Vec::with_capacity(files.len());
+    for file in &files {
+        images.push(load_png(&file.to_string_lossy())?);
+    }
+
+    // Images with and without alpha can be mixed, so bring everything to RGBA if any has alpha.
+    let has_alpha = |(width, height, pixels): &(u16, u16, Vec<u8>)| pixels.len() == *width as usize * *height as usize * 4;
+    let format = if images.iter().any(has_alpha) { PixelFormat::RGBA } else { PixelFormat::RGB };
+    if format == PixelFormat::RGBA {
+        for image in images.iter_mut().filter(|image| !has_alpha(image)) {
+            image.2 = image.2.chunks(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect();
+        }
+    }
+
+    let all: Vec<u8> = images.iter().flat_map(|(_, _, pixels)| pixels.iter().copied()).collect();
+    let palette = match max_colors {
+        Some(max_colors) => Palette::quantized(&all, format, max_colors),
+        None => Palette::from_pixels(&all, format).map_err(|error| format!("{}: {}, pass --quantize 256 to reduce them", dir.display(), error))?,
+    };
+    let written = if palette_path.ends_with(".act") { palette.write_act(palette_path) } else { palette.write_pip(palette_path) };
+    written.map_err(|error| format!("could not write {}", error))?;
+    if !quiet {
+        println!("wrote: {:?} with {} colours", palette_path, palette.colors.len() / if format == PixelFormat::RGBA { 4 } else { 3 });
+    }
+
+    let mut batch = Batch::new(files.len(), quiet);
+    for (file, (width, height, pixels)) in files.iter().zip(&images) {
+        let name = file.to_string_lossy();
+        batch.file(&name, || {
+            let remapped;
+            let pixels = if max_colors.is_some() {
+                remapped = palette.remap(pixels);
+                &remapped
+            } else {
+                pixels
+            };
+            let encoded = encode_pixels(*width, *height, pixels, false, Some(&palette)).map_err(|error| format!("could not encode {}: {}", name, error))?;
+            let out_path = file.with_extension("pie");
+            fs::write(&out_path, encoded.to_bytes()).map_err(|error| format!("could not write {}: {}", out_path.display(), error))?;
+            Ok((file_size(file)?, file_size(&out_path)?))
+        });
+    }
+    batch.finish()
+}
+
+fn file_size(path: &Path) -> Result<u64, String> {
+    fs::metadata(path).map(|metadata| metadata.len()).map_err(|error| format!("could not read {}: {}", path.display(), error))
+}
+
+/// Assemble PNG frames into one animated PIE file with a shared, embedded palette.
+fn animate(args: &[String]) -> Result<(), String> {
+    let mut inputs = Vec::new();
+    let mut fps = 12;
+    let mut out_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => fps = args.next().and_then(|fps| fps.parse::<u16>().ok()).filter(|fps| (1..=1000).contains(fps)).ok_or("--fps expects a number from 1 to 1000, as frames last whole milliseconds")?,
+            "-o" | "--output" => out_path = Some(args.next().ok_or("-o expects a path")?),
+            _ => inputs.push(arg),
+        }
+    }
+
+    let out_path = out_path.ok_or("missing output path, pass -o <out.pie>")?;
+    if inputs.is_empty() {
+        return Err("no frames given".to_string());
+    }
+
+    let mut frames: Vec<(u16, u16, Vec<u8>)> = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        span!("frame", path = %input);
+        let frame = load_png(input)?;
+        if let Some(first) = frames.first() {
+            if (frame.0, frame.1) != (first.0, first.1) {
+                return Err(format!("{} is {}x{} but {} is {}x{}", input, frame.0, frame.1, inputs[0], first.0, first.1));
+            }
+        }
+        frames.push(frame);
+    }
+
+    let (width, height) = (frames[0].0, frames[0].1);
+    let pixel_count = width as usize * height as usize;
+
+    // Frames with and without alpha can be mixed, so bring everything to RGBA if any has alpha.
+    if frames.iter().any(|frame| frame.2.len() == pixel_count * 4) {
+        for frame in frames.iter_mut().filter(|frame| frame.2.len() == pixel_count * 3) {
+            frame.2 = frame.2.chunks(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect();
+        }
+    }
+
+    let pixels: Vec<&[u8]> = frames.iter().map(|frame| frame.2.as_slice()).collect();
+    write_animation(out_path, width, height, true, None, &pixels, 1000 / fps)
+        .map_err(|error| format!("could not write {}", error))?;
+
+    println!("wrote: {:?} ({} frames)", out_path, frames.len());
+    Ok(())
+}
+
+/// Report how well every PNG under a directory encodes, as a table or as CSV.
+fn stats(args: &[String], quiet: bool) -> Result<(), String> {
+    let mut dir = None;
+    let mut csv_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csv" => csv_path = Some(args.next().ok_or("--csv expects a path")?),
+            _ => dir = Some(arg),
+        }
+    }
+
+    let dir = dir.ok_or("missing directory")?;
+    let mut rows = vec![["file", "width", "height", "palette_size", "runs", "average_run", "pie_size", "external_pie_size", "png_size", "ratio"].map(String::from)];
+
+    let files = files_with_extension(Path::new(dir), "png")?;
+    let mut batch = Batch::new(files.len(), quiet);
+
+    for path in &files {
+        let name = path.to_string_lossy().to_string();
+        batch.file(&name, || {
+            let (width, height, pixels) = load_png(&name)?;
+            let encoded = encode_pixels(width, height, &pixels, true, None).map_err(|error| format!("could not encode {}: {}", name, error))?;
+            let stats = encoded.stats();
+            let size = stats.bytes_out;
+            let external_size = EncodedPIE { palette: None, ..encoded }.to_bytes().len();
+            let png_size = file_size(path)?;
+
+            rows.push([
+                name.clone(),
+                width.to_string(),
+                height.to_string(),
+                stats.colors.to_string(),
+                stats.runs.to_string(),
+                format!("{:.2}", stats.average_run),
+                size.to_string(),
+                external_size.to_string(),
+                png_size.to_string(),
+                format!("{:.2}", size as f64 / png_size as f64),
+            ]);
+            Ok((png_size, size as u64))
+        });
+    }
+    let summary = batch.finish();
+
+    if let Some(csv_path) = csv_path {
+        let csv: String = rows.iter().map(|row| row.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",") + "\n").collect();
+        fs::write(csv_path, csv).map_err(|error| format!("could not write {}: {}", csv_path, error))?;
+        println!("wrote: {:?} ({} files)", csv_path, rows.len() - 1);
+    } else {
+        let widths: Vec<usize> = (0..rows[0].len()).map(|i| rows.iter().map(|row| row[i].len()).max().unwrap()).collect();
+        for row in &rows {
+            let line: Vec<String> = row.iter().zip(&widths).map(|(field, width)| format!("{:width$}", field, width = width)).collect();
+            println!("{}", line.join("  ").trim_end());
+        }
+    }
+
+    summary
+}
+
+/// Quote a CSV field if it needs it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Print or change the metadata of a PIE file in place.
+fn meta(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let path = match args.as_slice() {
+        ["get", path, _] | ["set", path, _, _] | [path] | [path, "--json"] => *path,
+        _ => return Err(format!("unexpected arguments\n{}", USAGE)),
+    };
+
+    span!("meta", path);
+    let bytes = fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+    let mut metadata = decode_metadata(&bytes).map_err(|error| format!("could not read {}: {}", path, error))?;
+
+    match args.as_slice() {
+        ["get", _, key] => match metadata.get(key) {
+            Some(value) => println!("{}", value),
+            None => return Err(format!("{} has no {:?}", path, key)),
+        },
+        ["set", _, key, value] => {
+            metadata.set(key, value);
+            let bytes = set_metadata(&bytes, &metadata).map_err(|error| format!("could not update {}: {}", path, error))?;
+            fs::write(path, bytes).map_err(|error| format!("could not write {}: {}", path, error))?;
+        }
+        [_, "--json"] => {
+            let entries: Vec<String> = metadata.iter().map(|(key, value)| format!("  {}: {}", json_string(key), json_string(value))).collect();
+            if entries.is_empty() {
+                println!("{{}}");
+            } else {
+                println!("{{\n{}\n}}", entries.join(",\n"));
+            }
+        }
+        _ => {
+            for (key, value) in metadata.iter() {
+                println!("{}={}", key, value);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Report spec violations and warnings for each file, rewriting them with `--fix` where that
+/// would not change the image. Fails if any errors remain.
+fn lint(args: &[String]) -> Result<(), String> {
+    let do_fix = args.iter().any(|arg| arg == "--fix");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--fix").collect();
+    if paths.is_empty() {
+        return Err(format!("lint expects at least one file\n{}", USAGE));
+    }
+
+    let mut errors = 0;
+    for path in paths {
+        span!("lint", path = path.as_str());
+        let bytes = fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+        let mut issues = lint_bytes(&bytes);
+
+        if do_fix && issues.iter().any(|issue| issue.fixable) {
+            match fix(&bytes) {
+                Ok(fixed) => {
+                    fs::write(path, &fixed).map_err(|error| format!("could not write {}: {}", path, error))?;
+                    println!("{}: fixed, {} -> {} bytes", path, bytes.len(), fixed.len());
+                    issues = lint_bytes(&fixed);
+                }
+                Err(error) => println!("{}: could not fix: {}", path, error),
+            }
+        }
+
+        for issue in &issues {
+            let severity = if issue.severity == Severity::Error { "error" } else { "warning" };
+            let hint = if issue.fixable && !do_fix { " (--fix)" } else { "" };
+            println!("{}: {}: {}{}", path, severity, issue.message, hint);
+        }
+        errors += issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    }
+
+    match errors {
+        0 => Ok(()),
+        errors => Err(format!("{} errors", errors)),
+    }
+}
+
+/// Rewrite files in place as another version of the format.
+fn migrate(args: &[String]) -> Result<(), String> {
+    let mut version = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" => version = Some(args.next().and_then(|version| version.parse::<u8>().ok()).ok_or("--to expects a version number")?),
+            _ => paths.push(arg),
+        }
+    }
+    let version = version.ok_or_else(|| format!("migrate expects --to <version>\n{}", USAGE))?;
+
+    for path in paths {
+        span!("migrate", path = path.as_str(), version);
+        let bytes = fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+        let migrated = migrate_bytes(&bytes, version).map_err(|error| format!("could not migrate {}: {}", path, error))?;
+        fs::write(path, migrated).map_err(|error| format!("could not write {}: {}", path, error))?;
+    }
+
+    Ok(())
+}
+
+/// Add the palettes of images to a bundle, creating it if needed, or list what it holds.
+fn bundle(args: &[String]) -> Result<(), String> {
+    let Some((path, entries)) = args.split_first() else {
+        return Err(format!("bundle expects a .piep file\n{}", USAGE));
+    };
+
+    span!("bundle", path = path.as_str());
+    if entries.is_empty() {
+        let bundle = PaletteBundle::read(path).map_err(|error| format!("could not read {}", error))?;
+        for (name, palette) in bundle.iter() {
+            let stride = if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+            println!("{}: {} colours, {:?}", name, palette.colors.len() / stride, palette.format);
+        }
+        return Ok(());
+    }
+
+    let mut bundle = match Path::new(path).exists() {
+        true => PaletteBundle::read(path).map_err(|error| format!("could not read {}", error))?,
+        false => PaletteBundle::default(),
+    };
+    for entry in entries {
+        let (name, image) = entry.split_once('=').ok_or_else(|| format!("expected <name>=<image> but found {}", entry))?;
+        bundle.set(name, load_palette(image)?);
+    }
+    bundle.write(path).map_err(|error| format!("could not write {}", error))?;
+
+    println!("wrote: {:?} ({} palettes)", path, bundle.iter().count());
+    Ok(())
+}
+
+/// Write the palette of an image to a .pip or .act file.
+fn palette(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let [image, "-o" | "--output", out_path] = args.as_slice() else {
+        return Err(format!("unexpected arguments\n{}", USAGE));
+    };
+
+    span!("palette", path = image);
+    let palette = load_palette(image)?;
+    let written = if out_path.ends_with(".act") { palette.write_act(out_path) } else { palette.write_pip(out_path) };
+    written.map_err(|error| format!("could not write {}", error))?;
+    println!("wrote: {:?}", out_path);
+    Ok(())
+}
+
+/// Write a .ico, or a .cur with its hotspot at the pivot, at several sizes.
+fn icon(args: &[String]) -> Result<(), String> {
+    let mut input = None;
+    let mut out_path = None;
+    let mut sizes = vec![16, 32, 48, 256];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sizes" => {
+                let list = args.next().ok_or("--sizes expects sizes like 16,32")?;
+                sizes = list.split(',').map(|size| size.trim().parse::<u16>().ok().filter(|size| (1..=256).contains(size)))
+                    .collect::<Option<_>>().ok_or("--sizes expects sizes from 1 to 256")?;
+            }
+            "-o" | "--output" => out_path = Some(args.next().ok_or("-o expects a path")?),
+            _ => input = Some(arg),
+        }
+    }
+    let input = input.ok_or_else(|| format!("icon expects a .pie file\n{}", USAGE))?;
+    let out_path = out_path.ok_or("missing output path, pass -o <out.ico>")?;
+
+    span!("icon", path = input.as_str());
+    let bytes = fs::read(input).map_err(|error| format!("could not read {}: {}", input, error))?;
+    let image = decode(&bytes, None).map_err(|error| format!("could not read {}: {}", input, error))?;
+    let file = File::create(out_path).map_err(|error| format!("could not write {}: {}", out_path, error))?;
+
+    let written = if out_path.ends_with(".cur") {
+        let hotspot = decode_pivot(&bytes).map_err(|error| format!("could not read {}: {}", input, error))?.unwrap_or((0, 0));
+        image.to_cur(&sizes, hotspot, file)
+    } else {
+        image.to_ico(&sizes, file)
+    };
+    written.map_err(|error| format!("could not write {}: {}", out_path, error))?;
+
+    println!("wrote: {:?} ({} sizes)", out_path, sizes.len());
+    Ok(())
+}
+
+/// Write a square PNG thumbnail of a PIE file, or of every PIE file under a directory, into an
+/// output directory that mirrors the input's layout.
+fn thumb(args: &[String], quiet: bool) -> Result<(), String> {
+    let mut input = None;
+    let mut out_dir = None;
+    let mut size = 64;
+    let mut crop = false;
+    let mut palette = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = args.next().and_then(|size| size.parse::<u16>().ok()).filter(|size| *size > 0).ok_or("--size expects a positive number")?,
+            "--crop" => crop = true,
+            "--palette" => {
+                let file = args.next().ok_or("--palette expects a palette file")?;
+                palette = Some(load_palette(file)?);
+            }
+            "--out" | "-o" => out_dir = Some(Path::new(args.next().ok_or("--out expects a directory")?)),
+            _ => input = Some(Path::new(arg)),
+        }
+    }
+    let input = input.ok_or_else(|| format!("thumb expects a .pie file or directory\n{}", USAGE))?;
+    let out_dir = out_dir.ok_or("missing output directory, pass --out <dir>")?;
+
+    let (root, files) = match input.is_dir() {
+        true => (input, files_with_extension(input, "pie")?),
+        false => (input.parent().unwrap_or(Path::new("")), vec![input.to_path_buf()]),
+    };
+    let mut batch = Batch::new(files.len(), quiet);
+    for file in &files {
+        let mut out_path = out_dir.join(file.strip_prefix(root).unwrap_or(file));
+        out_path.set_extension("png");
+
+        batch.file(&file.to_string_lossy(), || {
+            span!("thumb", path = %file.display());
+            let name = file.to_string_lossy();
+            let image = read(&name, palette.as_ref()).map_err(|error| format!("could not read {}", error))?;
+            let pixels = if crop { image.thumbnail_cropped(size) } else { image.thumbnail(size) };
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent).map_err(|error| format!("could not create {}: {}", parent.display(), error))?;
+            }
+            save_png(&out_path, size as u32, size as u32, &pixels)?;
+            Ok((file_size(file)?, file_size(&out_path)?))
+        });
+    }
+    batch.finish()
+}
+
+/// Arrange PIE files in a grid on one PNG, every frame of animations included, for looking over a
+/// set of sprites at once.
+fn montage(args: &[String]) -> Result<(), String> {
+    let mut inputs = Vec::new();
+    let mut columns = 8;
+    let mut padding = 0;
+    let mut labels = false;
+    let mut palette = None;
+    let mut out_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--columns" => columns = args.next().and_then(|columns| columns.parse::<usize>().ok()).filter(|columns| *columns > 0).ok_or("--columns expects a positive number")?,
+            "--padding" => padding = args.next().and_then(|padding| padding.parse::<usize>().ok()).ok_or("--padding expects a number")?,
+            "--labels" => labels = true,
+            "--palette" => {
+                let file = args.next().ok_or("--palette expects a palette file")?;
+                palette = Some(load_palette(file)?);
+            }
+            "-o" | "--output" => out_path = Some(Path::new(args.next().ok_or("-o expects a path")?)),
+            _ => match Path::new(arg).is_dir() {
+                true => inputs.append(&mut files_with_extension(Path::new(arg), "pie")?),
+                false => inputs.push(PathBuf::from(arg)),
+            },
+        }
+    }
+    let out_path = out_path.ok_or("missing output path, pass -o <sheet.png>")?;
+    if inputs.is_empty() {
+        return Err("no images given".to_string());
+    }
+
+    // Every image as (label, width, height, RGBA pixels).
+    let mut cells = Vec::new();
+    for input in &inputs {
+        span!("cell", path = %input.display());
+        let name = input.to_string_lossy();
+        let bytes = fs::read(input).map_err(|error| format!("could not read {}: {}", name, error))?;
+        let stem = input.file_stem().map_or(name.to_string(), |stem| stem.to_string_lossy().to_string());
+
+        match decode_animation(&bytes, palette.as_ref()) {
+            Ok(animation) => for (i, frame) in animation.frames.into_iter().enumerate() {
+                let image = DecodedPIE { width: animation.width, height: animation.height, format: animation.format, pixels: frame.pixels, palette: animation.palette.clone(), chunks: Vec::new(), comments: Vec::new(), density: None };
+                cells.push((format!("{} {}", stem, i), image.width as usize, image.height as usize, image.to_rgba8()));
+            },
+            Err(DecodeError::NotAnimated) => {
+                let image = decode(&bytes, palette.as_ref()).map_err(|error| format!("could not read {}: {}", name, error))?;
+                cells.push((stem, image.width as usize, image.height as usize, image.to_rgba8()));
+            }
+            Err(error) => return Err(format!("could not read {}: {}", name, error)),
+        }
+    }
+
+    let cell_width = cells.iter().map(|cell| cell.1).max().unwrap_or(0);
+    let cell_height = cells.iter().map(|cell| cell.2).max().unwrap_or(0);
+    // Labels sit under each image on a white strip with a pixel of space around the text.
+    let label_height = if labels { font::GLYPH_HEIGHT + 2 } else { 0 };
+    let columns = columns.min(cells.len());
+    let rows = cells.len().div_ceil(columns);
+    // Checked, as padding comes straight from the command line.
+    let side = |count: usize, cell: usize| cell.checked_add(padding)?.checked_mul(count)?.checked_add(padding);
+    let (width, height) = match (side(columns, cell_width), side(rows, cell_height + label_height)) {
+        (Some(width), Some(height)) if width <= u32::MAX as usize && height <= u32::MAX as usize
+            && width.checked_mul(height).is_some_and(|area| area <= isize::MAX as usize / 4) => (width, height),
+        _ => return Err(format!("a sheet of {}x{} cells of {}x{} pixels with {} pixels of padding is too large", columns, rows, cell_width, cell_height + label_height, padding)),
+    };
+
+    let mut pixels = vec![0; width * height * 4];
+    for (i, (label, cell_w, cell_h, rgba)) in cells.iter().enumerate() {
+        let left = padding + i % columns * (cell_width + padding);
+        let top = padding + i / columns * (cell_height + label_height + padding);
+        for y in 0..*cell_h {
+            let to = ((top + y) * width + left) * 4;
+            pixels[to..to + cell_w * 4].copy_from_slice(&rgba[y * cell_w * 4..(y + 1) * cell_w * 4]);
+        }
+
+        if labels {
+            let strip = top + cell_height;
+            for y in strip..strip + label_height {
+                pixels[(y * width + left) * 4..(y * width + left + cell_width) * 4].fill(0xFF);
+            }
+            font::draw_text(&mut pixels, width, (left + 1, strip + 1), left + cell_width, label, [0, 0, 0, 0xFF]);
+        }
+    }
+
+    save_png(out_path, width as u32, height as u32, &pixels)?;
+    println!("wrote: {:?} ({} images)", out_path, cells.len());
+    Ok(())
+}
+
+/// Compare two images pixel by pixel, failing if they differ so scripts can check for changes.
+fn diff(args: &[String]) -> Result<(), String> {
+    let mut inputs = Vec::new();
+    let mut palette = None;
+    let mut heatmap_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palette" => {
+                let file = args.next().ok_or("--palette expects a palette file")?;
+                palette = Some(load_palette(file)?);
+            }
+            "--heatmap" => heatmap_path = Some(Path::new(args.next().ok_or("--heatmap expects a path")?)),
+            _ => inputs.push(arg.as_str()),
+        }
+    }
+    let [a_path, b_path] = inputs.as_slice() else {
+        return Err(format!("diff expects two images\n{}", USAGE));
+    };
+
+    span!("diff", a = a_path, b = b_path);
+    let read_image = |path: &str| -> Result<DecodedPIE, String> {
+        let bytes = fs::read(path).map_err(|error| format!("could not read {}: {}", path, error))?;
+        decode(&bytes, palette.as_ref()).map_err(|error| format!("could not read {}: {}", path, error))
+    };
+    let (a, b) = (read_image(a_path)?, read_image(b_path)?);
+    let result = compare(&a, &b);
+    if result.identical {
+        println!("identical");
+        return Ok(());
+    }
+
+    if (a.width, a.height) != (b.width, b.height) {
+        println!("sizes differ: {}x{} and {}x{}", a.width, a.height, b.width, b.height);
+    } else {
+        println!("{} of {} pixels differ, largest channel difference {}", result.differing, a.width as usize * a.height as usize, result.max_delta);
+    }
+    if let (Some(path), Some(heatmap)) = (heatmap_path, &result.heatmap) {
+        save_png(path, heatmap.width as u32, heatmap.height as u32, &heatmap.to_rgba8())?;
+        println!("wrote: {:?}", path);
+    }
+    Err("images differ".to_string())
+}
+
+/// The palette of a .pip, .act or .kpl file, the embedded palette of a PIE file, or every colour of a
+/// PNG.
+fn load_palette(path: &str) -> Result<Palette, String> {
+    if path.ends_with(".act") {
+        return Palette::read_act(path).map_err(|error| format!("could not read {}", error));
+    }
+    #[cfg(feature = "kpl")]
+    if path.ends_with(".kpl") {
+        return Palette::read_kpl(path).map_err(|error| format!("could not read {}", error));
+    }
+    if path.ends_with(".pip") {
+        return Palette::read_pip(path).map_err(|error| format!("could not read {}", error));
+    }
+    if path.ends_with(".pie") {
+        return read(path, None).map(|decoded| decoded.palette).map_err(|error| format!("could not read {}", error));
+    }
+
+    let (width, height, pixels) = load_png(path)?;
+    let format = if pixels.len() == width as usize * height as usize * 4 { PixelFormat::RGBA } else { PixelFormat::RGB };
+    Palette::from_pixels(&pixels, format).map_err(|error| format!("could not use {}: {}", path, error))
+}
+
+/// Quote and escape a string for JSON output.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Every file under `dir` with `extension`, recursively, in a stable order.
+fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|error| format!("could not read {}: {}", dir.display(), error))?;
+
+    for entry in entries {
+        let path = entry.map_err(|error| format!("could not read {}: {}", dir.display(), error))?.path();
+        if path.is_dir() {
+            files.append(&mut files_with_extension(&path, extension)?);
+        } else if path.extension().is_some_and(|found| found.eq_ignore_ascii_case(extension)) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Read a PNG into `(width, height, pixels)` with pixels in RGB or RGBA byte format.
+fn load_png(path: &str) -> Result<(u16, u16, Vec<u8>), String> {
+    span!("load_png");
+    let file = File::open(path).map_err(|error| format!("could not open {}: {}", path, error))?;
+    decode_png(file).map_err(|error| format!("could not read {}: {}", path, error))
+}
+
+/// Read an indexed PNG with its exact palette embedded, or `None` if it is not indexed.
+fn load_indexed_png(path: &str) -> Result<Option<EncodedPIE>, String> {
+    span!("load_indexed_png");
+    let file = File::open(path).map_err(|error| format!("could not open {}: {}", path, error))?;
+    decode_indexed_png(file).map_err(|error| format!("could not read {}: {}", path, error))
+}
+
+/// Write RGBA pixels to a PNG.
+fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|error| format!("could not write {}: {}", path.display(), error))?;
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|error| format!("could not write {}: {}", path.display(), error))?;
+    writer.write_image_data(pixels).map_err(|error| format!("could not write {}: {}", path.display(), error))
+}
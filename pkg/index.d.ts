@@ -0,0 +1,21 @@
+/** An external palette, for files that do not embed theirs. */
+export interface Palette {
+  format: "rgb" | "rgba";
+  /** 3 bytes per colour for "rgb", 4 for "rgba". */
+  colors: ArrayLike<number>;
+}
+
+/**
+ * Load the decoder. `source` defaults to pie_wasm.wasm beside this file, and may be a URL, a
+ * Response or the bytes of the module.
+ */
+export function init(source?: string | URL | Response | Promise<Response> | BufferSource): Promise<void>;
+
+/**
+ * Decode a PIE file to `ImageData`. `palette` is needed when the file has no embedded palette.
+ * Throws the decoder's message if the file is invalid.
+ */
+export function decodePie(bytes: Uint8Array | ArrayBuffer, palette?: Palette): ImageData;
+
+/** Decode a PIE file and draw it to `canvas`, resizing the canvas to fit. */
+export function drawPie(canvas: HTMLCanvasElement | OffscreenCanvas, bytes: Uint8Array | ArrayBuffer, palette?: Palette): ImageData;
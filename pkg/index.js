@@ -0,0 +1,65 @@
+// Browser wrapper around the exports of the pie-wasm crate.
+let exports = null;
+
+/**
+ * Load the decoder. `source` defaults to pie_wasm.wasm beside this file, and may be a URL, a
+ * Response or the bytes of the module.
+ */
+export async function init(source = new URL("pie_wasm.wasm", import.meta.url)) {
+  if (exports) {
+    return;
+  }
+  if (typeof source === "string" || source instanceof URL) {
+    source = fetch(source);
+  }
+  source = await source;
+  const { instance } = source instanceof Response
+    ? await WebAssembly.instantiateStreaming(source)
+    : await WebAssembly.instantiate(source);
+  exports = instance.exports;
+}
+
+/** Copy `bytes` into wasm memory, returning the pointer to free afterwards. */
+function copyIn(bytes) {
+  const pointer = exports.pie_alloc(bytes.length);
+  new Uint8Array(exports.memory.buffer, pointer, bytes.length).set(bytes);
+  return pointer;
+}
+
+/**
+ * Decode a PIE file to `ImageData`. `palette` is needed when the file has no embedded palette.
+ * Throws the decoder's message if the file is invalid.
+ */
+export function decodePie(bytes, palette) {
+  if (!exports) {
+    throw new Error("call init() before decoding");
+  }
+  bytes = bytes instanceof Uint8Array ? bytes : new Uint8Array(bytes);
+  const colors = palette ? Uint8Array.from(palette.colors) : new Uint8Array(0);
+
+  const bytesPointer = copyIn(bytes);
+  const palettePointer = copyIn(colors);
+  let ok;
+  try {
+    ok = exports.pie_decode(bytesPointer, bytes.length, palettePointer, colors.length, palette?.format === "rgba");
+  } finally {
+    exports.pie_free(bytesPointer, bytes.length);
+    exports.pie_free(palettePointer, colors.length);
+  }
+
+  const output = new Uint8Array(exports.memory.buffer, exports.pie_output(), exports.pie_output_length());
+  if (!ok) {
+    throw new Error(new TextDecoder().decode(output));
+  }
+  // Copy out of wasm memory, which the next decode overwrites.
+  return new ImageData(new Uint8ClampedArray(output), exports.pie_width(), exports.pie_height());
+}
+
+/** Decode a PIE file and draw it to `canvas`, resizing the canvas to fit. */
+export function drawPie(canvas, bytes, palette) {
+  const image = decodePie(bytes, palette);
+  canvas.width = image.width;
+  canvas.height = image.height;
+  canvas.getContext("2d").putImageData(image, 0, 0);
+  return image;
+}
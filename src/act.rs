@@ -0,0 +1,80 @@
+//! Adobe Color Table (`.act`) palettes, as Photoshop and many other tools write them: 256 RGB
+//! colours, optionally followed by the number of colours used and the index of the transparent
+//! one, both u16 (BE).
+use std::fs;
+
+use crate::{read_file, section, DecodeError, EncodeError, Palette, PixelFormat};
+
+/// Transparent index meaning there is none.
+const NO_TRANSPARENCY: u16 = 0xFFFF;
+
+impl Palette {
+    /// Parse the bytes of a `.act` file. A transparent colour makes the palette RGBA, with that
+    /// colour's alpha 0 and the rest opaque.
+    pub fn from_act(bytes: impl AsRef<[u8]>) -> Result<Palette, DecodeError> {
+        let bytes = bytes.as_ref();
+        let table = section(bytes, 0..768)?;
+        let (count, transparent) = match bytes.get(768..772) {
+            Some(extra) => (u16::from_be_bytes([extra[0], extra[1]]) as usize, u16::from_be_bytes([extra[2], extra[3]])),
+            None => (256, NO_TRANSPARENCY),
+        };
+        if count > 256 {
+            return Err(DecodeError::InvalidPalette { offset: 768 });
+        }
+
+        let colors = &table[..count * 3];
+        if transparent == NO_TRANSPARENCY || transparent as usize >= count {
+            return Ok(Palette { format: PixelFormat::RGB, colors: colors.to_vec() });
+        }
+        let colors = colors.chunks_exact(3).enumerate().flat_map(|(index, color)| {
+            [color[0], color[1], color[2], if index == transparent as usize { 0 } else { 0xFF }]
+        }).collect();
+        Ok(Palette { format: PixelFormat::RGBA, colors })
+    }
+
+    /// The palette as the bytes of a `.act` file, always with the colour count. The first fully
+    /// transparent colour of an RGBA palette is marked transparent, and other alpha is dropped.
+    pub fn to_act(&self) -> Vec<u8> {
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let colors = self.colors.chunks_exact(stride).take(256);
+        let count = colors.len();
+        let transparent = match self.format {
+            PixelFormat::RGBA => colors.clone().position(|color| color[3] == 0).map_or(NO_TRANSPARENCY, |index| index as u16),
+            PixelFormat::RGB => NO_TRANSPARENCY,
+        };
+
+        let mut bytes: Vec<u8> = colors.flat_map(|color| [color[0], color[1], color[2]]).collect();
+        bytes.resize(768, 0);
+        bytes.extend_from_slice(&(count as u16).to_be_bytes());
+        bytes.extend_from_slice(&transparent.to_be_bytes());
+        bytes
+    }
+
+    /// Read a `.act` file.
+    pub fn read_act(path: &str) -> Result<Palette, DecodeError> {
+        Palette::from_act(read_file(path)?).map_err(|error| error.in_file(path))
+    }
+
+    /// Write a `.act` file.
+    pub fn write_act(&self, path: &str) -> Result<(), EncodeError> {
+        fs::write(path, self.to_act()).map_err(|error| EncodeError::Io(error.kind()).in_file(path))
+    }
+}
+
+#[test]
+fn test_act() {
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![1, 2, 3, 4, 5, 6] };
+    let bytes = palette.to_act();
+    assert_eq!(772, bytes.len());
+    assert_eq!(&[0, 2, 0xFF, 0xFF], &bytes[768..]);
+    assert_eq!(Ok(palette), Palette::from_act(&bytes));
+
+    let transparent = Palette { format: PixelFormat::RGBA, colors: vec![1, 2, 3, 0xFF, 4, 5, 6, 0] };
+    let bytes = transparent.to_act();
+    assert_eq!(&[0, 2, 0, 1], &bytes[768..]);
+    assert_eq!(Ok(transparent), Palette::from_act(&bytes));
+
+    // Files without the count hold all 256 colours.
+    assert_eq!(256 * 3, Palette::from_act(&bytes[..768]).unwrap().colors.len());
+    assert_eq!(Err(DecodeError::Truncated { expected: 768, found: 700 }), Palette::from_act(&bytes[..700]));
+}
@@ -0,0 +1,38 @@
+//! Dealing with alpha before palette building, for targets that want less of it.
+
+/// Composite RGBA `pixels` over `background`, dropping alpha.
+pub(crate) fn flatten(pixels: &[u8], background: [u8; 3]) -> Vec<u8> {
+    pixels.chunks_exact(4).flat_map(|pixel| {
+        let alpha = pixel[3] as u32;
+        [0, 1, 2].map(|channel| ((pixel[channel] as u32 * alpha + background[channel] as u32 * (255 - alpha) + 127) / 255) as u8)
+    }).collect()
+}
+
+/// Snap the alpha of RGBA `pixels` to opaque at `threshold` and above, and to transparent below.
+/// Transparent pixels become 0, 0, 0, 0 so that they share a palette entry.
+pub(crate) fn threshold(pixels: &[u8], threshold: u8) -> Vec<u8> {
+    pixels.chunks_exact(4).flat_map(|pixel| match pixel[3] >= threshold {
+        true => [pixel[0], pixel[1], pixel[2], 0xFF],
+        false => [0; 4],
+    }).collect()
+}
+
+#[test]
+fn test_flatten() {
+    let pixels = [10, 20, 30, 0xFF, 10, 20, 30, 0, 0xFF, 0xFF, 0xFF, 0x80];
+    assert_eq!(vec![10, 20, 30, 0, 0, 0xFF, 0x80, 0x80, 0xFF], flatten(&pixels, [0, 0, 0xFF]));
+
+    let options = crate::EncodeOptions { embed_palette: true, flatten: Some([0, 0, 0xFF]), ..Default::default() };
+    let decoded = crate::decode(crate::encode_with_options(3, 1, pixels, &options).unwrap(), None).unwrap();
+    assert_eq!((crate::PixelFormat::RGB, flatten(&pixels, [0, 0, 0xFF])), (decoded.format, decoded.pixels));
+}
+
+#[test]
+fn test_threshold() {
+    let pixels = [10, 20, 30, 0xFF, 10, 20, 30, 0x7F, 40, 50, 60, 0x80, 1, 2, 3, 1];
+    assert_eq!(vec![10, 20, 30, 0xFF, 0, 0, 0, 0, 40, 50, 60, 0xFF, 0, 0, 0, 0], threshold(&pixels, 0x80));
+
+    let options = crate::EncodeOptions { embed_palette: true, alpha_threshold: Some(0x80), ..Default::default() };
+    let decoded = crate::decode(crate::encode_with_options(4, 1, pixels, &options).unwrap(), None).unwrap();
+    assert_eq!(3 * 4, decoded.palette.colors.len());
+}
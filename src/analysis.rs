@@ -0,0 +1,52 @@
+//! Histograms of an encoded file, read straight from the runs, for deciding how an asset is best
+//! stored.
+use crate::{DecodeError, EncodedView};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Analysis {
+    /// How many runs are each length, indexed by length. Runs are at most 255 long.
+    pub run_lengths: [usize; 256],
+    /// How many pixels use each palette index.
+    pub index_counts: [usize; 256],
+    pub runs: usize,
+    pub pixels: usize,
+}
+
+impl Analysis {
+    /// Palette indices used by at least one pixel.
+    pub fn colors(&self) -> usize {
+        self.index_counts.iter().filter(|count| **count > 0).count()
+    }
+
+    /// Average pixels per run.
+    pub fn average_run(&self) -> f64 {
+        if self.runs == 0 { 0.0 } else { self.pixels as f64 / self.runs as f64 }
+    }
+}
+
+/// Count the runs and palette indices of the raw bytes of a PIE file without expanding them into
+/// pixels. For animations this is the first frame.
+pub fn analyze(bytes: impl AsRef<[u8]>) -> Result<Analysis, DecodeError> {
+    let view = EncodedView::new(bytes.as_ref())?;
+    let mut analysis = Analysis { run_lengths: [0; 256], index_counts: [0; 256], runs: 0, pixels: 0 };
+
+    for run in view.runs().chunks_exact(2) {
+        analysis.run_lengths[run[0] as usize] += 1;
+        analysis.index_counts[run[1] as usize] += run[0] as usize;
+        analysis.runs += 1;
+        analysis.pixels += run[0] as usize;
+    }
+    Ok(analysis)
+}
+
+#[test]
+fn test_analyze() {
+    let pixels = [[1, 2, 3].repeat(300), [4, 5, 6].repeat(3), [1, 2, 3].repeat(3)].concat();
+    let analysis = analyze(crate::encode(102, 3, &pixels, true, None).unwrap().to_bytes()).unwrap();
+
+    assert_eq!((4, 306, 2), (analysis.runs, analysis.pixels, analysis.colors()));
+    assert_eq!((1, 1, 2), (analysis.run_lengths[255], analysis.run_lengths[45], analysis.run_lengths[3]));
+    assert_eq!(&[303, 3], &analysis.index_counts[..2]);
+    assert_eq!(76.5, analysis.average_run());
+    assert_eq!(Err(DecodeError::BadMagic { found: *b"PNG" }), analyze(b"PNG"));
+}
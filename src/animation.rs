@@ -0,0 +1,617 @@
+//! Multi-frame PIE files. Every frame shares the dimensions and palette of the file, the first
+//! frame lives in the data section and the rest follow in `FRAM` chunks. A `FIDX` chunk holds
+//! where each `FRAM` chunk is so [`Animation`] can decode any frame without walking the others.
+use std::{borrow::Cow, fs, io::{self, Read}};
+
+use crate::{
+    chunk::{chunks, read_chunks, write_chunk, Chunk},
+    color_map::{pack, ColorMap},
+    encode, expand_runs, header_from_reader, read_file, resolve_palette, section, DecodeError, EncodeError, Header, Palette, PixelFormat,
+    FLAG_ANIMATION, FLAG_PALETTE, FLAG_TRANSPARENCY,
+};
+
+const CHUNK_ANIMATION: [u8; 4] = *b"ANIM";
+const CHUNK_FRAME: [u8; 4] = *b"FRAM";
+const CHUNK_FRAME_INDEX: [u8; 4] = *b"FIDX";
+
+/// A single frame of an [`EncodedAnimation`].
+#[derive(Debug, PartialEq)]
+pub struct EncodedFrame {
+    /// Runs `(count, index)` into the shared palette.
+    pub indices: Vec<u8>,
+    /// How long to show the frame for in milliseconds.
+    pub duration: u16,
+}
+
+/// Frames encoded against one shared palette. To write - use the [`write_animation`] function,
+/// or [`EncodedAnimation::to_bytes`] for the file contents in memory.
+#[derive(Debug, PartialEq)]
+pub struct EncodedAnimation {
+    pub width: u16,
+    pub height: u16,
+    /// The pixel format that was encoded, RGBA sets the transparency flag.
+    pub format: PixelFormat,
+    pub frames: Vec<EncodedFrame>,
+    pub palette: Option<Palette>,
+}
+
+impl EncodedAnimation {
+    /// The exact bytes of an animated PIE file holding these frames. Fails without any frames, as
+    /// the first one is the image single image decoders show, or with more than 65535.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        let Some(first) = self.frames.first() else {
+            return Err(EncodeError::NoFrames);
+        };
+        if self.frames.len() > u16::MAX as usize {
+            return Err(EncodeError::TooManyFrames { count: self.frames.len() });
+        }
+
+        let mut header = Header {
+            version: 2,
+            width: self.width,
+            height: self.height,
+            flags: FLAG_ANIMATION,
+            colors: 0,
+            runs: (first.indices.len() / 2) as u32,
+        };
+
+        if self.format == PixelFormat::RGBA {
+            header.flags |= FLAG_TRANSPARENCY;
+        }
+        if let Some(palette) = &self.palette {
+            header.flags |= FLAG_PALETTE;
+            header.colors = (palette.colors.len() / header.stride()) as u16;
+        }
+
+        let mut bytes = Vec::new();
+        header.write(&mut bytes);
+        bytes.extend_from_slice(&first.indices);
+
+        if let Some(palette) = &self.palette {
+            bytes.extend_from_slice(&palette.colors);
+        }
+
+        let mut animation = (self.frames.len() as u16).to_be_bytes().to_vec();
+        for frame in &self.frames {
+            animation.extend_from_slice(&frame.duration.to_be_bytes());
+        }
+        write_chunk(&mut bytes, CHUNK_ANIMATION, &animation);
+
+        // Offsets are from the first FRAM chunk rather than the file, so they hold while the
+        // frames stay together, even if chunks before them change.
+        let mut index = Vec::with_capacity((self.frames.len() - 1) * 4);
+        let mut offset = 0;
+        for frame in &self.frames[1..] {
+            index.extend_from_slice(&(offset as u32).to_be_bytes());
+            offset += 12 + frame.indices.len();
+        }
+        if !index.is_empty() {
+            write_chunk(&mut bytes, CHUNK_FRAME_INDEX, &index);
+        }
+
+        for frame in &self.frames[1..] {
+            let mut data = ((frame.indices.len() / 2) as u32).to_be_bytes().to_vec();
+            data.extend_from_slice(&frame.indices);
+            write_chunk(&mut bytes, CHUNK_FRAME, &data);
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// A single frame of a [`DecodedAnimation`].
+#[derive(Debug, PartialEq)]
+pub struct DecodedFrame {
+    pub pixels: Vec<u8>,
+    /// How long to show the frame for in milliseconds.
+    pub duration: u16,
+}
+
+/// Decoded animated PIE file with pixel data for every frame.
+#[derive(Debug, PartialEq)]
+pub struct DecodedAnimation {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    pub frames: Vec<DecodedFrame>,
+    /// The palette shared by every frame, embedded or external.
+    pub palette: Palette,
+}
+
+/// Encode and write an animated PIE file to disk.
+/// # Arguments
+/// * `path` - Path to the file.
+/// * `width` - Width in pixels of every frame.
+/// * `height` - Height in pixels of every frame.
+/// * `embed_palette` - If true, will embed the palette into the file.
+/// * `palette` - Optional palette to be embedded or referred to. If None, one palette will be
+///   generated from the colours of all frames.
+/// * `frames` - The pixel data of each frame in RGB or RGBA byte format.
+/// * `duration` - How long to show each frame for in milliseconds.
+pub fn write_animation(path: &str, width: u16, height: u16, embed_palette: bool, maybe_palette: Option<&Palette>, frames: &[impl AsRef<[u8]>], duration: u16) -> Result<bool, EncodeError> {
+    span!(INFO, "write_animation", path);
+    let encoded = encode_animation(width, height, frames, duration, embed_palette, maybe_palette).map_err(|error| error.in_file(path))?;
+    let bytes = encoded.to_bytes().map_err(|error| error.in_file(path))?;
+
+    fs::write(path, &bytes).map_err(|error| EncodeError::Io(error.kind()).in_file(path))?;
+    event!(DEBUG, bytes = bytes.len(), "wrote file");
+    Ok(true)
+}
+
+/// Encode frames of RGB or RGBA bytes against one shared palette into an [`EncodedAnimation`].
+/// Every frame must have the same dimensions and pixel format.
+pub fn encode_animation(width: u16, height: u16, frames: &[impl AsRef<[u8]>], duration: u16, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedAnimation, EncodeError> {
+    let frames: Vec<&[u8]> = frames.iter().map(AsRef::as_ref).collect();
+    span!(DEBUG, "encode_animation", width, height, frames = frames.len());
+    let pixel_count = width as usize * height as usize;
+    let frame_size = match frames.first() {
+        Some(frame) if frame.len() == pixel_count * 3 || frame.len() == pixel_count * 4 => frame.len(),
+        frame => return Err(EncodeError::wrong_pixel_count(pixel_count, frame.map_or(0, |frame| frame.len()))),
+    };
+
+    if let Some(frame) = frames.iter().find(|frame| frame.len() != frame_size) {
+        return Err(EncodeError::WrongPixelCount { expected: frame_size, found: frame.len() });
+    }
+    if frames.len() > u16::MAX as usize {
+        return Err(EncodeError::TooManyFrames { count: frames.len() });
+    }
+
+    // Borrow the given palette so it is only cloned if it is embedded.
+    let palette = match maybe_palette {
+        Some(palette) => Cow::Borrowed(palette),
+        None => Cow::Owned(shared_palette(&frames, frame_size / pixel_count)?),
+    };
+
+    let mut encoded = EncodedAnimation {
+        width, height,
+        format: palette.format,
+        frames: Vec::with_capacity(frames.len()),
+        palette: None,
+    };
+
+    for frame in frames {
+        encoded.frames.push(EncodedFrame {
+            indices: encode(width, height, frame, false, Some(&palette))?.indices,
+            duration,
+        });
+    }
+
+    if embed_palette {
+        encoded.palette = Some(palette.into_owned());
+    }
+
+    Ok(encoded)
+}
+
+/// Build one palette holding every colour used across `frames`, in order of first appearance.
+fn shared_palette(frames: &[&[u8]], stride: usize) -> Result<Palette, EncodeError> {
+    span!(DEBUG, "build_palette", frames = frames.len());
+    let mut palette = Palette {
+        format: if stride == 3 { PixelFormat::RGB } else { PixelFormat::RGBA },
+        colors: Vec::new(),
+    };
+    let mut map = ColorMap::default();
+
+    for chunk in frames.iter().flat_map(|frame| frame.chunks(stride)) {
+        if !map.contains_key(&pack(chunk)) {
+            map.insert(pack(chunk), map.len());
+            palette.colors.extend_from_slice(chunk);
+        }
+    }
+
+    event!(DEBUG, colors = map.len(), "built palette");
+    if map.len() > 256 {
+        return Err(EncodeError::TooManyColors { count: map.len() });
+    }
+
+    Ok(palette)
+}
+
+/// Read an animated PIE file from disk and decode every frame.
+/// Palette is required if not included in the image.
+pub fn read_animation(path: &str, palette: Option<&Palette>) -> Result<DecodedAnimation, DecodeError> {
+    span!(INFO, "read_animation", path);
+    let bytes = read_file(path)?;
+    decode_animation(&bytes, palette).map_err(|error| error.in_file(path))
+}
+
+/// Decode raw bytes from an animated PIE file into a [`DecodedAnimation`].
+/// * `bytes` - The raw bytes including header, index data, palette and frame chunks.
+/// * `palette` - Required if the palette is not embedded in `bytes`.
+pub fn decode_animation(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>) -> Result<DecodedAnimation, DecodeError> {
+    let bytes = bytes.as_ref();
+    span!(DEBUG, "decode_animation", bytes = bytes.len());
+    let header = Header::parse(bytes)?;
+    if header.flags & FLAG_ANIMATION == 0 {
+        return Err(DecodeError::NotAnimated);
+    }
+
+    let palette = resolve_palette(bytes, &header, maybe_palette)?;
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let animation = chunks.iter().find(|chunk| chunk.kind == CHUNK_ANIMATION).ok_or(DecodeError::MissingChunk { kind: CHUNK_ANIMATION })?;
+
+    let mut at = 0;
+    let frame_count = u16::from_be_bytes(animation.take(&mut at, 2)?.try_into().unwrap()) as usize;
+    let durations: Vec<u16> = animation.take(&mut at, frame_count * 2)?
+        .chunks_exact(2)
+        .map(|duration| u16::from_be_bytes([duration[0], duration[1]]))
+        .collect();
+
+    // Runs of every frame with where they start in the file, for errors.
+    let mut runs = vec![(header.size(), section(bytes, header.size()..header.palette_offset())?)];
+    for chunk in chunks.iter().filter(|chunk| chunk.kind == CHUNK_FRAME) {
+        let mut at = 0;
+        let count = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap()) as usize;
+        runs.push((chunk.offset + at, chunk.take(&mut at, count.saturating_mul(2))?));
+    }
+
+    if runs.len() != frame_count {
+        return Err(DecodeError::FrameCount { expected: frame_count, found: runs.len() });
+    }
+    event!(DEBUG, frames = frame_count, "read frames");
+
+    let pixel_count = header.width as usize * header.height as usize;
+    let mut frames = Vec::with_capacity(frame_count);
+    for ((offset, runs), duration) in runs.into_iter().zip(durations) {
+        frames.push(DecodedFrame { pixels: expand_runs(runs, offset, pixel_count, &palette)?, duration });
+    }
+
+    Ok(DecodedAnimation {
+        width: header.width,
+        height: header.height,
+        format: palette.format,
+        frames,
+        palette,
+    })
+}
+
+/// An animated PIE file that decodes one frame at a time, in any order, such as for scrubbing
+/// through an animation in an editor. Frames are stored whole, so each decodes on its own.
+#[derive(Debug, PartialEq)]
+pub struct Animation<'a> {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// The palette shared by every frame, embedded or external.
+    pub palette: Palette,
+    durations: Vec<u16>,
+    /// Runs of every frame with where they start in the file, for errors.
+    runs: Vec<(usize, &'a [u8])>,
+}
+
+impl<'a> Animation<'a> {
+    /// Find the frames of an animated PIE file without decoding them. Files with a `FIDX` chunk
+    /// only have the chunks before their frames read, others have every chunk walked.
+    /// * `palette` - Required if the palette is not embedded in `bytes`.
+    pub fn new(bytes: &'a [u8], maybe_palette: Option<&Palette>) -> Result<Animation<'a>, DecodeError> {
+        span!(DEBUG, "animation", bytes = bytes.len());
+        let header = Header::parse(bytes)?;
+        if header.flags & FLAG_ANIMATION == 0 {
+            return Err(DecodeError::NotAnimated);
+        }
+        let palette = resolve_palette(bytes, &header, maybe_palette)?;
+
+        let mut animation = None;
+        let mut index = None;
+        let mut frames = Vec::new();
+        for chunk in chunks(bytes, header.chunks_offset()) {
+            let chunk = chunk?;
+            match chunk.kind {
+                CHUNK_ANIMATION => animation = Some(chunk),
+                CHUNK_FRAME_INDEX => index = Some(chunk),
+                CHUNK_FRAME => {
+                    frames.push(chunk);
+                    // The index says where the rest are.
+                    if index.is_some() && animation.is_some() {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let animation = animation.ok_or(DecodeError::MissingChunk { kind: CHUNK_ANIMATION })?;
+        let mut at = 0;
+        let frame_count = u16::from_be_bytes(animation.take(&mut at, 2)?.try_into().unwrap()) as usize;
+        let durations: Vec<u16> = animation.take(&mut at, frame_count * 2)?
+            .chunks_exact(2)
+            .map(|duration| u16::from_be_bytes([duration[0], duration[1]]))
+            .collect();
+
+        if let (Some(index), Some(first)) = (index, frames.first().copied()) {
+            frames = indexed_frames(bytes, index, first)?;
+        }
+
+        let mut runs = vec![(header.size(), section(bytes, header.size()..header.palette_offset())?)];
+        for chunk in frames {
+            let mut at = 0;
+            let count = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap()) as usize;
+            runs.push((chunk.offset + at, chunk.take(&mut at, count.saturating_mul(2))?));
+        }
+        if runs.len() != frame_count {
+            return Err(DecodeError::FrameCount { expected: frame_count, found: runs.len() });
+        }
+        event!(DEBUG, frames = frame_count, indexed = index.is_some(), "found frames");
+
+        Ok(Animation { width: header.width, height: header.height, format: palette.format, palette, durations, runs })
+    }
+
+    /// How many frames there are.
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// How long to show frame `n` for in milliseconds, or None if there is no such frame.
+    pub fn duration(&self, n: usize) -> Option<u16> {
+        self.durations.get(n).copied()
+    }
+
+    /// Decode frame `n` alone.
+    pub fn frame(&self, n: usize) -> Result<DecodedFrame, DecodeError> {
+        span!(DEBUG, "frame", n);
+        let &(offset, runs) = self.runs.get(n).ok_or(DecodeError::FrameCount { expected: n + 1, found: self.runs.len() })?;
+        let pixel_count = self.width as usize * self.height as usize;
+        Ok(DecodedFrame { pixels: expand_runs(runs, offset, pixel_count, &self.palette)?, duration: self.durations[n] })
+    }
+}
+
+/// The `FRAM` chunks listed in the frame index, which are at offsets from the `first` of them.
+fn indexed_frames<'a>(bytes: &'a [u8], index: Chunk<'a>, first: Chunk<'a>) -> Result<Vec<Chunk<'a>>, DecodeError> {
+    let start = first.offset - 8;
+    let mut frames = Vec::with_capacity(index.data.len() / 4);
+    for (i, offset) in index.data.chunks(4).enumerate() {
+        let offset = <[u8; 4]>::try_from(offset).map(u32::from_be_bytes).map_err(|_| index.invalid(i * 4))? as usize;
+        let frame = chunks(bytes, start + offset).next().and_then(Result::ok).filter(|frame| frame.kind == CHUNK_FRAME);
+        frames.push(frame.ok_or(index.invalid(i * 4))?);
+    }
+    Ok(frames)
+}
+
+/// Frames of an animated PIE file decoded one at a time as they are read, so playing an animation
+/// only needs one frame in memory rather than all of them.
+#[derive(Debug)]
+pub struct AnimationReader<R: Read> {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// The palette shared by every frame, embedded or external.
+    pub palette: Palette,
+    reader: R,
+    durations: Vec<u16>,
+    /// Runs of the first frame, which come before the palette, and where they are in the file.
+    first: Option<(usize, Vec<u8>)>,
+    /// How many frames have been read, and how far into the file.
+    frames_read: usize,
+    position: usize,
+}
+
+impl<R: Read> AnimationReader<R> {
+    /// Read everything up to the `ANIM` chunk: the header, the runs of the first frame and the
+    /// palette.
+    /// * `palette` - Required if the palette is not embedded in the file.
+    pub fn new(mut reader: R, maybe_palette: Option<&Palette>) -> Result<AnimationReader<R>, DecodeError> {
+        span!(DEBUG, "animation_reader");
+        let header = header_from_reader(&mut reader)?;
+        if !header.is_animated() || header.version < 2 {
+            return Err(DecodeError::NotAnimated);
+        }
+
+        let mut position = header.size();
+        let first = read_exact(&mut reader, &mut position, header.runs as usize * 2)?;
+        let palette = match maybe_palette {
+            _ if header.has_palette() => Palette {
+                format: header.format(),
+                colors: read_exact(&mut reader, &mut position, header.colors as usize * header.stride())?,
+            },
+            Some(palette) => palette.clone(),
+            None => return Err(DecodeError::MissingPalette),
+        };
+
+        let mut animation = AnimationReader {
+            width: header.width,
+            height: header.height,
+            format: palette.format,
+            palette,
+            reader,
+            durations: Vec::new(),
+            first: Some((header.size(), first)),
+            frames_read: 0,
+            position,
+        };
+
+        let chunk = animation.next_chunk(CHUNK_ANIMATION)?.ok_or(DecodeError::MissingChunk { kind: CHUNK_ANIMATION })?;
+        let chunk = Chunk { kind: CHUNK_ANIMATION, data: &chunk.1, offset: chunk.0 };
+        let mut at = 0;
+        let frame_count = u16::from_be_bytes(chunk.take(&mut at, 2)?.try_into().unwrap()) as usize;
+        animation.durations = chunk.take(&mut at, frame_count * 2)?
+            .chunks_exact(2)
+            .map(|duration| u16::from_be_bytes([duration[0], duration[1]]))
+            .collect();
+
+        Ok(animation)
+    }
+
+    /// How many frames the file holds.
+    pub fn len(&self) -> usize {
+        self.durations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.durations.is_empty()
+    }
+
+    /// Skip ahead to the next chunk of `kind`, returning its data and where that is in the file,
+    /// or None at the end of the file. Frames must not come before the `ANIM` chunk, as they
+    /// could not be played without buffering them.
+    fn next_chunk(&mut self, kind: [u8; 4]) -> Result<Option<(usize, Vec<u8>)>, DecodeError> {
+        loop {
+            let mut head = [0; 8];
+            let read = (&mut self.reader).take(8).read(&mut head).map_err(|error| DecodeError::Io(error.kind()))?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if read < 8 {
+                self.reader.read_exact(&mut head[read..]).map_err(|error| DecodeError::Io(error.kind()))?;
+            }
+            self.position += 8;
+
+            let found = [head[0], head[1], head[2], head[3]];
+            let length = u32::from_be_bytes([head[4], head[5], head[6], head[7]]) as usize;
+            if found == kind {
+                let offset = self.position;
+                return Ok(Some((offset, read_exact(&mut self.reader, &mut self.position, length)?)));
+            }
+            if found == CHUNK_FRAME && kind == CHUNK_ANIMATION {
+                return Err(DecodeError::MissingChunk { kind: CHUNK_ANIMATION });
+            }
+
+            let skipped = io::copy(&mut (&mut self.reader).take(length as u64), &mut io::sink()).map_err(|error| DecodeError::Io(error.kind()))?;
+            if skipped < length as u64 {
+                return Err(DecodeError::Io(io::ErrorKind::UnexpectedEof));
+            }
+            self.position += length;
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<DecodedFrame, DecodeError> {
+        let pixel_count = self.width as usize * self.height as usize;
+        let duration = self.durations[self.frames_read];
+        if let Some((offset, runs)) = self.first.take() {
+            return Ok(DecodedFrame { pixels: expand_runs(&runs, offset, pixel_count, &self.palette)?, duration });
+        }
+
+        let (offset, data) = self.next_chunk(CHUNK_FRAME)?.ok_or(DecodeError::FrameCount { expected: self.len(), found: self.frames_read })?;
+        let chunk = Chunk { kind: CHUNK_FRAME, data: &data, offset };
+        let mut at = 0;
+        let count = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap()) as usize;
+        let runs = chunk.take(&mut at, count.saturating_mul(2))?;
+        Ok(DecodedFrame { pixels: expand_runs(runs, offset + at - runs.len(), pixel_count, &self.palette)?, duration })
+    }
+}
+
+impl<R: Read> Iterator for AnimationReader<R> {
+    type Item = Result<DecodedFrame, DecodeError>;
+
+    /// The next frame, or None once every frame has been read or after an error.
+    fn next(&mut self) -> Option<Result<DecodedFrame, DecodeError>> {
+        if self.frames_read >= self.durations.len() {
+            return None;
+        }
+        let frame = self.next_frame();
+        self.frames_read = if frame.is_ok() { self.frames_read + 1 } else { self.durations.len() };
+        Some(frame)
+    }
+}
+
+/// Read `length` bytes, moving `position` past them.
+fn read_exact(reader: &mut impl Read, position: &mut usize, length: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = Vec::new();
+    (&mut *reader).take(length as u64).read_to_end(&mut bytes).map_err(|error| DecodeError::Io(error.kind()))?;
+    if bytes.len() < length {
+        return Err(DecodeError::Truncated { expected: *position + length, found: *position + bytes.len() });
+    }
+    *position += length;
+    Ok(bytes)
+}
+
+#[test]
+fn test_animation() {
+    let frames: Vec<Vec<u8>> = vec![
+        vec![0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        vec![0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00],
+    ];
+    let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+
+    let encoded = encode_animation(3, 2, &frames, 100, true, None).unwrap();
+    assert_eq!(vec![0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00], encoded.palette.unwrap().colors);
+    assert_eq!(vec![1, 1, 3, 2, 2, 0], encoded.frames[1].indices);
+
+    assert!(write_animation("tmp_animation.pie", 3, 2, true, None, &frames, 100).is_ok());
+    let decoded = read_animation("tmp_animation.pie", None).unwrap();
+    let first = crate::read("tmp_animation.pie", None).unwrap();
+    assert!(fs::remove_file("tmp_animation.pie").is_ok());
+
+    assert_eq!(2, decoded.frames.len());
+    assert_eq!(frames[0], decoded.frames[0].pixels);
+    assert_eq!(frames[1], decoded.frames[1].pixels);
+    assert_eq!(100, decoded.frames[1].duration);
+    assert_eq!(frames[0], first.pixels);
+}
+
+#[test]
+fn test_animation_errors() {
+    let rgb = [0u8; 12];
+    let rgba = [0u8; 16];
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 12, found: 16 }), encode_animation(2, 2, &[rgb.as_slice(), &rgba], 100, true, None));
+
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0]).collect();
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), encode_animation(30, 10, &[&many], 100, true, None));
+
+    // Animations built by hand may have no frames to write.
+    let empty = EncodedAnimation { width: 2, height: 2, format: PixelFormat::RGB, frames: Vec::new(), palette: None };
+    assert_eq!(Err(EncodeError::NoFrames), empty.to_bytes());
+
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    assert_eq!(Err(DecodeError::NotAnimated), decode_animation(bytes, None));
+}
+
+#[test]
+fn test_seek_animation() {
+    let frames: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i, 0, 0, 0, 0, 0, 0xFF, 0xFF, i]).collect();
+    let bytes = encode_animation(3, 1, &frames, 50, true, None).unwrap().to_bytes().unwrap();
+    let chunk_kinds: Vec<[u8; 4]> = read_chunks(&bytes, Header::parse(&bytes).unwrap().chunks_offset()).unwrap().iter().map(|chunk| chunk.kind).collect();
+    assert_eq!(vec![CHUNK_ANIMATION, CHUNK_FRAME_INDEX, CHUNK_FRAME, CHUNK_FRAME, CHUNK_FRAME], chunk_kinds);
+
+    let animation = Animation::new(&bytes, None).unwrap();
+    assert_eq!(4, animation.len());
+    assert_eq!(Some(50), animation.duration(3));
+    for i in [3, 0, 2, 1] {
+        assert_eq!(frames[i], animation.frame(i).unwrap().pixels);
+    }
+    assert_eq!(Err(DecodeError::FrameCount { expected: 5, found: 4 }), animation.frame(4).map(|frame| frame.pixels));
+
+    // Moving the chunks before the frames does not throw the index off.
+    let moved = crate::chunk::replace_chunk(&bytes, CHUNK_ANIMATION, Some(&[0, 4, 0, 50, 0, 50, 0, 50, 0, 50])).unwrap();
+    assert_eq!(frames[2], Animation::new(&moved, None).unwrap().frame(2).unwrap().pixels);
+
+    // Files without an index are found by walking their chunks.
+    let unindexed = crate::chunk::replace_chunk(&bytes, CHUNK_FRAME_INDEX, None).unwrap();
+    assert_eq!(bytes.len() - 8 - 12, unindexed.len());
+    let animation = Animation::new(&unindexed, None).unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame, &animation.frame(i).unwrap().pixels);
+    }
+
+    let header = Header::parse(&bytes).unwrap();
+    let mut broken = bytes.clone();
+    let index = header.chunks_offset() + 8 + 2 + 8 + 8;
+    broken[index + 3] = 1;
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_FRAME_INDEX, offset: index }), Animation::new(&broken, None));
+}
+
+#[test]
+fn test_animation_reader() {
+    let frames: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i, 0, 0, 0xFF, 0xFF, i]).collect();
+    let bytes = encode_animation(2, 1, &frames, 40, true, None).unwrap().to_bytes().unwrap();
+
+    let reader = AnimationReader::new(bytes.as_slice(), None).unwrap();
+    assert_eq!((2, 1, 3), (reader.width, reader.height, reader.len()));
+    let decoded: Vec<DecodedFrame> = reader.collect::<Result<_, _>>().unwrap();
+    assert_eq!(frames, decoded.iter().map(|frame| frame.pixels.clone()).collect::<Vec<_>>());
+    assert!(decoded.iter().all(|frame| frame.duration == 40));
+
+    // Errors end the frames rather than repeating.
+    let header = Header::parse(&bytes).unwrap();
+    let mut reader = AnimationReader::new(&bytes[..header.chunks_offset() + 40], None).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(Some(Err(DecodeError::Truncated { expected: header.chunks_offset() + 48, found: header.chunks_offset() + 40 })), reader.next().map(|frame| frame.map(|frame| frame.pixels)));
+    assert!(reader.next().is_none());
+
+    assert_eq!(Err(DecodeError::MissingPalette), AnimationReader::new(encode_animation(2, 1, &frames, 40, false, None).unwrap().to_bytes().unwrap().as_slice(), None).map(|reader| reader.len()));
+    let still = include_bytes!("../images/test_embedded_palette.pie");
+    assert_eq!(Err(DecodeError::NotAnimated), AnimationReader::new(still.as_slice(), None).map(|reader| reader.len()));
+}
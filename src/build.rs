@@ -0,0 +1,137 @@
+//! Converting PNG sources to PIE files from a build script, so game projects can ship .pie assets
+//! as part of `cargo build`.
+//!
+//! ```no_run
+//! // In build.rs, with pie_format under [build-dependencies] with the png feature.
+//! println!("cargo:rerun-if-changed=assets");
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("assets");
+//! let options = pie_format::EncodeOptions { embed_palette: true, ..Default::default() };
+//! pie_format::build::convert_dir("assets", out, &options).unwrap();
+//! ```
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{decode_png, encode_with_options, ConvertError, EncodeOptions};
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// A file or directory could not be read or written.
+    Io { path: PathBuf, kind: io::ErrorKind },
+    /// The PNG at `path` could not be converted.
+    Convert { path: PathBuf, error: ConvertError },
+}
+
+/// The .pie files [`convert_dir`] wrote, and those it left as they were.
+#[derive(Debug, Default, PartialEq)]
+pub struct BuildReport {
+    pub converted: Vec<PathBuf>,
+    pub unchanged: Vec<PathBuf>,
+}
+
+/// Convert every PNG under `src` to a .pie file at the same place under `out` with `options`.
+/// PNGs whose .pie file was modified after them are skipped, so changing `options` needs `out`
+/// cleaned first.
+pub fn convert_dir(src: impl AsRef<Path>, out: impl AsRef<Path>, options: &EncodeOptions) -> Result<BuildReport, BuildError> {
+    let (src, out) = (src.as_ref(), out.as_ref());
+    span!(DEBUG, "convert_dir", src = %src.display(), out = %out.display());
+    let mut report = BuildReport::default();
+
+    for source in png_files(src)? {
+        let target = out.join(source.strip_prefix(src).unwrap_or(&source)).with_extension("pie");
+        if is_fresh(&source, &target)? {
+            report.unchanged.push(target);
+            continue;
+        }
+
+        span!(DEBUG, "convert", path = %source.display());
+        let png = fs::read(&source).map_err(|error| io_error(&source, error))?;
+        let (width, height, pixels) = decode_png(png.as_slice()).map_err(|error| BuildError::Convert { path: source.clone(), error })?;
+        let bytes = encode_with_options(width, height, pixels, options)
+            .map_err(|error| BuildError::Convert { path: source.clone(), error: ConvertError::Encode(error) })?;
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|error| io_error(parent, error))?;
+        }
+        fs::write(&target, bytes).map_err(|error| io_error(&target, error))?;
+        report.converted.push(target);
+    }
+
+    event!(DEBUG, converted = report.converted.len(), unchanged = report.unchanged.len(), "converted directory");
+    Ok(report)
+}
+
+fn io_error(path: &Path, error: io::Error) -> BuildError {
+    BuildError::Io { path: path.to_path_buf(), kind: error.kind() }
+}
+
+/// Every PNG under `dir`, sorted so builds convert in the same order.
+fn png_files(dir: &Path) -> Result<Vec<PathBuf>, BuildError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|error| io_error(dir, error))? {
+        let path = entry.map_err(|error| io_error(dir, error))?.path();
+        if path.is_dir() {
+            files.append(&mut png_files(&path)?);
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Whether `target` exists and was modified no earlier than `source`.
+fn is_fresh(source: &Path, target: &Path) -> Result<bool, BuildError> {
+    let Ok(built) = fs::metadata(target).and_then(|metadata| metadata.modified()) else {
+        return Ok(false);
+    };
+    let modified = fs::metadata(source).and_then(|metadata| metadata.modified()).map_err(|error| io_error(source, error))?;
+    Ok(built >= modified)
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Io { path, kind } => write!(f, "{}: {}", path.display(), kind),
+            BuildError::Convert { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Io { .. } => None,
+            BuildError::Convert { error, .. } => Some(error),
+        }
+    }
+}
+
+#[test]
+fn test_convert_dir() {
+    let dir = std::env::temp_dir().join(format!("pie-build-{}", std::process::id()));
+    let (src, out) = (dir.join("assets"), dir.join("out"));
+    fs::create_dir_all(src.join("sprites")).unwrap();
+    let source = src.join("sprites/cubikism.png");
+    fs::copy("images/cubikism-023391.png", &source).unwrap();
+
+    let options = EncodeOptions { embed_palette: true, ..Default::default() };
+    let target = out.join("sprites/cubikism.pie");
+    assert_eq!(BuildReport { converted: vec![target.clone()], unchanged: vec![] }, convert_dir(&src, &out, &options).unwrap());
+    // The bundled file predates the transparency flag, which is the only byte that differs.
+    let mut expected = fs::read("images/cubikism-023391.pie").unwrap();
+    expected[8] |= crate::FLAG_TRANSPARENCY;
+    assert_eq!(expected, fs::read(&target).unwrap());
+    assert_eq!(BuildReport { converted: vec![], unchanged: vec![target.clone()] }, convert_dir(&src, &out, &options).unwrap());
+
+    // Touching the source converts it again.
+    let later = fs::metadata(&target).unwrap().modified().unwrap() + std::time::Duration::from_secs(1);
+    fs::File::options().write(true).open(&source).unwrap().set_modified(later).unwrap();
+    assert_eq!(vec![target], convert_dir(&src, &out, &options).unwrap().converted);
+
+    assert!(matches!(convert_dir(dir.join("missing"), &out, &options), Err(BuildError::Io { kind: io::ErrorKind::NotFound, .. })));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,149 @@
+//! Several named palettes in one `.piep` file, such as every team colour of a game, so they can
+//! be shipped together rather than as a folder of loose files.
+//!
+//! ```text
+//! magic    u8[3]  -- Magic bytes "PIB"
+//! version  u8     -- Version, currently 1
+//! count    u16    -- Number of palettes (BE)
+//! name     u16    -- Name length (BE)
+//!          u8[]   -- Name as UTF-8
+//! flags    u8     -- 0b00000010 is whether colours are RGBA rather than RGB
+//! colors   u16    -- Number of colours (BE)
+//!          u8[]   -- Colours
+//!                 -- Repeated from name for every palette
+//! ```
+use std::fs;
+
+use crate::{read_file, section, DecodeError, EncodeError, Palette, PixelFormat, FLAG_TRANSPARENCY};
+
+const MAGIC: &[u8; 3] = b"PIB";
+const BUNDLE_VERSION: u8 = 1;
+
+/// Named palettes in the order they were added. Names are unique.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PaletteBundle {
+    entries: Vec<(String, Palette)>,
+}
+
+impl PaletteBundle {
+    pub fn get(&self, name: &str) -> Option<&Palette> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, palette)| palette)
+    }
+
+    /// Set `name` to `palette`, replacing any existing palette in place.
+    pub fn set(&mut self, name: &str, palette: Palette) {
+        match self.entries.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = palette,
+            None => self.entries.push((name.to_string(), palette)),
+        }
+    }
+
+    /// Remove `name`, returning its palette if it was there.
+    pub fn remove(&mut self, name: &str) -> Option<Palette> {
+        let index = self.entries.iter().position(|(n, _)| n == name)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Palette)> {
+        self.entries.iter().map(|(name, palette)| (name.as_str(), palette))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(BUNDLE_VERSION);
+        bytes.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+        for (name, palette) in &self.entries {
+            bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            write_palette(&mut bytes, palette);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<PaletteBundle, DecodeError> {
+        let bytes = bytes.as_ref();
+        let head = section(bytes, 0..6)?;
+        if &head[..3] != MAGIC {
+            return Err(DecodeError::BadMagic { found: [head[0], head[1], head[2]] });
+        }
+        if head[3] != BUNDLE_VERSION {
+            return Err(DecodeError::UnsupportedVersion { found: head[3], max_supported: BUNDLE_VERSION });
+        }
+
+        let mut bundle = PaletteBundle::default();
+        let mut at = 6;
+        for _ in 0..u16::from_be_bytes([head[4], head[5]]) {
+            let length = u16::from_be_bytes(take(bytes, &mut at, 2)?.try_into().unwrap()) as usize;
+            let start = at;
+            let name = String::from_utf8(take(bytes, &mut at, length)?.to_vec()).map_err(|_| DecodeError::InvalidPalette { offset: start })?;
+            let palette = read_palette(bytes, &mut at)?;
+            bundle.set(&name, palette);
+        }
+        Ok(bundle)
+    }
+
+    /// Read a `.piep` file.
+    pub fn read(path: &str) -> Result<PaletteBundle, DecodeError> {
+        PaletteBundle::from_bytes(read_file(path)?).map_err(|error| error.in_file(path))
+    }
+
+    /// Write a `.piep` file.
+    pub fn write(&self, path: &str) -> Result<(), EncodeError> {
+        fs::write(path, self.to_bytes()).map_err(|error| EncodeError::Io(error.kind()).in_file(path))
+    }
+}
+
+/// Take `length` bytes from `at`, moving `at` past them.
+fn take<'a>(bytes: &'a [u8], at: &mut usize, length: usize) -> Result<&'a [u8], DecodeError> {
+    let taken = section(bytes, *at..at.saturating_add(length))?;
+    *at += length;
+    Ok(taken)
+}
+
+/// Append the flags, colour count and colours of `palette`.
+pub(crate) fn write_palette(bytes: &mut Vec<u8>, palette: &Palette) {
+    let (flags, stride) = if palette.format == PixelFormat::RGBA { (FLAG_TRANSPARENCY, 4) } else { (0, 3) };
+    bytes.push(flags);
+    bytes.extend_from_slice(&((palette.colors.len() / stride) as u16).to_be_bytes());
+    bytes.extend_from_slice(&palette.colors[..palette.colors.len() / stride * stride]);
+}
+
+/// Read a palette written by [`write_palette`] from `at`, moving `at` past it.
+pub(crate) fn read_palette(bytes: &[u8], at: &mut usize) -> Result<Palette, DecodeError> {
+    let start = *at;
+    let head = take(bytes, at, 3)?;
+    if head[0] & !FLAG_TRANSPARENCY > 0 {
+        return Err(DecodeError::InvalidPalette { offset: start });
+    }
+    let count = u16::from_be_bytes([head[1], head[2]]) as usize;
+    if count > 256 {
+        return Err(DecodeError::InvalidPalette { offset: start + 1 });
+    }
+
+    let (format, stride) = if head[0] & FLAG_TRANSPARENCY > 0 { (PixelFormat::RGBA, 4) } else { (PixelFormat::RGB, 3) };
+    Ok(Palette { format, colors: take(bytes, at, count * stride)?.to_vec() })
+}
+
+#[test]
+fn test_bundle() {
+    let mut bundle = PaletteBundle::default();
+    bundle.set("red team", Palette { format: PixelFormat::RGB, colors: vec![0xFF, 0, 0, 0x80, 0, 0] });
+    bundle.set("ghost", Palette { format: PixelFormat::RGBA, colors: vec![0xFF, 0xFF, 0xFF, 0x40] });
+    bundle.set("blue team", Palette { format: PixelFormat::RGB, colors: vec![0, 0, 0xFF] });
+    bundle.remove("blue team");
+
+    let bytes = bundle.to_bytes();
+    assert_eq!(&bytes[..6], b"PIB\x01\x00\x02");
+    assert_eq!(Ok(bundle.clone()), PaletteBundle::from_bytes(&bytes));
+    assert_eq!(vec!["red team", "ghost"], bundle.iter().map(|(name, _)| name).collect::<Vec<_>>());
+
+    assert_eq!(Err(DecodeError::BadMagic { found: *b"PIE" }), PaletteBundle::from_bytes(b"PIE\x01\x00\x00"));
+    assert_eq!(Err(DecodeError::Truncated { expected: bytes.len(), found: bytes.len() - 1 }), PaletteBundle::from_bytes(&bytes[..bytes.len() - 1]));
+    let mut flags = bytes.clone();
+    flags[16] = 0x80;
+    assert_eq!(Err(DecodeError::InvalidPalette { offset: 16 }), PaletteBundle::from_bytes(&flags));
+}
@@ -0,0 +1,109 @@
+//! Chunks follow the palette in version 2 files. Each one is a four byte ASCII kind, a u32 (BE)
+//! length and then that many bytes of data.
+use crate::{section, DecodeError, Header};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Chunk<'a> {
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+    /// Where `data` starts in the file, for errors.
+    pub offset: usize,
+}
+
+impl<'a> Chunk<'a> {
+    /// An error for contents that could not be parsed `at` bytes into the data.
+    pub(crate) fn invalid(&self, at: usize) -> DecodeError {
+        DecodeError::InvalidChunk { kind: self.kind, offset: self.offset + at }
+    }
+
+    /// Take `length` bytes of data from `at`, moving `at` past them.
+    pub(crate) fn take(&self, at: &mut usize, length: usize) -> Result<&'a [u8], DecodeError> {
+        let taken = at.checked_add(length).and_then(|end| self.data.get(*at..end)).ok_or(self.invalid(*at))?;
+        *at += length;
+        Ok(taken)
+    }
+}
+
+/// Append a chunk to `bytes`.
+pub(crate) fn write_chunk(bytes: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&kind);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
+
+/// Split the file `bytes` into chunks, starting at `offset` and running to the end of the file.
+pub(crate) fn read_chunks(bytes: &[u8], offset: usize) -> Result<Vec<Chunk<'_>>, DecodeError> {
+    chunks(bytes, offset).collect()
+}
+
+/// Like [`read_chunks`] but one chunk at a time, so readers looking for an early chunk can stop
+/// without walking the rest. Stops after the first error.
+pub(crate) fn chunks(bytes: &[u8], mut offset: usize) -> impl Iterator<Item = Result<Chunk<'_>, DecodeError>> {
+    std::iter::from_fn(move || {
+        if offset >= bytes.len() {
+            return None;
+        }
+        let chunk = section(bytes, offset..offset.saturating_add(8)).and_then(|head| {
+            let kind = [head[0], head[1], head[2], head[3]];
+            let length = u32::from_be_bytes([head[4], head[5], head[6], head[7]]) as usize;
+            let data = section(bytes, offset + 8..(offset + 8).saturating_add(length))?;
+            Ok(Chunk { kind, data, offset: offset + 8 })
+        });
+        offset = match &chunk {
+            Ok(chunk) => chunk.offset + chunk.data.len(),
+            Err(_) => bytes.len(),
+        };
+        Some(chunk)
+    })
+}
+
+/// Rewrite a PIE file with every chunk of `kind` replaced by one holding `data`, or removed if
+/// `data` is None. Version 1 files are upgraded to version 2 as they cannot hold chunks.
+pub(crate) fn replace_chunk(bytes: &[u8], kind: [u8; 4], data: Option<&[u8]>) -> Result<Vec<u8>, DecodeError> {
+    let mut header = Header::parse(bytes)?;
+    let body = section(bytes, header.size()..header.chunks_offset())?;
+    let chunks = if header.version < 2 { Vec::new() } else { read_chunks(bytes, header.chunks_offset())? };
+
+    header.version = header.version.max(2);
+    let mut rewritten = Vec::with_capacity(bytes.len());
+    header.write(&mut rewritten);
+    rewritten.extend_from_slice(body);
+
+    for chunk in chunks.iter().filter(|chunk| chunk.kind != kind) {
+        write_chunk(&mut rewritten, chunk.kind, chunk.data);
+    }
+    if let Some(data) = data {
+        write_chunk(&mut rewritten, kind, data);
+    }
+
+    Ok(rewritten)
+}
+
+#[test]
+fn test_chunks() {
+    let mut bytes = Vec::new();
+    write_chunk(&mut bytes, *b"ABCD", &[1, 2, 3]);
+    write_chunk(&mut bytes, *b"EFGH", &[]);
+
+    let chunks = read_chunks(&bytes, 0).unwrap();
+    assert_eq!(chunks, vec![
+        Chunk { kind: *b"ABCD", data: &[1, 2, 3], offset: 8 },
+        Chunk { kind: *b"EFGH", data: &[], offset: 19 },
+    ]);
+    assert_eq!(Err(DecodeError::Truncated { expected: 8, found: 5 }), read_chunks(&bytes[..5], 0));
+    assert_eq!(Err(DecodeError::Truncated { expected: 11, found: 10 }), read_chunks(&bytes[..10], 0));
+}
+
+#[test]
+fn test_replace_chunk() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    let upgraded = replace_chunk(bytes, *b"ABCD", Some(&[1, 2, 3])).unwrap();
+    let header = Header::parse(&upgraded).unwrap();
+    assert_eq!(2, header.version);
+    assert_eq!(4, header.colors);
+    assert_eq!(vec![Chunk { kind: *b"ABCD", data: &[1, 2, 3], offset: 81 }], read_chunks(&upgraded, header.chunks_offset()).unwrap());
+    assert_eq!(crate::decode(bytes, None), crate::decode(&upgraded, None));
+
+    let removed = replace_chunk(&upgraded, *b"ABCD", None).unwrap();
+    assert_eq!(upgraded.len() - 11, removed.len());
+}
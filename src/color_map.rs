@@ -0,0 +1,40 @@
+//! Maps keyed by colour. Colours are packed into a `u32` and hashed with a single multiply rather
+//! than SipHash, as looking up every pixel is the hot spot of encoding.
+use std::{collections::HashMap, hash::{BuildHasherDefault, Hasher}};
+
+pub(crate) type ColorMap<V> = HashMap<u32, V, BuildHasherDefault<ColorHasher>>;
+
+/// Pack an RGB or RGBA colour into a `u32`. RGB colours leave the alpha byte as 0.
+pub(crate) fn pack(color: &[u8]) -> u32 {
+    u32::from_le_bytes([color[0], color[1], color[2], color.get(3).copied().unwrap_or(0)])
+}
+
+#[derive(Default)]
+pub(crate) struct ColorHasher(u64);
+
+impl Hasher for ColorHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u32(*byte as u32);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.0 = (self.0.rotate_left(5) ^ value as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+
+    fn finish(&self) -> u64 {
+        // Fold the well mixed high bits down, the table picks buckets with the low bits.
+        self.0 ^ (self.0 >> 32)
+    }
+}
+
+#[test]
+fn test_color_map() {
+    let mut map = ColorMap::default();
+    map.insert(pack(&[1, 2, 3]), 0);
+    map.insert(pack(&[1, 2, 3, 0xFF]), 1);
+    assert_eq!(Some(&0), map.get(&pack(&[1, 2, 3])));
+    assert_eq!(Some(&1), map.get(&pack(&[1, 2, 3, 0xFF])));
+    assert_eq!(None, map.get(&pack(&[3, 2, 1])));
+}
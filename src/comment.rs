@@ -0,0 +1,60 @@
+//! Text comments stored one to a `TEXT` chunk, like PNG's tEXt, for annotations such as an author
+//! or a description that every tool can read without agreeing on a chunk kind of its own.
+//!
+//! ```text
+//! TEXT     u8[]   -- Keyword as UTF-8, 1 to 79 bytes
+//!          u8     -- 0
+//!          u8[]   -- Text as UTF-8 until the end of the chunk
+//! ```
+use crate::{chunk, EncodeError, Header};
+
+pub(crate) const CHUNK_TEXT: [u8; 4] = *b"TEXT";
+
+/// Fail if `keyword` is empty, longer than 79 bytes or holds a 0 byte.
+pub(crate) fn check_keyword(keyword: &str) -> Result<(), EncodeError> {
+    if keyword.is_empty() || keyword.len() > 79 || keyword.contains('\0') {
+        return Err(EncodeError::InvalidCommentKeyword { keyword: keyword.to_string() });
+    }
+    Ok(())
+}
+
+/// The data of the `TEXT` chunk for a comment.
+pub(crate) fn to_bytes(keyword: &str, text: &str) -> Vec<u8> {
+    [keyword.as_bytes(), &[0], text.as_bytes()].concat()
+}
+
+/// The keyword and text of a `TEXT` chunk, or None if it is not one.
+pub(crate) fn from_bytes(data: &[u8]) -> Option<(String, String)> {
+    let split = data.iter().position(|&byte| byte == 0)?;
+    let keyword = std::str::from_utf8(&data[..split]).ok()?;
+    let text = std::str::from_utf8(&data[split + 1..]).ok()?;
+    Some((keyword.to_string(), text.to_string()))
+}
+
+/// The comments of the file in file order, up to any chunk that cannot be read. Comments that are
+/// not valid UTF-8 are skipped rather than failing the image.
+pub(crate) fn comments(bytes: &[u8], header: &Header) -> Vec<(String, String)> {
+    if header.version < 2 {
+        return Vec::new();
+    }
+    chunk::chunks(bytes, header.chunks_offset())
+        .map_while(Result::ok)
+        .filter(|chunk| chunk.kind == CHUNK_TEXT)
+        .filter_map(|chunk| from_bytes(chunk.data))
+        .filter(|(keyword, _)| check_keyword(keyword).is_ok())
+        .collect()
+}
+
+#[test]
+fn test_comment_bytes() {
+    assert_eq!(b"Author\0Ada \xE2\x9C\x93".to_vec(), to_bytes("Author", "Ada ✓"));
+    assert_eq!(Some(("Author".to_string(), "Ada ✓".to_string())), from_bytes(&to_bytes("Author", "Ada ✓")));
+    assert_eq!(Some(("Note".to_string(), String::new())), from_bytes(b"Note\0"));
+    assert_eq!(None, from_bytes(b"Note"));
+    assert_eq!(None, from_bytes(b"Note\0\xFF"));
+
+    assert_eq!(Ok(()), check_keyword(&"k".repeat(79)));
+    for keyword in ["", "a\0b", &"k".repeat(80)] {
+        assert_eq!(Err(EncodeError::InvalidCommentKeyword { keyword: keyword.to_string() }), check_keyword(keyword));
+    }
+}
@@ -0,0 +1,61 @@
+//! Comparing decoded images pixel by pixel, for asset regression tests and the CLI's `diff`.
+use crate::{DecodedPIE, Palette, PixelFormat};
+
+/// How two images differ, see [`compare`].
+#[derive(Debug, PartialEq)]
+pub struct CompareResult {
+    /// Whether the images are the same size with the same pixels.
+    pub identical: bool,
+    /// Pixels that differ in any channel, or every pixel of the larger image if the sizes differ.
+    pub differing: usize,
+    /// The largest difference in any one channel, 255 if the sizes differ.
+    pub max_delta: u8,
+    /// An RGB image of the same size, black where the pixels match and brighter red the larger
+    /// their difference. None if the images are identical or their sizes differ.
+    pub heatmap: Option<DecodedPIE>,
+}
+
+/// Compare the pixels of `a` and `b` as RGBA, so an RGB image matches an RGBA one if every pixel
+/// of the latter is opaque. Palettes are not compared, only the colours they give.
+pub fn compare(a: &DecodedPIE, b: &DecodedPIE) -> CompareResult {
+    span!(DEBUG, "compare", width = a.width, height = a.height);
+    if (a.width, a.height) != (b.width, b.height) {
+        let differing = (a.width as usize * a.height as usize).max(b.width as usize * b.height as usize);
+        return CompareResult { identical: false, differing, max_delta: 255, heatmap: None };
+    }
+
+    let (a_rgba, b_rgba) = (a.to_rgba8(), b.to_rgba8());
+    let deltas: Vec<u8> = a_rgba.chunks_exact(4).zip(b_rgba.chunks_exact(4))
+        .map(|(a, b)| a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0))
+        .collect();
+    let differing = deltas.iter().filter(|delta| **delta > 0).count();
+    let max_delta = deltas.iter().copied().max().unwrap_or(0);
+    event!(DEBUG, differing, max_delta, "compared pixels");
+    if differing == 0 {
+        return CompareResult { identical: true, differing, max_delta, heatmap: None };
+    }
+
+    let pixels: Vec<u8> = deltas.iter().flat_map(|delta| [*delta, 0, 0]).collect();
+    // Deltas are single bytes, so there are at most 256 shades.
+    let palette = Palette::from_pixels(&pixels, PixelFormat::RGB).unwrap();
+    let heatmap = DecodedPIE {
+        width: a.width, height: a.height, format: PixelFormat::RGB, pixels, palette,
+        chunks: Vec::new(), comments: Vec::new(), density: None,
+    };
+    CompareResult { identical: false, differing, max_delta, heatmap: Some(heatmap) }
+}
+
+#[test]
+fn test_compare() {
+    let rgb = crate::decode(crate::encode(3, 1, [1, 2, 3, 4, 5, 6, 1, 2, 3], true, None).unwrap().to_bytes(), None).unwrap();
+    let rgba = crate::decode(crate::encode(3, 1, [1, 2, 3, 0xFF, 4, 5, 6, 0xFF, 1, 2, 3, 0xFF], true, None).unwrap().to_bytes(), None).unwrap();
+    assert_eq!(CompareResult { identical: true, differing: 0, max_delta: 0, heatmap: None }, compare(&rgb, &rgba));
+
+    let changed = crate::decode(crate::encode(3, 1, [1, 2, 3, 0xFF, 4, 5, 6, 0x80, 1, 2, 13, 0xFF], true, None).unwrap().to_bytes(), None).unwrap();
+    let result = compare(&rgb, &changed);
+    assert_eq!((false, 2, 0x7F), (result.identical, result.differing, result.max_delta));
+    assert_eq!(vec![0, 0, 0, 0x7F, 0, 0, 10, 0, 0], result.heatmap.unwrap().pixels);
+
+    let wider = crate::decode(crate::encode(4, 1, [1, 2, 3].repeat(4), true, None).unwrap().to_bytes(), None).unwrap();
+    assert_eq!(CompareResult { identical: false, differing: 4, max_delta: 255, heatmap: None }, compare(&rgb, &wider));
+}
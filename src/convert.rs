@@ -0,0 +1,175 @@
+//! PNG <-> PIE conversion in memory, for servers and tools that have the bytes of an upload rather
+//! than a file on disk. PNGs of any colour type are read, with greyscale images expanded to RGB.
+//! Indexed PNGs keep their exact palette and indices unless converted against another palette.
+use std::io::{Read, Write};
+
+use crate::{decode, encode, rle, ConvertError, DecodeError, EncodeError, EncodedPIE, Palette, PixelFormat};
+
+/// Read a PNG into `(width, height, pixels)` with pixels in RGB or RGBA byte format.
+pub fn decode_png(reader: impl Read) -> Result<(u16, u16, Vec<u8>), ConvertError> {
+    span!(DEBUG, "decode_png");
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(|error| ConvertError::Png(error.to_string()))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|error| ConvertError::Png(error.to_string()))?;
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|va| [va[0], va[0], va[0], va[1]]).collect(),
+        _ => buf,
+    };
+
+    if info.width > u16::MAX as u32 || info.height > u16::MAX as u32 {
+        return Err(ConvertError::TooLarge { width: info.width, height: info.height });
+    }
+
+    event!(DEBUG, width = info.width, height = info.height, color_type = ?info.color_type, "decoded png");
+    Ok((info.width as u16, info.height as u16, pixels))
+}
+
+/// Read an indexed PNG straight into an [`EncodedPIE`] with its palette embedded, or `None` if it
+/// is not indexed. The palette is its PLTE chunk as is, RGBA if it has a tRNS chunk, and the
+/// indices are the PNG's own.
+pub fn decode_indexed_png(reader: impl Read) -> Result<Option<EncodedPIE>, ConvertError> {
+    span!(DEBUG, "decode_indexed_png");
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+
+    let mut reader = decoder.read_info().map_err(|error| ConvertError::Png(error.to_string()))?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed {
+        return Ok(None);
+    }
+    if info.width > u16::MAX as u32 || info.height > u16::MAX as u32 {
+        return Err(ConvertError::TooLarge { width: info.width, height: info.height });
+    }
+    let (width, height, depth) = (info.width as usize, info.height as usize, info.bit_depth as usize);
+
+    let plte = info.palette.as_deref().ok_or_else(|| ConvertError::Png("indexed image without a palette".to_string()))?;
+    let colors = plte.len() / 3;
+    let palette = match info.trns.as_deref() {
+        // Entries past the end of tRNS are opaque.
+        Some(trns) => Palette {
+            format: PixelFormat::RGBA,
+            colors: plte.chunks(3).enumerate().flat_map(|(i, rgb)| [rgb[0], rgb[1], rgb[2], trns.get(i).copied().unwrap_or(0xFF)]).collect(),
+        },
+        None => Palette { format: PixelFormat::RGB, colors: plte.to_vec() },
+    };
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf).map_err(|error| ConvertError::Png(error.to_string()))?;
+
+    // Rows of images under 8 bits deep are packed, leftmost pixel in the highest bits.
+    let mut indices = Vec::with_capacity(width * height);
+    for row in buf.chunks(frame.line_size).take(height) {
+        indices.extend((0..width).map(|x| {
+            let bit = x * depth;
+            row[bit / 8] >> (8 - depth - bit % 8) & ((1u16 << depth) - 1) as u8
+        }));
+    }
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= colors) {
+        return Err(EncodeError::IndexOutOfPalette { index }.into());
+    }
+
+    event!(DEBUG, width, height, depth, colors, "decoded indexed png");
+    Ok(Some(EncodedPIE {
+        width: width as u16,
+        height: height as u16,
+        format: palette.format,
+        indices: rle(&indices, 255),
+        palette: Some(palette),
+    }))
+}
+
+/// Convert a PNG to PIE, see [`encode`] for the palette arguments. Use [`EncodedPIE::to_bytes`]
+/// for the bytes of the file. Without `palette`, indexed PNGs keep their palette and indices.
+pub fn png_to_pie(mut reader: impl Read, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, ConvertError> {
+    let mut png = Vec::new();
+    reader.read_to_end(&mut png).map_err(|error| ConvertError::Png(error.to_string()))?;
+
+    if maybe_palette.is_none() {
+        if let Some(mut encoded) = decode_indexed_png(png.as_slice())? {
+            encoded.palette = encoded.palette.filter(|_| embed_palette);
+            return Ok(encoded);
+        }
+    }
+
+    let (width, height, pixels) = decode_png(png.as_slice())?;
+    Ok(encode(width, height, pixels, embed_palette, maybe_palette)?)
+}
+
+/// Convert the bytes of a PIE file to a PNG, RGBA if the image has transparency.
+/// * `palette` - Required if the palette is not embedded in `bytes`.
+pub fn pie_to_png(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>, writer: impl Write) -> Result<(), ConvertError> {
+    span!(DEBUG, "pie_to_png");
+    let image = decode(bytes, maybe_palette)?;
+    let mut encoder = png::Encoder::new(writer, image.width as u32, image.height as u32);
+    encoder.set_color(if image.format == PixelFormat::RGBA { png::ColorType::Rgba } else { png::ColorType::Rgb });
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|error| ConvertError::Png(error.to_string()))?;
+    writer.write_image_data(&image.pixels).map_err(|error| ConvertError::Png(error.to_string()))
+}
+
+impl From<DecodeError> for ConvertError {
+    fn from(error: DecodeError) -> ConvertError {
+        ConvertError::Decode(error)
+    }
+}
+
+impl From<EncodeError> for ConvertError {
+    fn from(error: EncodeError) -> ConvertError {
+        ConvertError::Encode(error)
+    }
+}
+
+#[test]
+fn test_convert() {
+    let png = std::fs::read("images/cubikism-023391.png").unwrap();
+    let encoded = png_to_pie(png.as_slice(), true, None).unwrap();
+    // The bundled file predates the transparency flag, which is the only byte that differs.
+    let mut expected = include_bytes!("../images/cubikism-023391.pie").to_vec();
+    expected[8] |= crate::FLAG_TRANSPARENCY;
+    assert_eq!(expected, encoded.to_bytes());
+
+    let mut converted = Vec::new();
+    pie_to_png(encoded.to_bytes(), None, &mut converted).unwrap();
+    assert_eq!(decode_png(png.as_slice()).unwrap(), decode_png(converted.as_slice()).unwrap());
+
+    // Greyscale is expanded to RGB.
+    let mut grey = Vec::new();
+    let mut encoder = png::Encoder::new(&mut grey, 2, 1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.write_header().unwrap().write_image_data(&[0x10, 0x20]).unwrap();
+    assert_eq!((2, 1, vec![0x10, 0x10, 0x10, 0x20, 0x20, 0x20]), decode_png(grey.as_slice()).unwrap());
+
+    assert!(matches!(png_to_pie(&b"not a png"[..], true, None), Err(ConvertError::Png(_))));
+    assert_eq!(None, decode_indexed_png(png.as_slice()).unwrap());
+}
+
+#[test]
+fn test_indexed_png() {
+    // Two bit indices, with a palette out of order of appearance and a partial tRNS chunk.
+    let mut indexed = Vec::new();
+    let mut encoder = png::Encoder::new(&mut indexed, 5, 2);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Two);
+    encoder.set_palette(vec![0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0]);
+    encoder.set_trns(vec![0x00, 0x80]);
+    encoder.write_header().unwrap().write_image_data(&[0b10_01_00_10, 0b10_000000, 0b00_00_01_01, 0b01_000000]).unwrap();
+
+    let palette = Palette { format: PixelFormat::RGBA, colors: vec![0, 0, 0, 0x00, 0xFF, 0, 0, 0x80, 0, 0xFF, 0, 0xFF] };
+    let encoded = decode_indexed_png(indexed.as_slice()).unwrap().unwrap();
+    assert_eq!((5, 2, Some(&palette)), (encoded.width, encoded.height, encoded.palette.as_ref()));
+    assert_eq!(vec![2, 1, 0, 2, 2, 0, 0, 1, 1, 1], crate::rle_decode(&encoded.indices));
+    assert_eq!(encoded, png_to_pie(indexed.as_slice(), true, None).unwrap());
+    assert_eq!(None, png_to_pie(indexed.as_slice(), false, None).unwrap().palette);
+
+    // Against another palette the colours are matched as usual.
+    let other = Palette { format: PixelFormat::RGBA, colors: vec![0, 0xFF, 0, 0xFF, 0xFF, 0, 0, 0x80, 0, 0, 0, 0x00] };
+    let encoded = png_to_pie(indexed.as_slice(), false, Some(&other)).unwrap();
+    assert_eq!(vec![0, 1, 2, 0, 0, 2, 2, 1, 1, 1], crate::rle_decode(&encoded.indices));
+}
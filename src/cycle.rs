@@ -0,0 +1,124 @@
+//! Palette cycling, where ranges of the palette rotate over time to animate things like water
+//! and fire without any extra frames. Ranges are stored in a `CYCL` chunk.
+//!
+//! ```text
+//! CYCL     u8     -- First palette index of the range
+//!          u8     -- Number of entries in the range
+//!          u16    -- Milliseconds between each rotation by one entry (BE)
+//!          u8     -- 0b00000001 is whether the range rotates backwards
+//!                 -- Repeated for every range
+//! ```
+use crate::{chunk::{read_chunks, replace_chunk}, DecodeError, Header, Palette, PixelFormat};
+
+const CHUNK_CYCLE: [u8; 4] = *b"CYCL";
+const CYCLE_REVERSE: u8 = 1 << 0;
+
+/// A range of palette entries that rotates by one entry every `step` milliseconds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CycleRange {
+    pub start: u8,
+    pub length: u8,
+    /// Milliseconds between each rotation. 0 never rotates.
+    pub step: u16,
+    /// Rotate towards the start of the range rather than the end.
+    pub reverse: bool,
+}
+
+impl Palette {
+    /// The palette as it is `time` milliseconds into cycling `ranges`. Ranges reaching past the
+    /// end of the palette are cut short.
+    pub fn cycled(&self, ranges: &[CycleRange], time: u64) -> Palette {
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let entries = self.colors.len() / stride;
+        let mut cycled = self.clone();
+
+        for range in ranges.iter().filter(|range| range.step > 0) {
+            let start = (range.start as usize).min(entries);
+            let end = (start + range.length as usize).min(entries);
+            if end - start < 2 {
+                continue;
+            }
+
+            let shift = ((time / range.step as u64) % (end - start) as u64) as usize * stride;
+            let colors = &mut cycled.colors[start * stride..end * stride];
+            if range.reverse {
+                colors.rotate_left(shift);
+            } else {
+                colors.rotate_right(shift);
+            }
+        }
+
+        cycled
+    }
+}
+
+/// Read the cycling ranges from the raw bytes of a PIE file. Files without any have none.
+pub fn decode_cycles(bytes: impl AsRef<[u8]>) -> Result<Vec<CycleRange>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(Vec::new());
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == CHUNK_CYCLE) else {
+        return Ok(Vec::new());
+    };
+
+    let mut ranges = Vec::new();
+    let mut at = 0;
+    while at < chunk.data.len() {
+        let range = chunk.take(&mut at, 5)?;
+        ranges.push(CycleRange {
+            start: range[0],
+            length: range[1],
+            step: u16::from_be_bytes([range[2], range[3]]),
+            reverse: range[4] & CYCLE_REVERSE > 0,
+        });
+    }
+    Ok(ranges)
+}
+
+/// Replace the cycling ranges in the raw bytes of a PIE file, returning the new file. No ranges
+/// removes the chunk entirely.
+pub fn set_cycles(bytes: impl AsRef<[u8]>, ranges: &[CycleRange]) -> Result<Vec<u8>, DecodeError> {
+    if ranges.is_empty() {
+        return replace_chunk(bytes.as_ref(), CHUNK_CYCLE, None);
+    }
+
+    let mut data = Vec::with_capacity(ranges.len() * 5);
+    for range in ranges {
+        data.extend_from_slice(&[range.start, range.length]);
+        data.extend_from_slice(&range.step.to_be_bytes());
+        data.push(if range.reverse { CYCLE_REVERSE } else { 0 });
+    }
+    replace_chunk(bytes.as_ref(), CHUNK_CYCLE, Some(&data))
+}
+
+#[test]
+fn test_cycled() {
+    let palette = Palette { format: PixelFormat::RGB, colors: (0..5).flat_map(|i| [i, i, i]).collect() };
+    let forward = CycleRange { start: 1, length: 3, step: 100, reverse: false };
+    let backward = CycleRange { reverse: true, ..forward };
+
+    assert_eq!(palette, palette.cycled(&[forward], 99));
+    assert_eq!(vec![0, 3, 1, 2, 4], palette.cycled(&[forward], 100).colors.iter().step_by(3).copied().collect::<Vec<_>>());
+    assert_eq!(vec![0, 2, 3, 1, 4], palette.cycled(&[backward], 100).colors.iter().step_by(3).copied().collect::<Vec<_>>());
+    assert_eq!(palette, palette.cycled(&[forward], 300));
+    assert_eq!(palette, palette.cycled(&[CycleRange { start: 4, length: 9, ..forward }], 100));
+}
+
+#[test]
+fn test_cycles() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    assert!(decode_cycles(bytes).unwrap().is_empty());
+
+    let ranges = [
+        CycleRange { start: 0, length: 2, step: 250, reverse: false },
+        CycleRange { start: 2, length: 2, step: 80, reverse: true },
+    ];
+    let cycling = set_cycles(bytes, &ranges).unwrap();
+    assert_eq!(ranges.to_vec(), decode_cycles(&cycling).unwrap());
+    assert_eq!(crate::decode(bytes, None), crate::decode(&cycling, None));
+    assert!(decode_cycles(set_cycles(&cycling, &[]).unwrap()).unwrap().is_empty());
+}
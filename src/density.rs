@@ -0,0 +1,90 @@
+//! How many pixels make up a unit of physical size, like PNG's pHYs, stored in a `PHYS` chunk so
+//! print and export tools and UI scaling code know how large the art is meant to be.
+//!
+//! ```text
+//! PHYS     u32    -- Pixels per unit horizontally (BE)
+//!          u32    -- Pixels per unit vertically (BE)
+//!          u8     -- Unit, 0 for none so only the aspect ratio is known, 1 for metres
+//! ```
+use crate::{chunk::{self, Chunk}, DecodeError, Header};
+
+pub(crate) const CHUNK_PHYS: [u8; 4] = *b"PHYS";
+
+const METRES_PER_INCH: f64 = 0.0254;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DensityUnit {
+    /// Only the ratio of the two densities is known, as a pixel aspect ratio.
+    #[default]
+    None,
+    Metre,
+}
+
+/// Pixels per unit along each axis, see [`crate::EncodeOptions::density`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PixelDensity {
+    pub x: u32,
+    pub y: u32,
+    pub unit: DensityUnit,
+}
+
+impl PixelDensity {
+    /// A density of `dpi` dots per inch along both axes, rounded to whole pixels per metre.
+    pub fn from_dpi(dpi: f64) -> PixelDensity {
+        let per_metre = (dpi / METRES_PER_INCH).round() as u32;
+        PixelDensity { x: per_metre, y: per_metre, unit: DensityUnit::Metre }
+    }
+
+    /// Dots per inch along each axis, or None without a physical unit.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            DensityUnit::None => None,
+            DensityUnit::Metre => Some((self.x as f64 * METRES_PER_INCH, self.y as f64 * METRES_PER_INCH)),
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let unit = match self.unit {
+            DensityUnit::None => 0,
+            DensityUnit::Metre => 1,
+        };
+        [&self.x.to_be_bytes()[..], &self.y.to_be_bytes(), &[unit]].concat()
+    }
+
+    pub(crate) fn from_chunk(chunk: &Chunk) -> Result<PixelDensity, DecodeError> {
+        let mut at = 0;
+        let x = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap());
+        let y = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap());
+        let unit = match chunk.take(&mut at, 1)?[0] {
+            0 => DensityUnit::None,
+            1 => DensityUnit::Metre,
+            _ => return Err(chunk.invalid(8)),
+        };
+        Ok(PixelDensity { x, y, unit })
+    }
+}
+
+/// The density of the file, or None if it has none or it, or a chunk before it, cannot be read.
+pub(crate) fn density(bytes: &[u8], header: &Header) -> Option<PixelDensity> {
+    if header.version < 2 {
+        return None;
+    }
+    chunk::chunks(bytes, header.chunks_offset())
+        .map_while(Result::ok)
+        .find(|chunk| chunk.kind == CHUNK_PHYS)
+        .and_then(|chunk| PixelDensity::from_chunk(&chunk).ok())
+}
+
+#[test]
+fn test_density() {
+    let density = PixelDensity::from_dpi(72.0);
+    assert_eq!(PixelDensity { x: 2835, y: 2835, unit: DensityUnit::Metre }, density);
+    let (x, y) = density.dpi().unwrap();
+    assert!((x - 72.0).abs() < 0.01 && (y - 72.0).abs() < 0.01);
+    assert_eq!(None, PixelDensity { x: 2, y: 1, unit: DensityUnit::None }.dpi());
+
+    let bytes = density.to_bytes();
+    assert_eq!(Ok(density), PixelDensity::from_chunk(&Chunk { kind: CHUNK_PHYS, data: &bytes, offset: 0 }));
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_PHYS, offset: 108 }), PixelDensity::from_chunk(&Chunk { kind: CHUNK_PHYS, data: &[0, 0, 0, 1, 0, 0, 0, 1, 2], offset: 100 }));
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_PHYS, offset: 104 }), PixelDensity::from_chunk(&Chunk { kind: CHUNK_PHYS, data: &[0, 0, 0, 1], offset: 100 }));
+}
@@ -0,0 +1,111 @@
+//! Oddities that decoding can recover from, for tools to surface and for strict decoding to
+//! refuse.
+use std::fmt;
+
+use crate::{chunk::read_chunks, decode, rows::data_runs, DecodeError, DecodedPIE, Header, Palette, FLAG_ANIMATION, FLAG_PALETTE, FLAG_ROWS, FLAG_SERPENTINE, FLAG_TRANSPARENCY};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Diagnostic {
+    /// Flag bits this crate does not know were set, and ignored.
+    UnknownFlags { flags: u8 },
+    /// The embedded palette has `count` colours but the runs only use the first `used`.
+    UnusedColors { used: usize, count: usize },
+    /// `count` bytes from `offset` are not part of the file and were ignored.
+    TrailingBytes { offset: usize, count: usize },
+}
+
+/// Every [`Diagnostic`] from decoding a file, in the order they were found.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.entries.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The first diagnostic as an error, for callers that want to refuse anything unusual.
+    pub fn into_result(self) -> Result<(), DecodeError> {
+        match self.entries.into_iter().next() {
+            Some(diagnostic) => Err(DecodeError::Strict(diagnostic)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::UnknownFlags { flags } => write!(f, "unknown flag bits {:#010b} were ignored", flags),
+            Diagnostic::UnusedColors { used, count } => write!(f, "the palette has {} colours but only {} are used", count, used),
+            Diagnostic::TrailingBytes { offset, count } => write!(f, "{} bytes from byte {} were ignored", count, offset),
+        }
+    }
+}
+
+/// Like [`decode`], also returning anything unusual about the file that did not stop it decoding.
+pub fn decode_with_diagnostics(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>) -> Result<(DecodedPIE, Diagnostics), DecodeError> {
+    let bytes = bytes.as_ref();
+    let decoded = decode(bytes, maybe_palette)?;
+    let header = Header::parse(bytes)?;
+    let mut diagnostics = Diagnostics::default();
+
+    let unknown = header.flags & !(FLAG_PALETTE | FLAG_TRANSPARENCY | FLAG_ANIMATION | FLAG_ROWS | FLAG_SERPENTINE);
+    if unknown > 0 {
+        diagnostics.entries.push(Diagnostic::UnknownFlags { flags: unknown });
+    }
+
+    if header.has_palette() {
+        let runs = data_runs(bytes, &header)?;
+        let used = runs.chunks_exact(2).map(|run| run[1] as usize + 1).max().unwrap_or(0);
+        let count = decoded.palette.colors.len() / header.stride();
+        if used < count {
+            diagnostics.entries.push(Diagnostic::UnusedColors { used, count });
+        }
+    }
+
+    // Version 1 files end at the palette, version 2 files at the last chunk that can be read.
+    let end = if header.version < 2 || read_chunks(bytes, header.chunks_offset()).is_err() { header.chunks_offset() } else { bytes.len() };
+    if bytes.len() > end {
+        diagnostics.entries.push(Diagnostic::TrailingBytes { offset: end, count: bytes.len() - end });
+    }
+
+    Ok((decoded, diagnostics))
+}
+
+/// Like [`decode`], failing with [`DecodeError::Strict`] on anything
+/// [`decode_with_diagnostics`] would report.
+pub fn decode_strict(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>) -> Result<DecodedPIE, DecodeError> {
+    let (decoded, diagnostics) = decode_with_diagnostics(bytes, maybe_palette)?;
+    diagnostics.into_result()?;
+    Ok(decoded)
+}
+
+#[test]
+fn test_diagnostics() {
+    let clean = crate::encode(2, 1, [1, 2, 3, 4, 5, 6], true, None).unwrap().to_bytes();
+    let (decoded, diagnostics) = decode_with_diagnostics(&clean, None).unwrap();
+    assert_eq!(crate::decode(&clean, None).unwrap(), decoded);
+    assert!(diagnostics.is_empty());
+
+    let mut odd = clean.clone();
+    odd[8] |= 0x40;
+    odd.extend_from_slice(&[7, 8, 9, 0xAA]);
+    let (_, diagnostics) = decode_with_diagnostics(&odd, None).unwrap();
+    assert_eq!(vec![
+        &Diagnostic::UnknownFlags { flags: 0x40 },
+        &Diagnostic::UnusedColors { used: 2, count: 3 },
+        &Diagnostic::TrailingBytes { offset: odd.len() - 1, count: 1 },
+    ], diagnostics.iter().collect::<Vec<_>>());
+    assert_eq!(Err(DecodeError::Strict(Diagnostic::UnknownFlags { flags: 0x40 })), decode_strict(&odd, None));
+
+    let external = crate::encode(2, 1, [1, 2, 3, 4, 5, 6], false, None).unwrap().to_bytes();
+    let palette = Palette { format: crate::PixelFormat::RGB, colors: vec![1, 2, 3, 4, 5, 6] };
+    let garbage = [external.as_slice(), &[1, 2]].concat();
+    assert_eq!(Err(DecodeError::Strict(Diagnostic::TrailingBytes { offset: external.len(), count: 2 })), decode_strict(&garbage, Some(&palette)));
+}
@@ -0,0 +1,100 @@
+//! Effects that work on palette indices rather than colours, so results stay on the palette.
+use crate::{rle, rle_decode, EncodeError, EncodedPIE, Palette, PixelFormat};
+
+/// Draw a `thickness` pixel outline of `color` around the opaque pixels of `image`, on the
+/// transparent pixels next to them. Pixels count as next to each other horizontally and
+/// vertically, so thicker outlines have cut corners. Transparent pixels are ones whose colour in
+/// `palette` has alpha 0. `color` is reused if the palette has it and added otherwise, giving
+/// back the palette to decode the result with. The result embeds it if `image` did.
+pub fn outline(image: &EncodedPIE, palette: &Palette, color: &[u8], thickness: u16) -> Result<(EncodedPIE, Palette), EncodeError> {
+    span!(DEBUG, "outline", width = image.width, height = image.height, thickness);
+    let stride = if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+    if color.len() != stride {
+        return Err(EncodeError::WrongPixelCount { expected: stride, found: color.len() });
+    }
+    let count = palette.colors.len() / stride;
+    if count > 256 {
+        return Err(EncodeError::TooManyColors { count });
+    }
+
+    let mut transparent = [false; 256];
+    if palette.format == PixelFormat::RGBA {
+        for (index, entry) in palette.colors.chunks_exact(4).enumerate() {
+            transparent[index] = entry[3] == 0;
+        }
+    }
+
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut indices = rle_decode(&image.indices);
+    // Pixels the outline can grow from, which starts as the opaque ones.
+    let mut filled: Vec<bool> = indices.iter().map(|index| !transparent[*index as usize]).collect();
+    let mut outlined = Vec::new();
+
+    for _ in 0..thickness {
+        let edge: Vec<usize> = (0..indices.len()).filter(|&i| !filled[i] && {
+            let (x, y) = (i % width, i / width);
+            (x > 0 && filled[i - 1]) || (x + 1 < width && filled[i + 1]) || (y > 0 && filled[i - width]) || (y + 1 < height && filled[i + width])
+        }).collect();
+        if edge.is_empty() {
+            break;
+        }
+        for &i in &edge {
+            filled[i] = true;
+        }
+        outlined.extend(edge);
+    }
+
+    let mut palette = palette.clone();
+    if !outlined.is_empty() {
+        let index = match palette.colors.chunks_exact(stride).position(|entry| entry == color) {
+            Some(index) => index,
+            None => {
+                let count = palette.colors.len() / stride + 1;
+                if count > 256 {
+                    return Err(EncodeError::TooManyColors { count });
+                }
+                palette.colors.extend_from_slice(color);
+                count - 1
+            }
+        };
+        for i in outlined {
+            indices[i] = index as u8;
+        }
+    }
+
+    let result = EncodedPIE {
+        width: image.width,
+        height: image.height,
+        format: image.format,
+        indices: rle(&indices, 255),
+        palette: image.palette.as_ref().map(|_| palette.clone()),
+    };
+    Ok((result, palette))
+}
+
+#[test]
+fn test_outline() {
+    let clear = [0, 0, 0, 0];
+    let red = [0xFF, 0, 0, 0xFF];
+    let pixels: Vec<u8> = (0..25).flat_map(|i| if i == 12 { red } else { clear }).collect();
+    let image = crate::encode(5, 5, &pixels, true, None).unwrap();
+    let palette = image.palette.clone().unwrap();
+
+    let (outlined, palette) = outline(&image, &palette, &[0, 0, 0, 0xFF], 1).unwrap();
+    assert_eq!(3 * 4, palette.colors.len());
+    assert_eq!(vec![
+        0, 0, 0, 0, 0,
+        0, 0, 2, 0, 0,
+        0, 2, 1, 2, 0,
+        0, 0, 2, 0, 0,
+        0, 0, 0, 0, 0,
+    ], rle_decode(&outlined.indices));
+
+    let (thick, same) = outline(&outlined, &palette, &[0, 0, 0, 0xFF], 2).unwrap();
+    assert_eq!(palette, same);
+    assert_eq!(4 + 16, rle_decode(&thick.indices).iter().filter(|index| **index == 2).count());
+    assert_eq!(Some(same), thick.palette);
+
+    let many = Palette { format: PixelFormat::RGBA, colors: vec![0; 300 * 4] };
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), outline(&image, &many, &[0, 0, 0, 0xFF], 1));
+}
@@ -0,0 +1,270 @@
+//! Encoding with buffers that are kept between images, for servers and batch pipelines that
+//! encode many images one after the other.
+use crate::{
+    color_map::{pack, ColorMap},
+    rle_into, write_image, EncodeError, EncodedPIE, Palette, PixelFormat,
+};
+
+/// Pixels in rows that may be padded, for [`Encoder::encode_strided`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StridedPixels<'a> {
+    pub bytes: &'a [u8],
+    /// How far apart rows start in bytes, at least the bytes of pixels in a row.
+    pub row_stride: usize,
+    pub format: PixelFormat,
+}
+
+/// Where each channel sits in a pixel packed into a `u32`, read as a number so the same on any
+/// endianness.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PackedLayout {
+    /// 0xRRGGBBAA
+    RGBA,
+    /// 0xAARRGGBB
+    ARGB,
+}
+
+impl PackedLayout {
+    pub(crate) fn unpack(self, pixel: u32) -> [u8; 4] {
+        match self {
+            PackedLayout::RGBA => pixel.to_be_bytes(),
+            PackedLayout::ARGB => pixel.rotate_left(8).to_be_bytes(),
+        }
+    }
+
+    pub(crate) fn pack(self, [r, g, b, a]: [u8; 4]) -> u32 {
+        match self {
+            PackedLayout::RGBA => u32::from_be_bytes([r, g, b, a]),
+            PackedLayout::ARGB => u32::from_be_bytes([a, r, g, b]),
+        }
+    }
+}
+
+/// Encodes images while reusing its index, run and palette buffers, so encoding many images
+/// does not allocate them again for each one. [`crate::encode`] uses a fresh one every call.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    indices: Vec<u8>,
+    runs: Vec<u8>,
+    colors: Vec<u8>,
+    map: ColorMap<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// Encode an array of RGB or RGBA bytes into an [`EncodedPIE`], see [`crate::encode`].
+    pub fn encode(&mut self, width: u16, height: u16, pixel_bytes: impl AsRef<[u8]>, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+        let format = self.index(width, height, pixel_bytes.as_ref(), maybe_palette)?;
+
+        // The palette is only borrowed while encoding and cloned once if it is to be embedded.
+        let palette = embed_palette.then(|| match maybe_palette {
+            Some(palette) => palette.to_owned(),
+            None => Palette { format, colors: self.colors.clone() },
+        });
+
+        Ok(EncodedPIE { width, height, format, indices: self.runs.clone(), palette })
+    }
+
+    /// Encode straight to the bytes of a PIE file, appended to `bytes`. Unlike [`Encoder::encode`]
+    /// this allocates nothing once the buffers have grown to fit.
+    pub fn encode_to_bytes(&mut self, width: u16, height: u16, pixel_bytes: impl AsRef<[u8]>, embed_palette: bool, maybe_palette: Option<&Palette>, bytes: &mut Vec<u8>) -> Result<(), EncodeError> {
+        let format = self.index(width, height, pixel_bytes.as_ref(), maybe_palette)?;
+        let colors = match (embed_palette, maybe_palette) {
+            (false, _) => None,
+            (true, Some(palette)) => Some(palette.colors.as_slice()),
+            (true, None) => Some(self.colors.as_slice()),
+        };
+
+        write_image(bytes, width, height, format, &self.runs, colors, &[]);
+        Ok(())
+    }
+
+    /// Like [`Encoder::encode`] for pixels in padded rows, such as buffers from a GPU or window
+    /// system. The padding is skipped and the last row need not have it.
+    pub fn encode_strided(&mut self, width: u16, height: u16, pixels: StridedPixels, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+        let StridedPixels { bytes: pixel_bytes, row_stride, format } = pixels;
+        let chunk_size = if format == PixelFormat::RGBA { 4 } else { 3 };
+        let row = width as usize * chunk_size;
+        if row_stride < row {
+            return Err(EncodeError::InvalidStride { stride: row_stride, row });
+        }
+        let expected = if height == 0 { 0 } else { (height as usize - 1) * row_stride + row };
+        if pixel_bytes.len() < expected {
+            return Err(EncodeError::WrongPixelCount { expected, found: pixel_bytes.len() });
+        }
+
+        self.index_rows(width, height, pixel_bytes, row_stride, chunk_size, maybe_palette)?;
+        let palette = embed_palette.then(|| match maybe_palette {
+            Some(palette) => palette.to_owned(),
+            None => Palette { format, colors: self.colors.clone() },
+        });
+
+        Ok(EncodedPIE { width, height, format, indices: self.runs.clone(), palette })
+    }
+
+    /// Like [`Encoder::encode`] for pixels packed into a `u32` each, as many frameworks hold
+    /// them. They are unpacked one at a time and encoded as RGBA.
+    pub fn encode_u32(&mut self, width: u16, height: u16, pixels: &[u32], layout: PackedLayout, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+        let pixel_count = width as usize * height as usize;
+        if pixels.len() != pixel_count {
+            return Err(EncodeError::WrongPixelCount { expected: pixel_count * 4, found: pixels.len() * 4 });
+        }
+
+        span!(DEBUG, "encode", width, height);
+        self.index_pixels(width, pixels.iter().map(|&pixel| layout.unpack(pixel)), 4, maybe_palette)?;
+        let palette = embed_palette.then(|| match maybe_palette {
+            Some(palette) => palette.to_owned(),
+            None => Palette { format: PixelFormat::RGBA, colors: self.colors.clone() },
+        });
+
+        Ok(EncodedPIE { width, height, format: PixelFormat::RGBA, indices: self.runs.clone(), palette })
+    }
+
+    /// Clear the buffers and free their memory, such as after encoding an unusually large image.
+    /// Encoding does not need this, the buffers are cleared before every image.
+    pub fn reset(&mut self) {
+        *self = Encoder::default();
+    }
+
+    /// Fill `runs` with the runs of `pixel_bytes`, and `colors` with the palette built on the fly
+    /// if `maybe_palette` is None. Returns the pixel format.
+    fn index(&mut self, width: u16, height: u16, pixel_bytes: &[u8], maybe_palette: Option<&Palette>) -> Result<PixelFormat, EncodeError> {
+        let pixel_count = width as usize * height as usize;
+        let mut chunk_size = 4;
+        if pixel_bytes.len() == pixel_count * 3 {
+            chunk_size = 3;
+        } else if pixel_bytes.len() != pixel_count * 4 {
+            return Err(EncodeError::wrong_pixel_count(pixel_count, pixel_bytes.len()));
+        }
+
+        self.index_rows(width, height, pixel_bytes, width as usize * chunk_size, chunk_size, maybe_palette)?;
+        Ok(if chunk_size == 3 { PixelFormat::RGB } else { PixelFormat::RGBA })
+    }
+
+    /// [`Encoder::index`] for pixels of `chunk_size` bytes in rows `row_stride` bytes apart,
+    /// which the caller has checked `pixel_bytes` holds.
+    fn index_rows(&mut self, width: u16, height: u16, pixel_bytes: &[u8], row_stride: usize, chunk_size: usize, maybe_palette: Option<&Palette>) -> Result<(), EncodeError> {
+        span!(DEBUG, "encode", width, height);
+        let row = width as usize * chunk_size;
+        // Empty rows would make a zero stride, which chunks does not allow.
+        let pixels = pixel_bytes.chunks(row_stride.max(1)).take(height as usize).flat_map(|bytes| bytes[..row].chunks(chunk_size));
+        self.index_pixels(width, pixels, chunk_size, maybe_palette)
+    }
+
+    /// Fill the buffers from `pixels`, each `chunk_size` bytes, in order.
+    fn index_pixels<C: AsRef<[u8]>>(&mut self, width: u16, pixels: impl Iterator<Item = C>, chunk_size: usize, maybe_palette: Option<&Palette>) -> Result<(), EncodeError> {
+        self.indices.clear();
+        self.runs.clear();
+        self.colors.clear();
+        self.map.clear();
+
+        // If palette is not included, it must be created on the fly.
+        if let Some(palette) = maybe_palette {
+            palette.check_encodable(if chunk_size == 4 { PixelFormat::RGBA } else { PixelFormat::RGB })?;
+            for (index, color) in palette.colors.chunks_exact(chunk_size).enumerate() {
+                self.map.insert(pack(color), index as u8);
+            }
+            for (i, chunk) in pixels.enumerate() {
+                match self.map.get(&pack(chunk.as_ref())) {
+                    Some(index) => self.indices.push(*index),
+                    None => return Err(EncodeError::color_not_in_palette(chunk.as_ref(), i, width)),
+                }
+            }
+        } else {
+            span!(DEBUG, "build_palette");
+            for chunk in pixels {
+                let chunk = chunk.as_ref();
+                // Past 256 colours indices would alias earlier ones, so only count the rest.
+                let next = self.map.len().min(255) as u8;
+                let index = *self.map.entry(pack(chunk)).or_insert_with(|| {
+                    self.colors.extend_from_slice(chunk);
+                    next
+                });
+                self.indices.push(index);
+            }
+            event!(DEBUG, colors = self.map.len(), "built palette");
+            if self.map.len() > 256 {
+                return Err(EncodeError::TooManyColors { count: self.map.len() });
+            }
+        }
+
+        rle_into(&self.indices, 255, &mut self.runs);
+        event!(DEBUG, runs = self.runs.len() / 2, "encoded");
+        Ok(())
+    }
+}
+
+#[test]
+fn test_encoder() {
+    let red = [0xFF, 0x00, 0x00].repeat(6);
+    let blue = [0x00, 0x00, 0xFF, 0xFF].repeat(6);
+    let mut encoder = Encoder::new();
+
+    for pixels in [red.as_slice(), blue.as_slice(), red.as_slice()] {
+        assert_eq!(crate::encode(3, 2, pixels, true, None), encoder.encode(3, 2, pixels, true, None));
+
+        let mut bytes = Vec::new();
+        encoder.encode_to_bytes(3, 2, pixels, true, None, &mut bytes).unwrap();
+        assert_eq!(crate::encode(3, 2, pixels, true, None).unwrap().to_bytes(), bytes);
+    }
+
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 16, found: 18 }), encoder.encode(2, 2, &red, true, None));
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 12, found: 13 }), encoder.encode(2, 2, [0; 13], true, None));
+    let mut stray = red.clone();
+    stray[13] = 0x80;
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![0xFF, 0x00, 0x00] };
+    assert_eq!(Err(EncodeError::ColorNotInPalette { color: [0xFF, 0x80, 0x00, 0xFF], x: 1, y: 1 }), encoder.encode(3, 2, &stray, false, Some(&palette)));
+    // Palettes of the other format are rejected rather than read with the wrong stride.
+    assert_eq!(Err(EncodeError::PaletteFormatMismatch { palette: PixelFormat::RGB, pixels: PixelFormat::RGBA }), encoder.encode(3, 2, &blue, false, Some(&palette)));
+    let rgba = Palette { format: PixelFormat::RGBA, colors: vec![0; 200 * 4] };
+    assert_eq!(Err(EncodeError::PaletteFormatMismatch { palette: PixelFormat::RGBA, pixels: PixelFormat::RGB }), encoder.encode(3, 2, &stray[..18], false, Some(&rgba)));
+    encoder.reset();
+    assert_eq!(vec![6, 0], encoder.encode(3, 2, &blue, false, None).unwrap().indices);
+
+    // Colours past 256 are an error rather than wrapping around to earlier indices.
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 0]).collect();
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), encoder.encode(30, 10, &many, true, None));
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), crate::encode_with_options(30, 10, &many, &crate::EncodeOptions::default()));
+    let palette = Palette::quantized(&many, PixelFormat::RGB, 256);
+    let remapped = palette.remap(&many);
+    assert!(encoder.encode(30, 10, &remapped, false, Some(&palette)).is_ok());
+}
+
+#[test]
+fn test_encode_strided() {
+    let pixels = [1, 2, 3, 4, 5, 6, 0xAA, 0xAA, 4, 5, 6, 1, 2, 3];
+    let packed = [1, 2, 3, 4, 5, 6, 4, 5, 6, 1, 2, 3];
+    let mut encoder = Encoder::new();
+
+    let rows = |bytes, row_stride| StridedPixels { bytes, row_stride, format: PixelFormat::RGB };
+
+    let strided = encoder.encode_strided(2, 2, rows(&pixels, 8), true, None).unwrap();
+    assert_eq!(crate::encode(2, 2, packed, true, None).unwrap(), strided);
+    assert_eq!(crate::encode(2, 2, packed, true, None), encoder.encode_strided(2, 2, rows(&packed, 6), true, None));
+
+    let decoded = crate::decode(strided.to_bytes(), None).unwrap();
+    assert_eq!(Some(vec![1, 2, 3, 4, 5, 6, 0, 0, 4, 5, 6, 1, 2, 3, 0, 0]), decoded.to_strided(8));
+    assert_eq!(None, decoded.to_strided(5));
+
+    assert_eq!(Err(EncodeError::InvalidStride { stride: 5, row: 6 }), encoder.encode_strided(2, 2, rows(&pixels, 5), true, None));
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 14, found: 13 }), encoder.encode_strided(2, 2, rows(&pixels[..13], 8), true, None));
+}
+
+#[test]
+fn test_encode_u32() {
+    let bytes = [1, 2, 3, 0xFF, 4, 5, 6, 0x80, 1, 2, 3, 0xFF];
+    let rgba = [0x010203FF, 0x04050680, 0x010203FF];
+    let argb = [0xFF010203, 0x80040506, 0xFF010203];
+    let mut encoder = Encoder::new();
+
+    let encoded = encoder.encode_u32(3, 1, &rgba, PackedLayout::RGBA, true, None).unwrap();
+    assert_eq!(crate::encode(3, 1, bytes, true, None).unwrap(), encoded);
+    assert_eq!(Ok(encoded), encoder.encode_u32(3, 1, &argb, PackedLayout::ARGB, true, None));
+
+    let decoded = crate::decode(crate::encode(3, 1, bytes, true, None).unwrap().to_bytes(), None).unwrap();
+    assert_eq!(argb.to_vec(), decoded.to_u32(PackedLayout::ARGB));
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 16, found: 12 }), encoder.encode_u32(2, 2, &rgba, PackedLayout::RGBA, true, None));
+}
@@ -0,0 +1,302 @@
+//! Errors from encoding and decoding. Offsets are in bytes from the start of the file when
+//! decoding, and positions are pixel coordinates when encoding.
+use std::{error::Error, fmt, io};
+
+use crate::{Diagnostic, PixelFormat};
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The palette is not embedded and no external palette was given.
+    MissingPalette,
+    /// The palette the file names is not known.
+    UnknownPalette { name: String },
+    /// The file ends at `found` bytes but a section runs to `expected` bytes.
+    Truncated { expected: usize, found: usize },
+    /// The file does not start with "PIE".
+    BadMagic { found: [u8; 3] },
+    /// The file is a version this crate does not know how to read. `max_supported` is the newest
+    /// one it does, so files from newer writers fail here rather than being misread.
+    UnsupportedVersion { found: u8, max_supported: u8 },
+    /// An animation was expected but the file has a single frame.
+    NotAnimated,
+    /// A chunk needed to decode the file is not there.
+    MissingChunk { kind: [u8; 4] },
+    /// The contents of a chunk could not be parsed at `offset`.
+    InvalidChunk { kind: [u8; 4], offset: usize },
+    /// The run at `offset` uses palette entry `index`, which the palette does not have.
+    IndexOutOfPalette { index: u8, offset: usize },
+    /// Runs expand to `found` pixels rather than the `expected` pixels.
+    WrongPixelCount { expected: usize, found: usize },
+    /// A palette outside of an image could not be parsed at `offset`.
+    InvalidPalette { offset: usize },
+    /// An archive of assets could not be parsed at `offset`.
+    InvalidArchive { offset: usize },
+    /// The row of the data section starting at `offset` has an unknown mode or does not hold
+    /// exactly the width of the image. Row `height` is anything after the last row.
+    InvalidRow { row: usize, offset: usize },
+    /// The animation chunk promises `expected` frames but there are `found`.
+    FrameCount { expected: usize, found: usize },
+    /// Strict decoding found something [`crate::decode_with_diagnostics`] would only report.
+    Strict(Diagnostic),
+    Io(io::ErrorKind),
+    /// Decoding the file at `path` failed.
+    File { path: String, error: Box<DecodeError> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EncodeError {
+    /// The pixels are `found` bytes long rather than the `expected` bytes for the dimensions.
+    WrongPixelCount { expected: usize, found: usize },
+    /// The pixel at `x`, `y` is `color`, which is not in the given palette. RGB colours have
+    /// alpha 0xFF.
+    ColorNotInPalette { color: [u8; 4], x: u32, y: u32 },
+    /// More than the 256 colours a palette can hold. [`crate::Palette::quantized`] can reduce
+    /// them.
+    TooManyColors { count: usize },
+    /// The given palette holds colours of a different format to the pixels.
+    PaletteFormatMismatch { palette: PixelFormat, pixels: PixelFormat },
+    /// More frames than an animation can hold.
+    TooManyFrames { count: usize },
+    /// An animation needs at least one frame.
+    NoFrames,
+    /// More layers than a layered file can hold.
+    TooManyLayers { count: usize },
+    /// A layered file needs at least one layer.
+    NoLayers,
+    /// Padding an image of this size would make it larger than 65535 pixels on a side.
+    TooLarge { width: usize, height: usize },
+    /// Palette entry `index` is needed but the palette does not have it.
+    IndexOutOfPalette { index: u8 },
+    /// Rows are `stride` bytes apart, less than the `row` bytes of pixels in each.
+    InvalidStride { stride: usize, row: usize },
+    /// A custom chunk kind that is not four ASCII letters or digits starting with a lowercase
+    /// letter.
+    InvalidChunkKind { kind: [u8; 4] },
+    /// A comment keyword that is empty, longer than 79 bytes or holds a 0 byte.
+    InvalidCommentKeyword { keyword: String },
+    Io(io::ErrorKind),
+    /// Encoding the file at `path` failed.
+    File { path: String, error: Box<EncodeError> },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PaletteError {
+    /// More than the 256 colours a palette can hold.
+    TooManyColors { count: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrateError {
+    /// The file could not be read.
+    Decode(DecodeError),
+    /// The target is a version this crate does not know how to write.
+    UnsupportedVersion { target: u8 },
+    /// Version 1 can hold at most 65535 runs.
+    TooManyRuns { runs: u32 },
+    /// Version 1 cannot hold chunks, the first of which is `kind`.
+    HasChunks { kind: [u8; 4] },
+    /// A version 1 palette of `length` bytes that is not a whole number of colours.
+    UnevenPalette { length: usize },
+    /// Version 1 cannot hold per row modes or serpentine order, which `flags` are.
+    UnsupportedFlags { flags: u8 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TranscodeError {
+    /// The file could not be read.
+    Decode(DecodeError),
+    /// The options could not be applied, such as padding with an index the palette lacks.
+    Encode(EncodeError),
+    /// The options change the runs, which would only change the first frame of an animation.
+    Animated,
+}
+
+/// Errors from converting between PNG and PIE.
+#[cfg(feature = "png")]
+#[derive(Debug, PartialEq)]
+pub enum ConvertError {
+    /// The PNG could not be read or written, with the `png` crate's message.
+    Png(String),
+    /// The PNG is larger than the 65535 pixels on a side PIE can hold.
+    TooLarge { width: u32, height: u32 },
+    Decode(DecodeError),
+    Encode(EncodeError),
+}
+
+impl DecodeError {
+    /// Attach the path of the file being decoded.
+    pub(crate) fn in_file(self, path: &str) -> DecodeError {
+        DecodeError::File { path: path.to_string(), error: Box::new(self) }
+    }
+}
+
+impl EncodeError {
+    /// `pixel`, the `index`th pixel of an image `width` pixels wide, is not in the palette.
+    pub(crate) fn color_not_in_palette(pixel: &[u8], index: usize, width: u16) -> EncodeError {
+        let mut color = [0xFF; 4];
+        color[..pixel.len()].copy_from_slice(pixel);
+        let width = (width as usize).max(1);
+        EncodeError::ColorNotInPalette { color, x: (index % width) as u32, y: (index / width) as u32 }
+    }
+
+    /// `found` bytes do not hold `pixel_count` pixels, expecting RGB or RGBA bytes, whichever is
+    /// the nearer fit.
+    pub(crate) fn wrong_pixel_count(pixel_count: usize, found: usize) -> EncodeError {
+        let expected = if found.abs_diff(pixel_count * 3) < found.abs_diff(pixel_count * 4) { pixel_count * 3 } else { pixel_count * 4 };
+        EncodeError::WrongPixelCount { expected, found }
+    }
+
+    /// Attach the path of the file being encoded.
+    pub(crate) fn in_file(self, path: &str) -> EncodeError {
+        EncodeError::File { path: path.to_string(), error: Box::new(self) }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingPalette => write!(f, "the palette is not embedded and no external palette was given"),
+            DecodeError::UnknownPalette { name } => write!(f, "the file needs palette {:?} which is not registered", name),
+            DecodeError::Truncated { expected, found } => write!(f, "the file ends at byte {} but needs {} bytes", found, expected),
+            DecodeError::BadMagic { found } => write!(f, "expected magic bytes \"PIE\" but found {:?}", String::from_utf8_lossy(found)),
+            DecodeError::UnsupportedVersion { found, max_supported } => write!(f, "version {} is not supported, only versions up to {}", found, max_supported),
+            DecodeError::NotAnimated => write!(f, "the file is not animated"),
+            DecodeError::MissingChunk { kind } => write!(f, "missing {} chunk", String::from_utf8_lossy(kind)),
+            DecodeError::InvalidChunk { kind, offset } => write!(f, "invalid {} chunk at byte {}", String::from_utf8_lossy(kind), offset),
+            DecodeError::IndexOutOfPalette { index, offset } => write!(f, "the run at byte {} uses colour {} which is not in the palette", offset, index),
+            DecodeError::WrongPixelCount { expected, found } => write!(f, "expected {} pixels but the runs hold {}", expected, found),
+            DecodeError::InvalidPalette { offset } => write!(f, "invalid palette at byte {}", offset),
+            DecodeError::InvalidArchive { offset } => write!(f, "invalid archive at byte {}", offset),
+            DecodeError::InvalidRow { row, offset } => write!(f, "invalid row {} at byte {}", row, offset),
+            DecodeError::FrameCount { expected, found } => write!(f, "expected {} frames but found {}", expected, found),
+            DecodeError::Strict(diagnostic) => write!(f, "{}", diagnostic),
+            DecodeError::Io(kind) => write!(f, "{}", kind),
+            DecodeError::File { path, error } => write!(f, "{}: {}", path, error),
+        }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::WrongPixelCount { expected, found } => write!(f, "expected {} bytes of pixels but found {}", expected, found),
+            EncodeError::ColorNotInPalette { color, x, y } => write!(f, "the pixel at {}, {} is #{:02x}{:02x}{:02x}{:02x}, which is not in the palette", x, y, color[0], color[1], color[2], color[3]),
+            EncodeError::TooManyColors { count } => write!(f, "{} colours is more than the 256 a palette can hold, try quantizing", count),
+            EncodeError::PaletteFormatMismatch { palette, pixels } => write!(f, "the palette is {:?} but the pixels are {:?}", palette, pixels),
+            EncodeError::NoFrames => write!(f, "an animation needs at least one frame"),
+            EncodeError::TooManyFrames { count } => write!(f, "{} frames is more than the {} an animation can hold", count, u16::MAX),
+            EncodeError::NoLayers => write!(f, "a layered file needs at least one layer"),
+            EncodeError::TooManyLayers { count } => write!(f, "{} layers is more than the {} a layered file can hold", count, u16::MAX),
+            EncodeError::TooLarge { width, height } => write!(f, "padding {}x{} would be larger than {} pixels on a side", width, height, u16::MAX),
+            EncodeError::IndexOutOfPalette { index } => write!(f, "colour {} is not in the palette", index),
+            EncodeError::InvalidStride { stride, row } => write!(f, "rows {} bytes apart cannot hold {} bytes of pixels", stride, row),
+            EncodeError::InvalidChunkKind { kind } => write!(f, "{:?} is not a custom chunk kind, which start with a lowercase letter", String::from_utf8_lossy(kind)),
+            EncodeError::InvalidCommentKeyword { keyword } => write!(f, "{:?} is not a comment keyword, which are 1 to 79 bytes without a 0", keyword),
+            EncodeError::Io(kind) => write!(f, "{}", kind),
+            EncodeError::File { path, error } => write!(f, "{}: {}", path, error),
+        }
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::TooManyColors { count } => write!(f, "{} colours is more than the 256 a palette can hold", count),
+        }
+    }
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrateError::Decode(error) => write!(f, "{}", error),
+            MigrateError::UnsupportedVersion { target } => write!(f, "version {} is not supported", target),
+            MigrateError::TooManyRuns { runs } => write!(f, "{} runs is more than the {} version 1 can hold", runs, u16::MAX),
+            MigrateError::HasChunks { kind } => write!(f, "version 1 cannot hold the {} chunk", String::from_utf8_lossy(kind)),
+            MigrateError::UnevenPalette { length } => write!(f, "the palette is {} bytes, not a whole number of colours", length),
+            MigrateError::UnsupportedFlags { flags } => write!(f, "version 1 cannot hold flag bits {:#010b}", flags),
+        }
+    }
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::Decode(error) => write!(f, "{}", error),
+            TranscodeError::Encode(error) => write!(f, "{}", error),
+            TranscodeError::Animated => write!(f, "sorting the palette, padding, row modes or serpentine order would only change the first frame of an animation"),
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Png(message) => write!(f, "{}", message),
+            ConvertError::TooLarge { width, height } => write!(f, "{}x{} is larger than the {} pixels on a side PIE supports", width, height, u16::MAX),
+            ConvertError::Decode(error) => write!(f, "{}", error),
+            ConvertError::Encode(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::File { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncodeError::File { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Error for PaletteError {}
+
+impl Error for MigrateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MigrateError::Decode(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Error for TranscodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TranscodeError::Decode(error) => Some(error),
+            TranscodeError::Encode(error) => Some(error),
+            TranscodeError::Animated => None,
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+impl Error for ConvertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConvertError::Decode(error) => Some(error),
+            ConvertError::Encode(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_display() {
+    let error = DecodeError::Truncated { expected: 57, found: 40 }.in_file("a.pie");
+    assert_eq!("a.pie: the file ends at byte 40 but needs 57 bytes", error.to_string());
+    assert!(error.source().is_some());
+    assert_eq!("version 3 is not supported, only versions up to 2", DecodeError::UnsupportedVersion { found: 3, max_supported: 2 }.to_string());
+    assert_eq!("invalid META chunk at byte 12", DecodeError::InvalidChunk { kind: *b"META", offset: 12 }.to_string());
+    assert_eq!("the pixel at 1, 2 is #ff8000ff, which is not in the palette", EncodeError::color_not_in_palette(&[0xFF, 0x80, 0], 9, 4).to_string());
+}
@@ -0,0 +1,157 @@
+//! Windows icon and cursor export, so icons and cursors can come from the same files as the rest
+//! of the art. Every size is stored as a 32-bit BMP, which every version of Windows reads.
+use std::io::{self, Write};
+
+use crate::{decode_metadata, DecodeError, DecodedPIE};
+
+/// Metadata key for the point of an image that sits on its position, as `x,y` in pixels. Cursors
+/// use it as their hotspot.
+pub const PIVOT_KEY: &str = "pivot";
+
+/// Read the pivot from the metadata in the raw bytes of a PIE file, see [`PIVOT_KEY`]. Values
+/// that are not two numbers are ignored.
+pub fn decode_pivot(bytes: impl AsRef<[u8]>) -> Result<Option<(u16, u16)>, DecodeError> {
+    let metadata = decode_metadata(bytes)?;
+    Ok(metadata.get(PIVOT_KEY).and_then(|pivot| {
+        let (x, y) = pivot.split_once(',')?;
+        Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+    }))
+}
+
+impl DecodedPIE {
+    /// Write a `.ico` holding the image at each of `sizes`, which are 1 to 256. Each is scaled by
+    /// nearest neighbour to fit a square that size, centred on transparency.
+    pub fn to_ico(&self, sizes: &[u16], writer: impl Write) -> io::Result<()> {
+        self.write_icons(sizes, None, writer)
+    }
+
+    /// Like [`DecodedPIE::to_ico`] but a `.cur`, with the cursor's hotspot at `hotspot` in the
+    /// image, such as from [`decode_pivot`].
+    pub fn to_cur(&self, sizes: &[u16], hotspot: (u16, u16), writer: impl Write) -> io::Result<()> {
+        self.write_icons(sizes, Some(hotspot), writer)
+    }
+
+    fn write_icons(&self, sizes: &[u16], hotspot: Option<(u16, u16)>, mut writer: impl Write) -> io::Result<()> {
+        if sizes.is_empty() || sizes.iter().any(|size| !(1..=256).contains(size)) || self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "icons need at least one size, each 1 to 256"));
+        }
+
+        let mut directory = Vec::new();
+        directory.extend_from_slice(&0u16.to_le_bytes());
+        directory.extend_from_slice(&(if hotspot.is_some() { 2u16 } else { 1 }).to_le_bytes());
+        directory.extend_from_slice(&(sizes.len() as u16).to_le_bytes());
+
+        let mut images = Vec::new();
+        for &size in sizes {
+            let (pixels, scaled_hotspot) = self.fit(size, hotspot.unwrap_or((0, 0)));
+            let bitmap = bitmap(size, &pixels);
+
+            directory.push(size as u8); // 256 wraps to 0, which means 256.
+            directory.push(size as u8);
+            directory.extend_from_slice(&[0, 0]);
+            let (planes, bits) = match hotspot {
+                Some(_) => scaled_hotspot,
+                None => (1, 32),
+            };
+            directory.extend_from_slice(&planes.to_le_bytes());
+            directory.extend_from_slice(&bits.to_le_bytes());
+            directory.extend_from_slice(&(bitmap.len() as u32).to_le_bytes());
+            directory.extend_from_slice(&((6 + 16 * sizes.len() + images.len()) as u32).to_le_bytes());
+            images.extend_from_slice(&bitmap);
+        }
+
+        writer.write_all(&directory)?;
+        writer.write_all(&images)
+    }
+
+    /// RGBA pixels of the image scaled to fit a `size` square, and where `point` ends up.
+    pub(crate) fn fit(&self, size: u16, point: (u16, u16)) -> (Vec<u8>, (u16, u16)) {
+        let (width, height, size) = (self.width as usize, self.height as usize, size as usize);
+        let (scaled_width, scaled_height) = if width >= height {
+            (size, (height * size / width).max(1))
+        } else {
+            ((width * size / height).max(1), size)
+        };
+        let (left, top) = ((size - scaled_width) / 2, (size - scaled_height) / 2);
+
+        let rgba = self.to_rgba8();
+        let mut pixels = vec![0; size * size * 4];
+        for y in 0..scaled_height {
+            for x in 0..scaled_width {
+                let from = (y * height / scaled_height * width + x * width / scaled_width) * 4;
+                let to = ((top + y) * size + left + x) * 4;
+                pixels[to..to + 4].copy_from_slice(&rgba[from..from + 4]);
+            }
+        }
+
+        let x = left + (point.0 as usize * scaled_width / width).min(scaled_width - 1);
+        let y = top + (point.1 as usize * scaled_height / height).min(scaled_height - 1);
+        (pixels, (x as u16, y as u16))
+    }
+}
+
+/// A square RGBA image as a BMP without its file header, as icons store them: BGRA rows from the
+/// bottom, then a mask with a bit set for every fully transparent pixel.
+fn bitmap(size: u16, pixels: &[u8]) -> Vec<u8> {
+    let size = size as usize;
+    let mask_stride = size.div_ceil(32) * 4;
+    let image_size = size * size * 4 + mask_stride * size;
+
+    let mut bytes = Vec::with_capacity(40 + image_size);
+    for value in [40, size as u32, size as u32 * 2] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    for value in [0, image_size as u32, 0, 0, 0, 0] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for row in pixels.chunks_exact(size * 4).rev() {
+        bytes.extend(row.chunks_exact(4).flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]));
+    }
+    for row in pixels.chunks_exact(size * 4).rev() {
+        let mut mask = vec![0u8; mask_stride];
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            if pixel[3] == 0 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        bytes.extend_from_slice(&mask);
+    }
+    bytes
+}
+
+#[test]
+fn test_ico() {
+    let clear = [0, 0, 0, 0];
+    let red = [0xFF, 0, 0, 0xFF];
+    let pixels: Vec<u8> = [red, clear, red, clear].concat();
+    let image = crate::decode(crate::encode(2, 2, &pixels, true, None).unwrap().to_bytes(), None).unwrap();
+
+    let mut ico = Vec::new();
+    image.to_ico(&[4, 256], &mut ico).unwrap();
+    assert_eq!(&[0, 0, 1, 0, 2, 0], &ico[..6]);
+    assert_eq!(&[4, 4, 0, 0, 1, 0, 32, 0], &ico[6..14]);
+    assert_eq!(38, u32::from_le_bytes(ico[18..22].try_into().unwrap()));
+    assert_eq!(0, ico[22]);
+    assert_eq!(38 + 40 + 4 * 4 * 4 + 4 * 4, u32::from_le_bytes(ico[34..38].try_into().unwrap()));
+
+    // The bottom row comes first, and the right half of a 2x2 image scaled to 4x4 is clear.
+    let bitmap = &ico[38..];
+    assert_eq!(8, u32::from_le_bytes(bitmap[8..12].try_into().unwrap()));
+    assert_eq!(&[0, 0, 0xFF, 0xFF, 0, 0, 0xFF, 0xFF, 0, 0, 0, 0], &bitmap[40..52]);
+    assert_eq!(0b00110000, bitmap[40 + 64]);
+
+    let mut cur = Vec::new();
+    image.to_cur(&[4], (1, 1), &mut cur).unwrap();
+    assert_eq!(&[0, 0, 2, 0, 1, 0], &cur[..6]);
+    assert_eq!(&[2, 0, 2, 0], &cur[10..14]);
+
+    assert!(image.to_ico(&[257], &mut Vec::new()).is_err());
+
+    let mut metadata = crate::Metadata::default();
+    metadata.set(PIVOT_KEY, "3, 7");
+    let bytes = crate::set_metadata(crate::encode(2, 2, &pixels, true, None).unwrap().to_bytes(), &metadata).unwrap();
+    assert_eq!(Ok(Some((3, 7))), decode_pivot(bytes));
+}
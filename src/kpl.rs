@@ -0,0 +1,107 @@
+//! Krita (`.kpl`) palettes. These are zip archives holding a `colorset.xml`, where each swatch is
+//! a `ColorSetEntry` with its colour as an `RGB` or `Gray` element of 0 to 1 floats. Swatches may
+//! be grouped, and groups are flattened into one palette in the order they appear.
+use crate::{read_file, zip, DecodeError, Palette, PixelFormat};
+
+/// The entry of a `.kpl` archive holding the swatches.
+const COLORSET: &str = "colorset.xml";
+
+impl Palette {
+    /// Parse the bytes of a Krita `.kpl` file into an RGB palette.
+    pub fn from_kpl(bytes: impl AsRef<[u8]>) -> Result<Palette, DecodeError> {
+        let bytes = bytes.as_ref();
+        let invalid = |offset| DecodeError::InvalidPalette { offset };
+        let xml = zip::read_entry(bytes, COLORSET, invalid)?.ok_or_else(|| invalid(zip::directory_end(bytes).unwrap_or(bytes.len())))?;
+        let colors = read_colorset(&xml)?;
+        Ok(Palette { format: PixelFormat::RGB, colors })
+    }
+
+    /// Read a Krita `.kpl` file.
+    pub fn read_kpl(path: &str) -> Result<Palette, DecodeError> {
+        Palette::from_kpl(read_file(path)?).map_err(|error| error.in_file(path))
+    }
+}
+
+/// The value of the attribute `name` in the tag `attributes`, which follow the tag's name.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next()?;
+        let value = &value[1..];
+        let close = value.find(quote)?;
+        if key == name {
+            return Some(&value[..close]);
+        }
+        rest = &value[close + 1..];
+    }
+    None
+}
+
+/// The RGB colours of every swatch in a `colorset.xml`, in document order.
+fn read_colorset(xml: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let xml = std::str::from_utf8(xml).map_err(|error| DecodeError::InvalidPalette { offset: error.valid_up_to() })?;
+    let mut colors = Vec::new();
+    let mut rest = xml;
+
+    while let Some(open) = rest.find('<') {
+        let offset = xml.len() - rest.len() + open;
+        let tag = &rest[open + 1..];
+        let close = tag.find('>').ok_or(DecodeError::InvalidPalette { offset })?;
+        let tag = tag[..close].trim_end_matches('/');
+        rest = &rest[open + 1 + close + 1..];
+
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let channel = |key: &str| -> Result<u8, DecodeError> {
+            let value: f32 = attribute(attributes, key).and_then(|value| value.parse().ok()).ok_or(DecodeError::InvalidPalette { offset })?;
+            Ok((value.clamp(0.0, 1.0) * 255.0).round() as u8)
+        };
+        match name {
+            "RGB" => colors.extend_from_slice(&[channel("r")?, channel("g")?, channel("b")?]),
+            "Gray" => colors.extend_from_slice(&[channel("g")?; 3]),
+            _ => {}
+        }
+    }
+
+    Ok(colors)
+}
+
+#[test]
+fn test_kpl() {
+    let xml = br#"<?xml version="1.0" encoding="UTF-8"?>
+<Colorset version="1.0" columns="16" name="Test">
+ <ColorSetEntry name="Red" id="1" spot="false" bitdepth="U8">
+  <RGB space="sRGB-elle-V2-srgbtrc.icc" r="1" g="0" b="0"/>
+  <Position row="0" column="0"/>
+ </ColorSetEntry>
+ <Group name="Greys" rows="1">
+  <ColorSetEntry name="Mid" id="2" spot="false" bitdepth="U8">
+   <Gray space="Gray-D50-elle-V2-srgbtrc.icc" g='0.5'/>
+  </ColorSetEntry>
+ </Group>
+ <ColorSetEntry name="Blue" id="3" spot="false" bitdepth="U16">
+  <RGB space="sRGB-elle-V2-srgbtrc.icc" r="0.2" g="0.4" b="1"/>
+ </ColorSetEntry>
+</Colorset>"#;
+    let expected = Palette { format: PixelFormat::RGB, colors: vec![255, 0, 0, 128, 128, 128, 51, 102, 255] };
+
+    let stored = zip::archive(&[("mimetype", 0, b"krita/x-colorset"), ("colorset.xml", 0, xml)]);
+    assert_eq!(Ok(expected.clone()), Palette::from_kpl(&stored));
+
+    let mut deflated = Vec::new();
+    let mut encoder = flate2::write::DeflateEncoder::new(&mut deflated, flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, xml).unwrap();
+    encoder.finish().unwrap();
+    let mut archive = zip::archive(&[("colorset.xml", 8, &deflated)]);
+    assert_eq!(Ok(expected.clone()), Palette::from_kpl(&archive));
+
+    // A claimed size of 4GiB is not trusted.
+    let directory = archive.windows(4).position(|window| window == [0x50, 0x4B, 0x01, 0x02]).unwrap();
+    archive[directory + 24..directory + 28].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(Ok(expected), Palette::from_kpl(&archive));
+
+    let missing = zip::archive(&[("mimetype", 0, b"krita/x-colorset")]);
+    assert_eq!(Err(DecodeError::InvalidPalette { offset: missing.len() - 22 }), Palette::from_kpl(&missing));
+    assert!(Palette::from_kpl(&stored[..stored.len() - 1]).is_err());
+}
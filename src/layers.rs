@@ -0,0 +1,278 @@
+//! Layered PIE files, for using PIE as a simple working format in pixel art editors. Every layer
+//! shares the dimensions and palette of the file. The bottom layer lives in the data section, so
+//! single image decoders show it, and the rest follow in `LAYD` chunks, bottom to top. A `LAYR`
+//! chunk holds how each layer is drawn over the ones below it.
+//!
+//! ```text
+//! LAYR     u16    -- Number of layers (BE)
+//!          u8     -- Opacity, 0 for invisible to 255 for opaque
+//!          u8     -- Blend mode, 0 for normal, 1 for multiply, 2 for add
+//!                 -- Repeated for every layer
+//! LAYD     u32    -- Run count (BE), one chunk per layer after the first
+//!          u8[]   -- Runs
+//! ```
+use std::borrow::Cow;
+
+use crate::{
+    chunk::read_chunks, color_map::{pack, ColorMap}, encode, expand_runs, resolve_palette, rows::data_runs, write_image, DecodeError,
+    DecodedPIE, EncodeError, Header, Palette, PixelFormat,
+};
+
+const CHUNK_LAYERS: [u8; 4] = *b"LAYR";
+const CHUNK_LAYER_DATA: [u8; 4] = *b"LAYD";
+
+/// How a layer's colours combine with those of the layers below it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BlendMode {
+    /// The layer's colour, covering what is below.
+    #[default]
+    Normal,
+    /// The product of the two colours, for shading.
+    Multiply,
+    /// The sum of the two colours, clamped, for light.
+    Add,
+}
+
+impl BlendMode {
+    fn to_byte(self) -> u8 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Multiply => 1,
+            BlendMode::Add => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<BlendMode> {
+        match byte {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Multiply),
+            2 => Some(BlendMode::Add),
+            _ => None,
+        }
+    }
+
+    /// Blend channel `source` of this layer over `backdrop`, both 0 to 1.
+    fn blend(self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            BlendMode::Normal => source,
+            BlendMode::Multiply => backdrop * source,
+            BlendMode::Add => (backdrop + source).min(1.0),
+        }
+    }
+}
+
+/// A single layer of [`DecodedLayers`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layer {
+    /// RGB or RGBA bytes, in the format of every other layer.
+    pub pixels: Vec<u8>,
+    /// How opaque the whole layer is, from 0 for invisible to 255 for as its pixels are.
+    pub opacity: u8,
+    pub blend: BlendMode,
+}
+
+/// Every layer of a layered PIE file, drawn bottom to top over one shared palette.
+#[derive(Debug, PartialEq)]
+pub struct DecodedLayers {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// Bottom layer first.
+    pub layers: Vec<Layer>,
+    /// The palette shared by every layer, embedded or external.
+    pub palette: Palette,
+}
+
+impl DecodedLayers {
+    /// Composite every layer into one image. It is RGB if the layers are and the bottom one is
+    /// fully opaque, otherwise RGBA. Blending makes new colours, so the palette holds every colour
+    /// of the result, which may be more than the 256 a file can hold.
+    pub fn flatten(&self) -> DecodedPIE {
+        span!(DEBUG, "flatten", layers = self.layers.len());
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let pixel_count = self.width as usize * self.height as usize;
+        let mut canvas = vec![[0f32; 4]; pixel_count];
+
+        for layer in &self.layers {
+            let opacity = layer.opacity as f32 / 255.0;
+            for (backdrop, pixel) in canvas.iter_mut().zip(layer.pixels.chunks_exact(stride)) {
+                let alpha = if stride == 4 { pixel[3] as f32 / 255.0 } else { 1.0 } * opacity;
+                let covered = backdrop[3];
+                let out_alpha = alpha + covered * (1.0 - alpha);
+                if out_alpha == 0.0 {
+                    continue;
+                }
+                for channel in 0..3 {
+                    let source = pixel[channel] as f32 / 255.0;
+                    // Where nothing is below, the layer shows as it is whatever its blend mode.
+                    let mixed = (1.0 - covered) * source + covered * layer.blend.blend(backdrop[channel], source);
+                    backdrop[channel] = (mixed * alpha + backdrop[channel] * covered * (1.0 - alpha)) / out_alpha;
+                }
+                backdrop[3] = out_alpha;
+            }
+        }
+
+        let opaque = self.format == PixelFormat::RGB && self.layers.first().is_some_and(|layer| layer.opacity == 0xFF);
+        let (format, channels) = if opaque { (PixelFormat::RGB, 3) } else { (PixelFormat::RGBA, 4) };
+        let pixels: Vec<u8> = canvas.iter().flat_map(|pixel| pixel[..channels].iter().map(|value| (value * 255.0).round() as u8)).collect();
+
+        let mut seen = ColorMap::default();
+        let mut colors = Vec::new();
+        for pixel in pixels.chunks_exact(channels) {
+            if seen.insert(pack(pixel), ()).is_none() {
+                colors.extend_from_slice(pixel);
+            }
+        }
+        event!(DEBUG, colors = seen.len(), "flattened layers");
+
+        DecodedPIE {
+            width: self.width, height: self.height, format, pixels,
+            palette: Palette { format, colors },
+            chunks: Vec::new(), comments: Vec::new(), density: None,
+        }
+    }
+}
+
+/// Encode `layers`, bottom first, against one shared palette into the bytes of a layered PIE
+/// file. Every layer must have the same dimensions and pixel format.
+/// * `palette` - Optional palette to encode against. If None, one palette is generated from the
+///   colours of every layer.
+pub fn encode_layers(width: u16, height: u16, layers: &[Layer], embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<Vec<u8>, EncodeError> {
+    span!(DEBUG, "encode_layers", width, height, layers = layers.len());
+    let pixel_count = width as usize * height as usize;
+    let layer_size = match layers.first() {
+        Some(layer) if layer.pixels.len() == pixel_count * 3 || layer.pixels.len() == pixel_count * 4 => layer.pixels.len(),
+        Some(layer) => return Err(EncodeError::wrong_pixel_count(pixel_count, layer.pixels.len())),
+        None => return Err(EncodeError::NoLayers),
+    };
+    if layers.len() > u16::MAX as usize {
+        return Err(EncodeError::TooManyLayers { count: layers.len() });
+    }
+    if let Some(layer) = layers.iter().find(|layer| layer.pixels.len() != layer_size) {
+        return Err(EncodeError::WrongPixelCount { expected: layer_size, found: layer.pixels.len() });
+    }
+
+    let palette = match maybe_palette {
+        Some(palette) => Cow::Borrowed(palette),
+        None => {
+            let stride = layer_size / pixel_count.max(1);
+            let colors: Vec<u8> = layers.iter().flat_map(|layer| layer.pixels.iter().copied()).collect();
+            let format = if stride == 3 { PixelFormat::RGB } else { PixelFormat::RGBA };
+            Cow::Owned(Palette::from_pixels(colors, format).map_err(|crate::PaletteError::TooManyColors { count }| EncodeError::TooManyColors { count })?)
+        }
+    };
+
+    let mut runs = Vec::with_capacity(layers.len());
+    for layer in layers {
+        runs.push(encode(width, height, &layer.pixels, false, Some(&palette))?.indices);
+    }
+
+    let mut modes = (layers.len() as u16).to_be_bytes().to_vec();
+    for layer in layers {
+        modes.extend_from_slice(&[layer.opacity, layer.blend.to_byte()]);
+    }
+    let mut chunks = vec![(CHUNK_LAYERS, modes)];
+    for layer_runs in &runs[1..] {
+        chunks.push((CHUNK_LAYER_DATA, [&((layer_runs.len() / 2) as u32).to_be_bytes()[..], layer_runs].concat()));
+    }
+
+    let mut bytes = Vec::new();
+    let palette_colors = embed_palette.then_some(palette.colors.as_slice());
+    write_image(&mut bytes, width, height, palette.format, &runs[0], palette_colors, &chunks);
+    Ok(bytes)
+}
+
+/// Decode every layer of a layered PIE file.
+/// * `palette` - Required if the palette is not embedded in `bytes`.
+pub fn decode_layers(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>) -> Result<DecodedLayers, DecodeError> {
+    let bytes = bytes.as_ref();
+    span!(DEBUG, "decode_layers", bytes = bytes.len());
+    let header = Header::parse(bytes)?;
+    let palette = resolve_palette(bytes, &header, maybe_palette)?;
+    let chunks = if header.version < 2 { Vec::new() } else { read_chunks(bytes, header.chunks_offset())? };
+    let modes = chunks.iter().find(|chunk| chunk.kind == CHUNK_LAYERS).ok_or(DecodeError::MissingChunk { kind: CHUNK_LAYERS })?;
+
+    let mut at = 0;
+    let layer_count = u16::from_be_bytes(modes.take(&mut at, 2)?.try_into().unwrap()) as usize;
+    let mut drawn = Vec::with_capacity(layer_count);
+    for _ in 0..layer_count {
+        let start = at;
+        let mode = modes.take(&mut at, 2)?;
+        drawn.push((mode[0], BlendMode::from_byte(mode[1]).ok_or(modes.invalid(start + 1))?));
+    }
+
+    // Runs of every layer with where they start in the file, for errors.
+    let first = data_runs(bytes, &header)?;
+    let mut runs = vec![(header.size(), first.as_ref())];
+    for chunk in chunks.iter().filter(|chunk| chunk.kind == CHUNK_LAYER_DATA) {
+        let mut at = 0;
+        let count = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap()) as usize;
+        runs.push((chunk.offset + at, chunk.take(&mut at, count.saturating_mul(2))?));
+    }
+    if runs.len() != layer_count {
+        return Err(modes.invalid(0));
+    }
+
+    let pixel_count = header.width as usize * header.height as usize;
+    let mut layers = Vec::with_capacity(layer_count);
+    for ((offset, runs), (opacity, blend)) in runs.into_iter().zip(drawn) {
+        layers.push(Layer { pixels: expand_runs(runs, offset, pixel_count, &palette)?, opacity, blend });
+    }
+    event!(DEBUG, layers = layers.len(), "read layers");
+
+    Ok(DecodedLayers { width: header.width, height: header.height, format: palette.format, layers, palette })
+}
+
+#[test]
+fn test_layers() {
+    let base = Layer { pixels: [[200, 100, 50], [10, 20, 30]].concat(), opacity: 0xFF, blend: BlendMode::Normal };
+    let shade = Layer { pixels: [[128, 128, 128], [255, 255, 255]].concat(), opacity: 0xFF, blend: BlendMode::Multiply };
+    let light = Layer { pixels: [[0, 0, 100], [0, 0, 0]].concat(), opacity: 0xFF, blend: BlendMode::Add };
+    let layers = [base.clone(), shade, light];
+
+    let bytes = encode_layers(2, 1, &layers, true, None).unwrap();
+    // Single image decoders show the bottom layer.
+    assert_eq!(base.pixels, crate::decode(&bytes, None).unwrap().pixels);
+
+    let decoded = decode_layers(&bytes, None).unwrap();
+    assert_eq!(layers.to_vec(), decoded.layers);
+    let flattened = decoded.flatten();
+    assert_eq!(PixelFormat::RGB, flattened.format);
+    assert_eq!(vec![100, 50, 125, 10, 20, 30], flattened.pixels);
+
+    // A half opaque normal layer mixes evenly with what is below.
+    let half = Layer { pixels: [[0, 0, 0], [0, 0, 0]].concat(), opacity: 128, blend: BlendMode::Normal };
+    let decoded = decode_layers(encode_layers(2, 1, &[base.clone(), half], true, None).unwrap(), None).unwrap();
+    assert_eq!(vec![100, 50, 25, 5, 10, 15], decoded.flatten().pixels);
+
+    // Over nothing, layers show as they are whatever their blend mode, and the result is RGBA.
+    let faint = Layer { opacity: 0x80, blend: BlendMode::Multiply, ..base.clone() };
+    let flattened = decode_layers(encode_layers(2, 1, &[faint], true, None).unwrap(), None).unwrap().flatten();
+    assert_eq!(PixelFormat::RGBA, flattened.format);
+    assert_eq!(vec![200, 100, 50, 128, 10, 20, 30, 128], flattened.pixels);
+    assert_eq!(flattened.pixels, flattened.palette.colors);
+}
+
+#[test]
+fn test_layers_errors() {
+    let layer = Layer { pixels: vec![1, 2, 3, 4, 5, 6], opacity: 0xFF, blend: BlendMode::Normal };
+    let bytes = encode_layers(2, 1, &[layer.clone(), layer.clone()], true, None).unwrap();
+    let short = Layer { pixels: vec![1, 2, 3], ..layer.clone() };
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 6, found: 3 }), encode_layers(2, 1, &[layer.clone(), short], true, None));
+    assert_eq!(Err(EncodeError::NoLayers), encode_layers(2, 1, &[], true, None));
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 6, found: 5 }), encode_layers(2, 1, &[Layer { pixels: vec![0; 5], ..layer.clone() }], true, None));
+    let many = vec![Layer { pixels: vec![0; 3], ..layer.clone() }; u16::MAX as usize + 1];
+    assert_eq!(Err(EncodeError::TooManyLayers { count: many.len() }), encode_layers(1, 1, &many, true, None));
+
+    let plain = crate::encode(2, 1, [1, 2, 3, 4, 5, 6], true, None).unwrap().to_bytes();
+    assert_eq!(Err(DecodeError::MissingChunk { kind: CHUNK_LAYERS }), decode_layers(&plain, None));
+
+    // An unknown blend mode, and a LAYR chunk promising more layers than there are.
+    let layr = bytes.windows(4).position(|window| window == CHUNK_LAYERS).unwrap() + 8;
+    let mut unknown = bytes.clone();
+    unknown[layr + 3] = 9;
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_LAYERS, offset: layr + 3 }), decode_layers(&unknown, None));
+    let mut missing = bytes.clone();
+    missing.truncate(bytes.windows(4).position(|window| window == CHUNK_LAYER_DATA).unwrap());
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_LAYERS, offset: layr }), decode_layers(&missing, None));
+}
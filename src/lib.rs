@@ -37,13 +37,109 @@
    │ height   u16   -- Height in pixels (BE)                         │
    │ flags    u8    -- 0b00000001 is whether the palette is included │
    │                -- 0b00000010 is whether there is transparency   │
+   │                -- 0b00000100 is whether there are more frames   │
    │                -- Other bits are reserved for future updates    │
-   │ length   u16   -- Run count of the data section (BE)            │
+   │ colors   u16   -- Embedded palette entries (BE) (version 2+)    │
+   │ length   u16   -- Run count of the data section (BE) (u32 in 2+)│
    │ data     u8[]  -- Indices into palette (external or internal)   │
    │ palette? u8[]  -- Optional palette included in the image        │
    │                -- Stride can be 3 or 4 depending on RGB/RGBA    │
+   │ chunks   u8[]  -- Optional chunks until the end (version 2+)    │
    └─────────────────────────────────────────────────────────────────┘
-   
+
+   Version 1 files have no chunks, so the palette runs to the end of
+   the file. Version 2 files record the palette size in the header and
+   follow the palette with chunks, each being a four byte ASCII kind, a
+   u32 (BE) length and that many bytes of data.
+
+   Animation
+   ---------
+   Animated files are version 2 with the frames flag set. The data
+   section holds the first frame so single image decoders still show
+   something sensible. Every frame shares the size and palette. The
+   optional FIDX chunk lets `Animation` decode any one frame without
+   walking the chunks of the others, and comes before the FRAM chunks,
+   which stay together.
+
+   ANIM     u16    -- Frame count (BE)
+            u16[]  -- Duration of each frame in milliseconds (BE)
+   FIDX     u32[]  -- Offset of each FRAM chunk from the first one (BE)
+   FRAM     u32    -- Run count (BE), one chunk per frame after the first
+            u8[]   -- Runs, the same as the data section
+
+   Metadata
+   --------
+   Version 2 files may carry key/value text in a META chunk. Keys are
+   unique and both keys and values are UTF-8.
+
+   META     u16    -- Key length (BE)
+            u8[]   -- Key
+            u32    -- Value length (BE)
+            u8[]   -- Value
+                   -- Repeated for every entry
+
+   Palette Cycling
+   ---------------
+   Ranges of the palette may rotate over time, described in a CYCL
+   chunk. See Palette::cycled for the palette at a point in time.
+
+   CYCL     u8     -- First palette index of the range
+            u8     -- Number of entries in the range
+            u16    -- Milliseconds between each rotation (BE)
+            u8     -- 0b00000001 is whether the range rotates backwards
+                   -- Repeated for every range
+
+   9-Slice
+   -------
+   UI panels may carry insets in a SLCE chunk saying which rows and
+   columns stretch. Corners are drawn as they are.
+
+   SLCE     u16    -- Left inset in pixels (BE)
+            u16    -- Right inset in pixels (BE)
+            u16    -- Top inset in pixels (BE)
+            u16    -- Bottom inset in pixels (BE)
+
+   Trimming
+   --------
+   Images encoded with transparent borders cropped record where they
+   sat in the original in a TRIM chunk.
+
+   TRIM     u16    -- Column of the original the image starts at (BE)
+            u16    -- Row of the original the image starts at (BE)
+            u16    -- Original width in pixels (BE)
+            u16    -- Original height in pixels (BE)
+
+   Padding
+   -------
+   Images padded out to a power of two or a multiple of some size keep
+   their content in the top left and record its size in a PADS chunk.
+
+   PADS     u16    -- Content width in pixels (BE)
+            u16    -- Content height in pixels (BE)
+
+   Palette Files
+   -------------
+   External palettes can be kept in a .pip file.
+
+   magic    u8[3]  -- Magic bytes "PIP"
+   flags    u8     -- 0b00000010 is whether colours are RGBA
+   colors   u16    -- Number of colours (BE)
+            u8[]   -- Colours
+
+   Palette Bundles
+   ---------------
+   Several named palettes can be shipped together in a .piep file.
+
+   magic    u8[3]  -- Magic bytes "PIB"
+   version  u8     -- Version, currently 1
+   count    u16    -- Number of palettes (BE)
+   name     u16    -- Name length (BE)
+            u8[]   -- Name as UTF-8
+   flags    u8     -- 0b00000010 is whether colours are RGBA
+   colors   u16    -- Number of colours (BE)
+            u8[]   -- Colours
+                   -- Repeated from name for every palette
+
    Data Compression
    ----------------
    Given this format is designed for pixel art images, some assumptions
@@ -76,11 +172,128 @@
 //! Using an internal palette will increase the size depending on the
 //! palette, but still generally be smaller than other formats like PNG
 //! for pixel art or images with limited palettes.
-use std::{fs::{File, self}, io::Read, collections::HashMap};
+use std::{borrow::Cow, fs::{File, self}, io::Read, ops::Range};
+
+use chunk::write_chunk;
+use comment::CHUNK_TEXT;
+use density::CHUNK_PHYS;
+use nine_slice::CHUNK_NINE_SLICE;
+use padding::CHUNK_PADDING;
+use timestamp::CHUNK_TIME;
+use trim::CHUNK_TRIM;
+
+/// Enter a `tracing` span for the rest of the scope when the `tracing` feature is enabled.
+macro_rules! span {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $($args)*).entered();
+    };
+}
+
+/// Emit a `tracing` event when the `tracing` feature is enabled.
+macro_rules! event {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($args)*);
+    };
+}
+
+mod act;
+mod alpha;
+mod analysis;
+mod animation;
+mod bundle;
+#[cfg(feature = "png")]
+pub mod build;
+mod chunk;
+mod color_map;
+mod comment;
+mod compare;
+#[cfg(feature = "png")]
+mod convert;
+mod cycle;
+mod density;
+mod diagnostics;
+pub mod effects;
+mod encoder;
+mod error;
+mod ico;
+#[cfg(feature = "kpl")]
+mod kpl;
+mod layers;
+mod lint;
+mod mask;
+mod metadata;
+mod migrate;
+mod netpbm;
+mod nine_slice;
+mod padding;
+pub mod palette;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod pip;
+mod recover;
+mod registry;
+mod rows;
+mod scan;
+mod source;
+mod stats;
+mod thumbnail;
+mod timestamp;
+mod transcode;
+mod trim;
+mod usage;
+mod view;
+#[cfg(feature = "webp")]
+mod webp;
+#[cfg(any(feature = "kpl", feature = "zip"))]
+mod zip;
+#[cfg(feature = "notify")]
+pub mod watch;
+
+pub use analysis::{Analysis, analyze};
+pub use bundle::PaletteBundle;
+pub use compare::{CompareResult, compare};
+pub use cycle::{CycleRange, decode_cycles, set_cycles};
+pub use density::{DensityUnit, PixelDensity};
+pub use diagnostics::{Diagnostic, Diagnostics, decode_strict, decode_with_diagnostics};
+pub use encoder::{Encoder, PackedLayout, StridedPixels};
+pub use error::{DecodeError, EncodeError, MigrateError, PaletteError, TranscodeError};
+#[cfg(feature = "png")]
+pub use error::ConvertError;
+#[cfg(feature = "png")]
+pub use convert::{decode_indexed_png, decode_png, pie_to_png, png_to_pie};
+pub use layers::{BlendMode, DecodedLayers, Layer, decode_layers, encode_layers};
+pub use animation::{Animation, AnimationReader, DecodedAnimation, DecodedFrame, EncodedAnimation, EncodedFrame, decode_animation, encode_animation, read_animation, write_animation};
+pub use ico::{PIVOT_KEY, decode_pivot};
+pub use lint::{Issue, Severity, fix, lint};
+pub use mask::{BitMask, Rect};
+pub use metadata::{Metadata, decode_metadata, set_metadata};
+pub use migrate::migrate;
+pub use nine_slice::{NineSlice, decode_nine_slice, set_nine_slice};
+pub use padding::{Padding, decode_content_size};
+#[cfg(feature = "rayon")]
+pub use parallel::encode_parallel;
+pub use recover::{Recovered, decode_partial};
+pub use registry::{PALETTE_KEY, PaletteRegistry, read_with_registry};
+pub use source::{AssetSource, DirSource, read_from};
+#[cfg(feature = "zip")]
+pub use source::ZipSource;
+pub use stats::{EncodeStats, EncodeStrategy, write_with_stats};
+pub use timestamp::{current_timestamp, decode_timestamp};
+pub use transcode::transcode;
+pub use trim::{Trim, decode_trim};
+pub use view::EncodedView;
 
 const FLAG_PALETTE: u8      = 1 << 0;
 const FLAG_TRANSPARENCY: u8 = 1 << 1;
+const FLAG_ANIMATION: u8    = 1 << 2;
+const FLAG_ROWS: u8         = 1 << 3;
+const FLAG_SERPENTINE: u8   = 1 << 4;
+/// The newest version this crate can read and write.
+const VERSION: u8 = 2;
 const HEADER_SIZE: usize = 11;
+const HEADER_SIZE_V2: usize = 15;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PixelFormat {
@@ -94,27 +307,164 @@ pub struct DecodedPIE {
     pub height: u16,
     pub format: PixelFormat,
     pub pixels: Vec<u8>,
+    /// The palette the pixels were decoded with, embedded or external.
+    pub palette: Palette,
+    /// Custom chunks the file carries as `(kind, data)`, in file order, see
+    /// [`EncodeOptions::add_chunk`].
+    pub chunks: Vec<([u8; 4], Vec<u8>)>,
+    /// Text comments the file carries as `(keyword, text)`, in file order, see
+    /// [`EncodeOptions::set_comment`].
+    pub comments: Vec<(String, String)>,
+    /// How many pixels make up a unit of physical size, see [`EncodeOptions::density`].
+    pub density: Option<PixelDensity>,
+}
+
+/// Decodes files with an embedded palette, see [`decode`] for external palettes.
+impl TryFrom<&[u8]> for DecodedPIE {
+    type Error = DecodeError;
+
+    fn try_from(bytes: &[u8]) -> Result<DecodedPIE, DecodeError> {
+        decode(bytes, None)
+    }
+}
+
+impl DecodedPIE {
+    /// The pixels as RGBA, with alpha set to opaque if the image has none.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        match self.format {
+            PixelFormat::RGBA => self.pixels.clone(),
+            PixelFormat::RGB => self.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+        }
+    }
+
+    /// The pixels as RGB. Alpha is dropped, or if `background` is given, pixels are blended over
+    /// it first so transparent areas take on the background colour.
+    pub fn to_rgb8(&self, background: Option<[u8; 3]>) -> Vec<u8> {
+        match (self.format, background) {
+            (PixelFormat::RGB, _) => self.pixels.clone(),
+            (PixelFormat::RGBA, None) => self.pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+            (PixelFormat::RGBA, Some(background)) => self.pixels.chunks_exact(4).flat_map(|p| {
+                let alpha = p[3] as u32;
+                let blend = |c: u8, b: u8| ((c as u32 * alpha + b as u32 * (255 - alpha) + 127) / 255) as u8;
+                [blend(p[0], background[0]), blend(p[1], background[1]), blend(p[2], background[2])]
+            }).collect(),
+        }
+    }
+
+    /// The pixels packed into a `u32` each, with alpha set to opaque if the image has none.
+    pub fn to_u32(&self, layout: PackedLayout) -> Vec<u32> {
+        match self.format {
+            PixelFormat::RGBA => self.pixels.chunks_exact(4).map(|p| layout.pack([p[0], p[1], p[2], p[3]])).collect(),
+            PixelFormat::RGB => self.pixels.chunks_exact(3).map(|p| layout.pack([p[0], p[1], p[2], 0xFF])).collect(),
+        }
+    }
+
+    /// The pixels in rows `row_stride` bytes apart, with zeroes padding each row out, for APIs
+    /// that want padded rows. None if `row_stride` is less than the bytes in a row.
+    pub fn to_strided(&self, row_stride: usize) -> Option<Vec<u8>> {
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let row = self.width as usize * stride;
+        if row_stride < row {
+            return None;
+        }
+
+        let mut strided = vec![0; row_stride * self.height as usize];
+        if row > 0 {
+            for (to, from) in strided.chunks_exact_mut(row_stride).zip(self.pixels.chunks_exact(row)) {
+                to[..row].copy_from_slice(from);
+            }
+        }
+        Some(strided)
+    }
+
+    /// The colour of the pixel at `x`, `y`, or None if it is outside the image.
+    pub fn color_at(&self, x: u16, y: u16) -> Option<&[u8]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let offset = (y as usize * self.width as usize + x as usize) * stride;
+        self.pixels.get(offset..offset + stride)
+    }
 }
 
 /// A struct encoded with the necessary data for writing. You cannot just dump this struct into a
-/// file. To write - use the [`self::write`] function.
+/// file. To write - use the [`self::write`] function, or [`EncodedPIE::to_bytes`] for the file
+/// contents in memory.
 #[derive(Debug, PartialEq)]
 pub struct EncodedPIE {
     pub width: u16,
     pub height: u16,
+    /// The pixel format that was encoded, RGBA sets the transparency flag.
+    pub format: PixelFormat,
     pub indices: Vec<u8>,
     pub palette: Option<Palette>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum DecodeError {
-    MissingPalette,
+impl EncodedPIE {
+    /// The exact bytes of a PIE file holding this image. Version 1 is written unless there are
+    /// too many runs for its header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_image(&mut bytes, self.width, self.height, self.format, &self.indices, self.palette.as_ref().map(|palette| palette.colors.as_slice()), &[]);
+        bytes
+    }
+
+    /// Parse the bytes of a PIE file without expanding the runs into pixels. Chunks such as
+    /// metadata are not kept, and for animations this is the first frame. See [`EncodedView`] to
+    /// look at the sections without copying them.
+    pub fn from_bytes(bytes: &[u8]) -> Result<EncodedPIE, DecodeError> {
+        Ok(EncodedView::new(bytes)?.into())
+    }
+
+    /// The palette index of the pixel at `x`, `y`, or None if it is outside the image. This walks
+    /// the runs, so decode first when looking up many pixels.
+    pub fn index_of(&self, x: u16, y: u16) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let mut remaining = y as usize * self.width as usize + x as usize;
+        for run in self.indices.chunks_exact(2) {
+            if remaining < run[0] as usize {
+                return Some(run[1]);
+            }
+            remaining -= run[0] as usize;
+        }
+        None
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum EncodeError {
-    WrongPixelCount,
-    ColorNotInPalette,
+/// Append the bytes of a single frame PIE file to `bytes`, see [`EncodedPIE::to_bytes`]. Any
+/// `chunks` make it version 2.
+pub(crate) fn write_image(bytes: &mut Vec<u8>, width: u16, height: u16, format: PixelFormat, runs: &[u8], palette_colors: Option<&[u8]>, chunks: &[([u8; 4], Vec<u8>)]) {
+    let header = Header { version: 1, width, height, flags: 0, colors: 0, runs: (runs.len() / 2) as u32 };
+    write_sections(bytes, header, format, runs, palette_colors, chunks);
+}
+
+/// Write `header` followed by the data section, palette and chunks, setting the version and the
+/// flags that follow from them.
+fn write_sections(bytes: &mut Vec<u8>, mut header: Header, format: PixelFormat, data: &[u8], palette_colors: Option<&[u8]>, chunks: &[([u8; 4], Vec<u8>)]) {
+    if header.runs > u16::MAX as u32 || !chunks.is_empty() {
+        header.version = 2;
+    }
+    if format == PixelFormat::RGBA {
+        header.flags |= FLAG_TRANSPARENCY;
+    }
+    if let Some(colors) = palette_colors {
+        header.flags |= FLAG_PALETTE;
+        header.colors = (colors.len() / header.stride()) as u16;
+    }
+
+    bytes.reserve(header.chunks_offset());
+    header.write(bytes);
+    bytes.extend_from_slice(data);
+
+    if let Some(colors) = palette_colors {
+        bytes.extend_from_slice(colors);
+    }
+    for (kind, data) in chunks {
+        write_chunk(bytes, *kind, data);
+    }
 }
 
 /// Palette for embedding or keeping external. The maximum amount of colours supported is 256.
@@ -124,6 +474,184 @@ pub struct Palette {
     pub colors: Vec<u8>, // Stride will be 4 for RGBA, 3 for RGB.
 }
 
+/// The fixed size part at the start of every PIE file. See [`decode_header`] and [`read_header`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Header {
+    pub version: u8,
+    pub width: u16,
+    pub height: u16,
+    pub flags: u8,
+    /// Number of embedded palette entries. Version 1 files only record this implicitly, so it is
+    /// 0 unless the whole file was parsed.
+    pub colors: u16,
+    /// Number of `(count, index)` runs in the data section, or its length in bytes for files with
+    /// per row modes.
+    pub runs: u32,
+}
+
+impl Header {
+    /// The pixel format decoding will produce, unless an external palette says otherwise.
+    pub fn format(&self) -> PixelFormat {
+        if self.flags & FLAG_TRANSPARENCY > 0 { PixelFormat::RGBA } else { PixelFormat::RGB }
+    }
+
+    /// Whether the palette is embedded in the file rather than external.
+    pub fn has_palette(&self) -> bool {
+        self.flags & FLAG_PALETTE > 0
+    }
+
+    /// Bytes of pixels decoding will produce, for checking against a memory budget before
+    /// decoding. An external palette of another pixel format changes this, and animations
+    /// produce this much for every frame.
+    pub fn decoded_size(&self) -> usize {
+        self.width as usize * self.height as usize * self.stride()
+    }
+
+    /// Whether the file holds more frames than the first, see [`decode_animation`].
+    pub fn is_animated(&self) -> bool {
+        self.flags & FLAG_ANIMATION > 0
+    }
+
+    /// Whether each row of the data section is stored as runs or raw indices, see
+    /// [`EncodeOptions::row_modes`].
+    pub fn has_row_modes(&self) -> bool {
+        self.flags & FLAG_ROWS > 0
+    }
+
+    /// Whether every other row of the data section is stored right to left, see
+    /// [`EncodeOptions::serpentine`].
+    pub fn is_serpentine(&self) -> bool {
+        self.flags & FLAG_SERPENTINE > 0
+    }
+
+    pub(crate) fn parse(bytes: &[u8]) -> Result<Header, DecodeError> {
+        if !bytes.starts_with(b"PIE") && !b"PIE".starts_with(bytes) {
+            let mut found = [0; 3];
+            found[..bytes.len().min(3)].copy_from_slice(&bytes[..bytes.len().min(3)]);
+            return Err(DecodeError::BadMagic { found });
+        }
+        section(bytes, 0..HEADER_SIZE)?;
+
+        let mut header = Header {
+            version: bytes[3],
+            width: u16::from_be_bytes([bytes[4], bytes[5]]),
+            height: u16::from_be_bytes([bytes[6], bytes[7]]),
+            flags: bytes[8],
+            colors: 0,
+            runs: 0,
+        };
+
+        match header.version {
+            1 => {
+                header.runs = u16::from_be_bytes([bytes[9], bytes[10]]) as u32;
+                // Version 1 palettes run to the end of the file.
+                if header.flags & FLAG_PALETTE > 0 {
+                    header.colors = (bytes.len().saturating_sub(header.palette_offset()) / header.stride()) as u16;
+                }
+            }
+            2 => {
+                section(bytes, 0..HEADER_SIZE_V2)?;
+                header.colors = u16::from_be_bytes([bytes[9], bytes[10]]);
+                header.runs = u32::from_be_bytes([bytes[11], bytes[12], bytes[13], bytes[14]]);
+            }
+            found => return Err(DecodeError::UnsupportedVersion { found, max_supported: VERSION }),
+        }
+
+        Ok(header)
+    }
+
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(b"PIE");
+        bytes.push(self.version);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.push(self.flags);
+        if self.version < 2 {
+            bytes.extend_from_slice(&(self.runs as u16).to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&self.colors.to_be_bytes());
+            bytes.extend_from_slice(&self.runs.to_be_bytes());
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        if self.version < 2 { HEADER_SIZE } else { HEADER_SIZE_V2 }
+    }
+
+    /// Bytes per embedded palette entry.
+    pub(crate) fn stride(&self) -> usize {
+        if self.flags & FLAG_TRANSPARENCY > 0 { 4 } else { 3 }
+    }
+
+    // Saturating so that huge counts in crafted headers show up as truncation rather than
+    // wrapping around on 32-bit targets.
+    pub(crate) fn palette_offset(&self) -> usize {
+        let run_size = if self.has_row_modes() { 1 } else { 2 };
+        (self.runs as usize).saturating_mul(run_size).saturating_add(self.size())
+    }
+
+    pub(crate) fn chunks_offset(&self) -> usize {
+        self.palette_offset().saturating_add(self.colors as usize * self.stride())
+    }
+
+    /// Where the data section is in the file.
+    pub fn data_range(&self) -> Range<usize> {
+        self.size()..self.palette_offset()
+    }
+
+    /// Where the embedded palette is in the file, empty if it is external. Version 1 headers
+    /// parsed without the rest of the file do not know their palette size, so theirs is empty.
+    pub fn palette_range(&self) -> Range<usize> {
+        self.palette_offset()..self.chunks_offset()
+    }
+}
+
+impl TryFrom<&[u8]> for Header {
+    type Error = DecodeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Header, DecodeError> {
+        Header::parse(bytes)
+    }
+}
+
+/// Parse only the header from the raw bytes of a PIE file, which need not hold the whole file.
+pub fn decode_header(bytes: impl AsRef<[u8]>) -> Result<Header, DecodeError> {
+    Header::parse(bytes.as_ref())
+}
+
+/// Bytes of pixels decoding the raw bytes of a PIE file will produce, from its header alone, so
+/// loaders and servers can turn away images too large to decode. See [`Header::decoded_size`].
+pub fn estimated_decoded_size(bytes: impl AsRef<[u8]>) -> Result<usize, DecodeError> {
+    Ok(Header::parse(bytes.as_ref())?.decoded_size())
+}
+
+/// Read only the header of a PIE file on disk, without reading the rest of the file.
+pub fn read_header(path: &str) -> Result<Header, DecodeError> {
+    span!(DEBUG, "read_header", path);
+    let file = File::open(path).map_err(|error| DecodeError::Io(error.kind()).in_file(path))?;
+    header_from_reader(file).map_err(|error| error.in_file(path))
+}
+
+/// The width, height and pixel format of a PIE file, reading no more than its header. Works on
+/// anything readable, such as a `File` or a byte slice.
+pub fn dimensions<R: Read>(reader: R) -> Result<(u16, u16, PixelFormat), DecodeError> {
+    let header = header_from_reader(reader)?;
+    Ok((header.width, header.height, header.format()))
+}
+
+pub(crate) fn header_from_reader<R: Read>(reader: R) -> Result<Header, DecodeError> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE_V2);
+    reader.take(HEADER_SIZE_V2 as u64).read_to_end(&mut bytes).map_err(|error| DecodeError::Io(error.kind()))?;
+
+    // Short version 1 files may end before the size of a version 2 header.
+    let header = Header::parse(&bytes)?;
+    if header.version < 2 {
+        Ok(Header { colors: 0, ..header })
+    } else {
+        Ok(header)
+    }
+}
+
 /// Encode and write a PIE file to disk.
 /// # Arguments
 /// * `path` - Path to the file.
@@ -131,100 +659,245 @@ pub struct Palette {
 /// * `height` - Height in pixels.
 /// * `embed_palette` - If true, will embed the palette into the file.
 /// * `palette` - Optional palette to be embedded or referred to. If None, a palette will be
-///               generated on the fly and indices will match the auto-generated palette.
+///   generated on the fly and indices will match the auto-generated palette.
 /// * `pixels` - The pixel data in RGB or RGBA byte format.
-/// external palette.
-pub fn write(path: &str, width: u16, height: u16, embed_palette: bool, maybe_palette: Option<&Palette>, pixels: Vec<u8>) -> Result<bool, EncodeError> {
-    let encoded = encode(width, height, &pixels, embed_palette, maybe_palette).expect("Failed to encode data.");
-    let mut flags = 0;
+pub fn write(path: &str, width: u16, height: u16, embed_palette: bool, maybe_palette: Option<&Palette>, pixels: impl AsRef<[u8]>) -> Result<bool, EncodeError> {
+    span!(INFO, "write", path);
+    let bytes = encode(width, height, pixels, embed_palette, maybe_palette).map_err(|error| error.in_file(path))?.to_bytes();
 
-    if encoded.indices.len() / 2 > u16::MAX as usize {
-        return Err(EncodeError::WrongPixelCount);
+    fs::write(path, &bytes).map_err(|error| EncodeError::Io(error.kind()).in_file(path))?;
+    event!(DEBUG, bytes = bytes.len(), "wrote file");
+    Ok(true)
+}
+
+/// Options for [`encode_with_options`] and [`write_with_options`], for files that carry more than
+/// the image itself.
+#[derive(Debug, Default, Clone)]
+pub struct EncodeOptions<'a> {
+    /// If true, will embed the palette into the file.
+    pub embed_palette: bool,
+    /// Palette to be embedded or referred to. If None, one is generated from the pixels.
+    pub palette: Option<&'a Palette>,
+    /// How to stretch the image when it is a UI panel.
+    pub nine_slice: Option<NineSlice>,
+    /// Make RGBA pixels with at least this alpha opaque and the rest fully transparent before
+    /// anything else, so stray partial alpha does not add colours.
+    pub alpha_threshold: Option<u8>,
+    /// Crop fully transparent borders of RGBA images, recording where the rest was, see
+    /// [`decode_trim`].
+    pub trim: bool,
+    /// Pad the image out with `padding_index` after trimming, recording the size of the content,
+    /// see [`decode_content_size`].
+    pub padding: Option<Padding>,
+    /// Palette index to fill padding with.
+    pub padding_index: u8,
+    /// Composite RGBA images over this colour after trimming and encode them as RGB.
+    pub flatten: Option<[u8; 3]>,
+    /// Order a generated, embedded palette by how many pixels use each colour, most first,
+    /// rather than by first appearance. See [`EncodedPIE::sort_palette_by_usage`].
+    pub sort_palette: bool,
+    /// Store each row as runs or raw indices, whichever is smaller, for images mixing flat areas
+    /// with dithering or noise. Files with row modes are version 2.
+    pub row_modes: bool,
+    /// Also try storing every other row right to left, so runs carry on across row ends, and
+    /// keep it if the file is smaller. Files in this order are version 2.
+    pub serpentine: bool,
+    /// Custom chunks to write as `(kind, data)`, see [`EncodeOptions::add_chunk`].
+    pub chunks: Vec<([u8; 4], Vec<u8>)>,
+    /// Text comments to write as `(keyword, text)`, see [`EncodeOptions::set_comment`].
+    pub comments: Vec<(String, String)>,
+    /// When the file was made as UTC seconds since the Unix epoch, such as from
+    /// [`current_timestamp`]. See [`decode_timestamp`].
+    pub timestamp: Option<u64>,
+    /// How many pixels make up a unit of physical size, such as from [`PixelDensity::from_dpi`],
+    /// for tools that print or scale the art. Comes back in [`DecodedPIE::density`].
+    pub density: Option<PixelDensity>,
+}
+
+impl EncodeOptions<'_> {
+    /// Attach application data, such as attachment points or physics hints, as a chunk that
+    /// decoders which do not know it skip. Kinds are four ASCII letters or digits starting with a
+    /// lowercase letter, so they never clash with the chunks of the format itself. Custom chunks
+    /// make the file version 2 and come back in [`DecodedPIE::chunks`].
+    pub fn add_chunk(&mut self, kind: [u8; 4], data: impl Into<Vec<u8>>) -> &mut Self {
+        self.chunks.push((kind, data.into()));
+        self
     }
 
-    let mut bytes: Vec<u8> = vec!['P' as u8, 'I' as u8, 'E' as u8, 1];
-    bytes.append(&mut width.to_be_bytes().to_vec());
-    bytes.append(&mut height.to_be_bytes().to_vec());
-    bytes.push(0); // Fill with flags later
-    bytes.append(&mut ((encoded.indices.len() / 2) as u16).to_be_bytes().to_vec());
-    bytes.append(&mut encoded.indices.to_vec());
+    /// Annotate the file with `text` under `keyword`, such as "Author" or "Description",
+    /// replacing any comment with the same keyword. Keywords are 1 to 79 bytes without a 0.
+    /// Comments make the file version 2 and come back in [`DecodedPIE::comments`].
+    pub fn set_comment(&mut self, keyword: &str, text: &str) -> &mut Self {
+        match self.comments.iter_mut().find(|(k, _)| k == keyword) {
+            Some(comment) => comment.1 = text.to_string(),
+            None => self.comments.push((keyword.to_string(), text.to_string())),
+        }
+        self
+    }
+
+    /// Chunks to write after the palette.
+    fn chunks_to_write(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+        if let Some(nine_slice) = self.nine_slice {
+            chunks.push((CHUNK_NINE_SLICE, nine_slice.to_bytes()));
+        }
+        if let Some(timestamp) = self.timestamp {
+            chunks.push((CHUNK_TIME, timestamp.to_be_bytes().to_vec()));
+        }
+        if let Some(density) = self.density {
+            chunks.push((CHUNK_PHYS, density.to_bytes()));
+        }
+        chunks.extend(self.comments.iter().map(|(keyword, text)| (CHUNK_TEXT, comment::to_bytes(keyword, text))));
+        chunks.extend(self.chunks.iter().cloned());
+        chunks
+    }
 
-    if embed_palette {
-        flags |= FLAG_PALETTE;
-        bytes.append(&mut encoded.palette.unwrap().colors.to_vec());
+    /// Fail on the first custom chunk whose kind is not allowed, see [`is_custom_chunk`], or
+    /// comment whose keyword is not.
+    pub(crate) fn check_chunks(&self) -> Result<(), EncodeError> {
+        if let Some((kind, _)) = self.chunks.iter().find(|(kind, _)| !is_custom_chunk(kind)) {
+            return Err(EncodeError::InvalidChunkKind { kind: *kind });
+        }
+        self.comments.iter().try_for_each(|(keyword, _)| comment::check_keyword(keyword))
     }
+}
 
-    bytes[8] = flags;
+/// Whether `kind` is one applications may use for their own chunks.
+pub(crate) fn is_custom_chunk(kind: &[u8; 4]) -> bool {
+    kind[0].is_ascii_lowercase() && kind.iter().all(u8::is_ascii_alphanumeric)
+}
+
+/// Encode and write a PIE file to disk, see [`write`].
+pub fn write_with_options(path: &str, width: u16, height: u16, pixels: impl AsRef<[u8]>, options: &EncodeOptions) -> Result<bool, EncodeError> {
+    span!(INFO, "write", path);
+    let bytes = encode_with_options(width, height, pixels, options).map_err(|error| error.in_file(path))?;
 
-    fs::write(path, &bytes).expect("Failed to write file.");
+    fs::write(path, &bytes).map_err(|error| EncodeError::Io(error.kind()).in_file(path))?;
+    event!(DEBUG, bytes = bytes.len(), "wrote file");
     Ok(true)
 }
 
-/// Encode an array of RGB or RGBA bytes into an EncodedPIE.
-/// Note that an EncodedPIE struct is not the same format as a saved .PIE file.
-/// To get the correct format for saving, use the write function.
-pub fn encode(width: u16, height: u16, pixel_bytes: &[u8], embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
-    let mut encoded = EncodedPIE {
-        width, height,
-        indices: Vec::new(),
-        palette: None
+/// Encode an array of RGB or RGBA bytes straight to the bytes of a PIE file, including chunks
+/// for the extras in `options`.
+pub fn encode_with_options(width: u16, height: u16, pixels: impl AsRef<[u8]>, options: &EncodeOptions) -> Result<Vec<u8>, EncodeError> {
+    Ok(encode_with_stats(width, height, pixels, options)?.0)
+}
+
+/// Like [`encode_with_options`], also returning statistics about the file written, including
+/// which of the layouts `options` allow was chosen.
+pub fn encode_with_stats(width: u16, height: u16, pixels: impl AsRef<[u8]>, options: &EncodeOptions) -> Result<(Vec<u8>, EncodeStats), EncodeError> {
+    span!(DEBUG, "encode_with_options", width, height, trim = options.trim);
+    let mut pixels = Cow::Borrowed(pixels.as_ref());
+    let (mut width, mut height) = (width, height);
+    options.check_chunks()?;
+    let mut chunks = options.chunks_to_write();
+
+    let is_rgba = |width: u16, height: u16, pixels: &[u8]| pixels.len() == width as usize * height as usize * 4;
+    if let Some(threshold) = options.alpha_threshold.filter(|_| is_rgba(width, height, &pixels)) {
+        pixels = Cow::Owned(alpha::threshold(&pixels, threshold));
+    }
+    if options.trim {
+        if let Some((trimmed, trimmed_width, trimmed_height, cropped)) = trim::trim(width, height, &pixels) {
+            event!(DEBUG, x = trimmed.x, y = trimmed.y, width = trimmed_width, height = trimmed_height, "trimmed");
+            chunks.push((CHUNK_TRIM, trimmed.to_bytes()));
+            (width, height, pixels) = (trimmed_width, trimmed_height, Cow::Owned(cropped));
+        }
+    }
+    if let Some(background) = options.flatten.filter(|_| is_rgba(width, height, &pixels)) {
+        pixels = Cow::Owned(alpha::flatten(&pixels, background));
+    }
+
+    let mut encoded = encode(width, height, pixels, options.embed_palette, options.palette)?;
+    if options.sort_palette && options.palette.is_none() {
+        encoded.sort_palette_by_usage();
+    }
+    let colors = match options.palette {
+        Some(palette) => palette.colors.len() / if encoded.format == PixelFormat::RGBA { 4 } else { 3 },
+        // Generated palettes hold exactly the colours used, indexed in order of appearance.
+        None => encoded.indices.chunks(2).map(|run| run[1] as usize + 1).max().unwrap_or(0),
     };
+    finish_encoding(width, height, encoded, colors, options, chunks)
+}
 
+/// Encode palette indices, one per pixel, straight to the bytes of a PIE file, for callers that
+/// already work in indices. Every index must be in `palette`, which `options.palette` is ignored
+/// in favour of. Options that work on colours, `alpha_threshold`, `trim` and `flatten`, are
+/// ignored too.
+pub fn encode_indexed(width: u16, height: u16, indices: &[u8], palette: &Palette, options: &EncodeOptions) -> Result<Vec<u8>, EncodeError> {
+    span!(DEBUG, "encode_indexed", width, height);
+    let pixel_count = width as usize * height as usize;
+    if indices.len() != pixel_count {
+        return Err(EncodeError::WrongPixelCount { expected: pixel_count, found: indices.len() });
+    }
+    let colors = palette.colors.len() / if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= colors) {
+        return Err(EncodeError::IndexOutOfPalette { index });
+    }
 
-    let mut chunk_size = 4;
-    if pixel_bytes.len() == (width as usize * height as usize * 3) {
-        chunk_size = 3;
+    let encoded = EncodedPIE {
+        width, height,
+        format: palette.format,
+        indices: rle(indices, 255),
+        palette: options.embed_palette.then(|| palette.clone()),
     };
+    options.check_chunks()?;
+    Ok(finish_encoding(width, height, encoded, colors, options, options.chunks_to_write())?.0)
+}
 
-    // If palette is not included, it must be created on the fly.
-    if maybe_palette.is_none() {
-        let mut indices = Vec::new();
-        let mut palette = Palette {
-            format: if chunk_size == 3 { PixelFormat::RGB } else { PixelFormat::RGBA },
-            colors: Vec::new()
-        };
-        let mut map = HashMap::new();
-        let mut index: u8 = 0;
-        for chunk in pixel_bytes.chunks(chunk_size) {
-            if !map.contains_key(chunk) {
-                map.insert(chunk, index);
-                index += 1;
-                palette.colors.append(&mut chunk.to_vec());
-            }
+/// Pad `encoded` if `options` ask for it and write it out with `chunks`. `colors` is how many
+/// colours the palette has, to check the padding index against.
+fn finish_encoding(mut width: u16, mut height: u16, mut encoded: EncodedPIE, colors: usize, options: &EncodeOptions, mut chunks: Vec<([u8; 4], Vec<u8>)>) -> Result<(Vec<u8>, EncodeStats), EncodeError> {
+    let bytes_in = width as usize * height as usize * if encoded.format == PixelFormat::RGBA { 4 } else { 3 };
+    if let Some(padding) = options.padding {
+        let (padded_width, padded_height, runs) = padding::pad(width, height, &encoded.indices, padding, options.padding_index, colors)?;
+        chunks.push((CHUNK_PADDING, [width.to_be_bytes(), height.to_be_bytes()].concat()));
+        (width, height, encoded.indices) = (padded_width, padded_height, runs);
+    }
 
-            indices.push(*map.get(chunk).unwrap() as u8);
-        }
+    let mut bytes = Vec::new();
+    let (flags, data) = scan::encode_data(width as usize, &encoded.indices, options);
+    let runs = if flags & FLAG_ROWS > 0 { data.len() } else { data.len() / 2 } as u32;
+    let header = Header { version: if flags > 0 { 2 } else { 1 }, width, height, flags, colors: 0, runs };
+    write_sections(&mut bytes, header, encoded.format, &data, encoded.palette.as_ref().map(|palette| palette.colors.as_slice()), &chunks);
 
-        if embed_palette {
-            encoded.palette = Some(palette);
-        }
-        encoded.indices = rle(&indices, 255);
-    } else if let Some(palette) = maybe_palette {
-        let mut indices = Vec::new();
-        let map = palette.colors.chunks(chunk_size).into_iter().enumerate().fold(HashMap::new(), |mut acc, (idx, x)| {
-            acc.insert(x, idx);
-            acc
-        });
-        for chunk in pixel_bytes.chunks(chunk_size) {
-            if !map.contains_key(chunk) {
-                return Err(EncodeError::ColorNotInPalette);
-            }
+    let strategy = EncodeStrategy {
+        row_modes: flags & FLAG_ROWS > 0,
+        serpentine: flags & FLAG_SERPENTINE > 0,
+        sorted_palette: options.sort_palette && options.palette.is_none(),
+    };
+    let stats = EncodeStats::new(width, height, &encoded.indices, bytes_in, &bytes, strategy);
+    Ok((bytes, stats))
+}
 
-            indices.push(*map.get(chunk).unwrap() as u8);
+/// Encode an array of RGB or RGBA bytes into an EncodedPIE.
+/// Note that an EncodedPIE struct is not the same format as a saved .PIE file.
+/// To get the correct format for saving, use the write function. When encoding many images, an
+/// [`Encoder`] reuses its buffers between them.
+pub fn encode(width: u16, height: u16, pixel_bytes: impl AsRef<[u8]>, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+    Encoder::new().encode(width, height, pixel_bytes, embed_palette, maybe_palette)
+}
 
-            if embed_palette {
-                encoded.palette = Some(palette.to_owned());
-            }
-            encoded.indices = rle(&indices, 255);
-        }
-    }
+/// Encode pixels in padded rows, such as buffers from a GPU or window system, without repacking
+/// them first. See [`Encoder::encode_strided`].
+pub fn encode_strided(width: u16, height: u16, pixels: StridedPixels, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+    Encoder::new().encode_strided(width, height, pixels, embed_palette, maybe_palette)
+}
 
-    Ok(encoded)
+/// Encode pixels packed into a `u32` each, see [`Encoder::encode_u32`].
+pub fn encode_u32(width: u16, height: u16, pixels: &[u32], layout: PackedLayout, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+    Encoder::new().encode_u32(width, height, pixels, layout, embed_palette, maybe_palette)
 }
 
 /// Encode a series of u8s into runs `(count, value)` with a max of `limit`.
 pub fn rle(data: &[u8], limit: usize) -> Vec<u8> {
     let mut encoded = Vec::new();
+    rle_into(data, limit, &mut encoded);
+    encoded
+}
+
+/// Like [`rle`], appending the runs to `encoded`.
+pub(crate) fn rle_into(data: &[u8], limit: usize, encoded: &mut Vec<u8>) {
+    span!(TRACE, "rle", indices = data.len());
     let mut i = 0;
     while i < data.len() {
         let mut count = 1;
@@ -235,7 +908,26 @@ pub fn rle(data: &[u8], limit: usize) -> Vec<u8> {
         encoded.push(data[i]);
         i += count;
     }
-    encoded
+}
+
+/// Expand runs `(count, value)` made by [`rle`] back into the values. A trailing odd byte is
+/// ignored.
+pub fn rle_decode(runs: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    for run in runs.chunks_exact(2) {
+        decoded.extend(std::iter::repeat_n(run[1], run[0] as usize));
+    }
+    decoded
+}
+
+/// Like [`rle_decode`], but errors unless the runs expand to exactly `expected` values, such as
+/// the pixel count of an image.
+pub fn rle_decode_exact(runs: &[u8], expected: usize) -> Result<Vec<u8>, DecodeError> {
+    let found: usize = runs.chunks_exact(2).map(|run| run[0] as usize).sum();
+    if found != expected || !runs.len().is_multiple_of(2) {
+        return Err(DecodeError::WrongPixelCount { expected, found });
+    }
+    Ok(rle_decode(runs))
 }
 
 /// Read a PIE file from disk and decode it into a DecodedPIE.
@@ -243,70 +935,123 @@ pub fn rle(data: &[u8], limit: usize) -> Vec<u8> {
 /// # Arguments
 /// * `path` - A string slice that is a path to the file on disk.
 /// * `palette` - An optional palette that must be included if the PIE file was saved with an
-/// external palette.
+///   external palette.
 pub fn read(path: &str, palette: Option<&Palette>) -> Result<DecodedPIE, DecodeError> {
-    let mut file = File::open(path).expect("Could not open file");
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes).expect("Could not read file");
+    span!(INFO, "read", path);
+    let bytes = read_file(path)?;
+    decode(&bytes, palette).map_err(|error| error.in_file(path))
+}
 
-    decode(&bytes, palette)
+pub(crate) fn read_file(path: &str) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|error| DecodeError::Io(error.kind()).in_file(path))?;
+    event!(DEBUG, bytes = bytes.len(), "read file");
+    Ok(bytes)
 }
 
 /// Decode raw bytes from PIE format into a [`DecodedPIE`].
+/// For animated files this is the first frame, see [`decode_animation`] for the rest.
 /// * `bytes` - The raw bytes including header, index data, and optionally palette.
 /// * `palette` - Required if the palette is not embedded in `bytes`.
-pub fn decode(bytes: &[u8], maybe_palette: Option<&Palette>) -> Result<DecodedPIE, DecodeError> {
-    let mut decoded = DecodedPIE {
-        width: 0, height: 0,
-        format: PixelFormat::RGB, pixels: vec![]
-    };
+pub fn decode(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>) -> Result<DecodedPIE, DecodeError> {
+    let bytes = bytes.as_ref();
+    span!(DEBUG, "decode", bytes = bytes.len());
+    let header = Header::parse(bytes)?;
+    event!(DEBUG, version = header.version, width = header.width, height = header.height, flags = header.flags, "read header");
+    let palette = resolve_palette(bytes, &header, maybe_palette)?;
+    let runs = rows::data_runs(bytes, &header)?;
 
-    let mut palette = Palette {
-        format: PixelFormat::RGB,
-        colors: Vec::new(),
-    };
-
-    assert!(bytes[0] == 'P' as u8 && bytes[1] == 'I' as u8 && bytes[2] == 'E' as u8);
-    decoded.width = u16::from_be_bytes([bytes[4], bytes[5]]);
-    decoded.height = u16::from_be_bytes([bytes[6], bytes[7]]);
-    let flags = bytes[8];
-
-    palette.format = PixelFormat::RGB;
-    let mut step = 3;
+    Ok(DecodedPIE {
+        width: header.width,
+        height: header.height,
+        format: palette.format,
+        pixels: expand_runs(&runs, header.size(), header.width as usize * header.height as usize, &palette)?,
+        palette,
+        chunks: custom_chunks(bytes, &header),
+        comments: comment::comments(bytes, &header),
+        density: density::density(bytes, &header),
+    })
+}
 
-    if flags & FLAG_TRANSPARENCY > 0 {
-        palette.format = PixelFormat::RGBA;
-        step = 4;
+/// The custom chunks of the file, up to any that cannot be read.
+pub(crate) fn custom_chunks(bytes: &[u8], header: &Header) -> Vec<([u8; 4], Vec<u8>)> {
+    if header.version < 2 {
+        return Vec::new();
     }
+    chunk::chunks(bytes, header.chunks_offset())
+        .map_while(Result::ok)
+        .filter(|chunk| is_custom_chunk(&chunk.kind))
+        .map(|chunk| (chunk.kind, chunk.data.to_vec()))
+        .collect()
+}
 
-    let data_length = u16::from_be_bytes([bytes[9], bytes[10]]);
-
-    if flags & FLAG_PALETTE > 0 {
-        for (index, _) in bytes.iter().skip(HEADER_SIZE + (data_length * 2) as usize).enumerate().step_by(step) {
-            let absolute_index = HEADER_SIZE + (data_length * 2) as usize + index - 1;
-            for i in 0..step {
-                palette.colors.push(bytes[absolute_index + step - i]);
-            }
-        }
+/// The embedded palette if there is one, otherwise the external palette.
+pub(crate) fn resolve_palette(bytes: &[u8], header: &Header, maybe_palette: Option<&Palette>) -> Result<Palette, DecodeError> {
+    if header.flags & FLAG_PALETTE > 0 {
+        Ok(Palette {
+            format: header.format(),
+            colors: section(bytes, header.palette_offset()..header.chunks_offset())?.to_vec(),
+        })
     } else if let Some(p) = maybe_palette {
-        palette.format = p.format;
-        palette.colors = p.colors.to_owned();
+        Ok(p.to_owned())
     } else {
-        return Err(DecodeError::MissingPalette);
+        Err(DecodeError::MissingPalette)
+    }
+}
+
+/// The bytes in `range`, or an error saying how far short the file is.
+pub(crate) fn section(bytes: &[u8], range: Range<usize>) -> Result<&[u8], DecodeError> {
+    let found = bytes.len();
+    bytes.get(range.clone()).ok_or(DecodeError::Truncated { expected: range.end, found })
+}
+
+/// Expand `(count, index)` runs into `pixel_count` pixels using the colours in `palette`.
+/// `offset` is where the runs start in the file, for errors.
+pub(crate) fn expand_runs(runs: &[u8], offset: usize, pixel_count: usize, palette: &Palette) -> Result<Vec<u8>, DecodeError> {
+    span!(DEBUG, "expand_runs", runs = runs.len() / 2, pixels = pixel_count);
+    let step = if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+    let found: usize = runs.chunks_exact(2).map(|run| run[0] as usize).sum();
+    if found != pixel_count {
+        return Err(DecodeError::WrongPixelCount { expected: pixel_count, found });
+    }
+
+    // Every colour padded to 4 bytes, so finding a run's colour is one multiply and no lookup
+    // into a palette of unknown length.
+    let colors = (palette.colors.len() / step).min(256);
+    let mut table = [0u8; 256 * 4];
+    for (i, color) in palette.colors.chunks_exact(step).take(colors).enumerate() {
+        table[i * 4..i * 4 + step].copy_from_slice(color);
     }
 
-    for i in (HEADER_SIZE..(HEADER_SIZE + (data_length * 2) as usize)).step_by(2) {
-        let run_length = bytes[i];
-        let color_index = bytes[i + 1] as usize * step;
+    let mut pixels = vec![0; pixel_count * step];
+    let mut at = 0;
+    for (i, run) in runs.chunks_exact(2).enumerate() {
+        let index = run[1] as usize;
+        if index >= colors {
+            return Err(DecodeError::IndexOutOfPalette { index: run[1], offset: offset + i * 2 + 1 });
+        }
 
-        for _ in 0..run_length {
-            decoded.pixels.append(&mut vec![palette.colors[color_index + 2], palette.colors[color_index + 1], palette.colors[color_index]]);
+        let end = at + run[0] as usize * step;
+        let color = &table[index * 4..index * 4 + 4];
+        match step {
+            4 => fill::<4>(&mut pixels[at..end], color),
+            _ => fill::<3>(&mut pixels[at..end], color),
         }
+        at = end;
     }
 
-    decoded.format = palette.format;
+    Ok(pixels)
+}
 
-    Ok(decoded)
+/// Fill `pixels` with the first `N` bytes of `color`. Sized at compile time so copies are a few
+/// moves rather than a call to memcpy per pixel.
+fn fill<const N: usize>(pixels: &mut [u8], color: &[u8]) {
+    let color: [u8; N] = color[..N].try_into().unwrap();
+    for pixel in pixels.chunks_exact_mut(N) {
+        pixel.copy_from_slice(&color);
+    }
 }
 
 #[test]
@@ -329,6 +1074,7 @@ fn test_decode() {
     assert_eq!(start_pixel, decoded.pixels[0..3]);
     assert_eq!(end_pixel, decoded.pixels[decoded.pixels.len() - 3..]);
     assert_eq!(decoded.pixels, decoded_with_palette.pixels);
+    assert_eq!(palette_bytes.to_vec(), decoded.palette.colors);
 }
 
 #[test]
@@ -383,6 +1129,119 @@ fn test_encode() {
     assert!(encoded.palette.is_none());
 }
 
+#[test]
+fn test_encode_indexed() {
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00] };
+    let indices = [1, 1, 0, 0, 0, 1];
+    let options = EncodeOptions { embed_palette: true, ..Default::default() };
+
+    let bytes = encode_indexed(3, 2, &indices, &palette, &options).unwrap();
+    let pixels = [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00];
+    assert_eq!(encode_with_options(3, 2, pixels, &EncodeOptions { palette: Some(&palette), ..options.clone() }).unwrap(), bytes);
+    assert_eq!(vec![2, 1, 3, 0, 1, 1], EncodedPIE::from_bytes(&bytes).unwrap().indices);
+
+    let padded = decode(encode_indexed(3, 2, &indices, &palette, &EncodeOptions { padding: Some(Padding::PowerOfTwo), ..options.clone() }).unwrap(), None).unwrap();
+    assert_eq!((4, 2), (padded.width, padded.height));
+
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 6, found: 5 }), encode_indexed(3, 2, &indices[..5], &palette, &options));
+    assert_eq!(Err(EncodeError::IndexOutOfPalette { index: 2 }), encode_indexed(3, 2, &[0, 1, 2, 0, 1, 2], &palette, &options));
+}
+
+#[test]
+fn test_row_modes() {
+    // A flat row over a dithered one, which is smaller raw.
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![0, 0, 0, 0xFF, 0xFF, 0xFF] };
+    let indices = [[0; 16].as_slice(), &[0, 1].repeat(8)].concat();
+    let options = EncodeOptions { embed_palette: true, row_modes: true, ..Default::default() };
+    let rows = encode_indexed(16, 2, &indices, &palette, &options).unwrap();
+    let runs = encode_indexed(16, 2, &indices, &palette, &EncodeOptions { row_modes: false, ..options.clone() }).unwrap();
+
+    let header = decode_header(&rows).unwrap();
+    assert!(header.has_row_modes());
+    assert_eq!((2, 20, 15..35), (header.version, header.runs, header.data_range()));
+    assert!(rows.len() < runs.len());
+    assert_eq!(decode(&runs, None), decode(&rows, None));
+    assert_eq!(EncodedPIE::from_bytes(&runs), EncodedPIE::from_bytes(&rows));
+    assert!(decode_with_diagnostics(&rows, None).unwrap().1.is_empty());
+    assert!(lint(&rows).is_empty());
+    assert_eq!(Err(MigrateError::UnsupportedFlags { flags: FLAG_ROWS }), migrate(&rows, 1));
+
+    let mut invalid = rows.clone();
+    invalid[15] = 2;
+    assert_eq!(Err(DecodeError::InvalidRow { row: 0, offset: 15 }), decode(&invalid, None));
+}
+
+#[test]
+fn test_serpentine() {
+    // A band down one side, which reversing every other row joins up.
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![0, 0, 0, 0xFF, 0xFF, 0xFF] };
+    let indices = [0, 0, 0, 1].repeat(4);
+    let plain = EncodeOptions { embed_palette: true, ..Default::default() };
+    let runs = encode_indexed(4, 4, &indices, &palette, &plain).unwrap();
+
+    let serpentine = encode_indexed(4, 4, &indices, &palette, &EncodeOptions { serpentine: true, ..plain.clone() }).unwrap();
+    let header = decode_header(&serpentine).unwrap();
+    assert!(header.is_serpentine());
+    assert_eq!((8, 5), (decode_header(&runs).unwrap().runs, header.runs));
+    assert!(serpentine.len() < runs.len());
+    assert_eq!(decode(&runs, None), decode(&serpentine, None));
+    assert_eq!(EncodedPIE::from_bytes(&runs), EncodedPIE::from_bytes(&serpentine));
+
+    // With row modes each row costs the same either way, so the normal order is kept.
+    let rows = encode_indexed(4, 4, &indices, &palette, &EncodeOptions { serpentine: true, row_modes: true, ..plain.clone() }).unwrap();
+    assert!(!decode_header(&rows).unwrap().is_serpentine());
+    assert_eq!(decode(&runs, None), decode(&rows, None));
+}
+
+#[test]
+fn test_custom_chunks() {
+    let pixels = [1, 2, 3, 4, 5, 6];
+    let mut options = EncodeOptions { embed_palette: true, ..Default::default() };
+    options.add_chunk(*b"attc", [0, 4, 0, 2]).add_chunk(*b"phy2", *b"solid");
+
+    let bytes = encode_with_options(2, 1, pixels, &options).unwrap();
+    let decoded = decode(&bytes, None).unwrap();
+    assert_eq!(vec![(*b"attc", vec![0, 4, 0, 2]), (*b"phy2", b"solid".to_vec())], decoded.chunks);
+    assert_eq!(pixels.to_vec(), decoded.pixels);
+    assert!(decode(encode(2, 1, pixels, true, None).unwrap().to_bytes(), None).unwrap().chunks.is_empty());
+
+    // Kinds of the format itself are refused.
+    for kind in [*b"META", *b"aB-c", *b"1abc"] {
+        let mut options = EncodeOptions::default();
+        options.add_chunk(kind, []);
+        assert_eq!(Err(EncodeError::InvalidChunkKind { kind }), encode_with_options(2, 1, pixels, &options));
+    }
+}
+
+#[test]
+fn test_comments() {
+    let pixels = [1, 2, 3, 4, 5, 6];
+    let mut options = EncodeOptions { embed_palette: true, ..Default::default() };
+    options.set_comment("Author", "Ada").set_comment("Description", "Two pixels ✓").set_comment("Author", "Grace");
+
+    let bytes = encode_with_options(2, 1, pixels, &options).unwrap();
+    let decoded = decode(&bytes, None).unwrap();
+    let expected = vec![("Author".to_string(), "Grace".to_string()), ("Description".to_string(), "Two pixels ✓".to_string())];
+    assert_eq!(expected, decoded.comments);
+    assert!(decoded.chunks.is_empty());
+    assert!(decode(encode(2, 1, pixels, true, None).unwrap().to_bytes(), None).unwrap().comments.is_empty());
+
+    let mut options = EncodeOptions::default();
+    options.set_comment("", "empty");
+    assert_eq!(Err(EncodeError::InvalidCommentKeyword { keyword: String::new() }), encode_with_options(2, 1, pixels, &options));
+}
+
+#[test]
+fn test_density() {
+    let pixels = [1, 2, 3, 4, 5, 6];
+    let density = PixelDensity::from_dpi(144.0);
+    let bytes = encode_with_options(2, 1, pixels, &EncodeOptions { embed_palette: true, density: Some(density), ..Default::default() }).unwrap();
+    let decoded = decode(&bytes, None).unwrap();
+    assert_eq!(Some(density), decoded.density);
+    assert_eq!(pixels.to_vec(), decoded.pixels);
+    assert_eq!(None, decode(encode(2, 1, pixels, true, None).unwrap().to_bytes(), None).unwrap().density);
+}
+
 #[test]
 fn test_read() {
     let decoded = read("images/test_embedded_palette.pie", None).unwrap();
@@ -424,9 +1283,122 @@ fn test_write() {
         ],
     };
 
-    assert!(write("tmp.pie", 5, 4, true, Some(&palette), pixels.to_owned()).is_ok());
+    assert!(write("tmp.pie", 5, 4, true, Some(&palette), &pixels).is_ok());
 
     let decoded = read("tmp.pie", Some(&palette)).expect("Could not read");
     assert_eq!(pixels, decoded.pixels);
     assert!(fs::remove_file("tmp.pie").is_ok());
 }
+
+#[test]
+fn test_malformed() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+
+    // The last run points past the 4 colour palette.
+    let mut bad_index = bytes.to_vec();
+    bad_index[56] = 9;
+    assert_eq!(Err(DecodeError::IndexOutOfPalette { index: 9, offset: 56 }), decode(&bad_index, None));
+
+    let mut bad_count = bytes.to_vec();
+    bad_count[55] += 1;
+    assert_eq!(Err(DecodeError::WrongPixelCount { expected: 64, found: 65 }), decode(&bad_count, None));
+
+    let mut bad_runs = bytes.to_vec();
+    bad_runs[9..11].copy_from_slice(&[0xFF, 0xFF]);
+    assert_eq!(Err(DecodeError::Truncated { expected: 11 + 0xFFFF * 2, found: bytes.len() }), decode(&bad_runs, None));
+}
+
+#[test]
+fn test_rle() {
+    let data = [1, 1, 1, 2, 3, 3];
+    assert_eq!(vec![3, 1, 1, 2, 2, 3], rle(&data, 255));
+    assert_eq!(vec![2, 1, 1, 1, 1, 2, 2, 3], rle(&data, 2));
+    assert_eq!(data.to_vec(), rle_decode(&rle(&data, 2)));
+    assert_eq!(Ok(data.to_vec()), rle_decode_exact(&rle(&data, 255), 6));
+    assert_eq!(Err(DecodeError::WrongPixelCount { expected: 7, found: 6 }), rle_decode_exact(&rle(&data, 255), 7));
+}
+
+#[test]
+fn test_accessors() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    let encoded = EncodedPIE::from_bytes(bytes).unwrap();
+    let decoded = decode(bytes, None).unwrap();
+    let (x, y) = (decoded.width - 1, decoded.height - 1);
+
+    assert_eq!(Some([0x6A, 0xBE, 0x30].as_slice()), decoded.color_at(0, 0));
+    assert_eq!(Some([0x5B, 0x6E, 0xE1].as_slice()), decoded.color_at(x, y));
+    assert_eq!(Some(0), encoded.index_of(0, 0));
+    assert_eq!(Some(3), encoded.index_of(x, y));
+    assert_eq!(None, decoded.color_at(x + 1, 0));
+    assert_eq!(None, encoded.index_of(0, y + 1));
+}
+
+#[test]
+fn test_pixel_conversions() {
+    let palette = |format, colors| Palette { format, colors };
+    let rgb = DecodedPIE { width: 2, height: 1, format: PixelFormat::RGB, pixels: vec![1, 2, 3, 4, 5, 6], palette: palette(PixelFormat::RGB, vec![1, 2, 3, 4, 5, 6]), chunks: Vec::new(), comments: Vec::new(), density: None };
+    assert_eq!(vec![1, 2, 3, 0xFF, 4, 5, 6, 0xFF], rgb.to_rgba8());
+    assert_eq!(rgb.pixels, rgb.to_rgb8(Some([0xFF, 0xFF, 0xFF])));
+
+    let rgba = DecodedPIE { width: 2, height: 1, format: PixelFormat::RGBA, pixels: vec![0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x80], palette: palette(PixelFormat::RGBA, vec![0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x80]), chunks: Vec::new(), comments: Vec::new(), density: None };
+    assert_eq!(rgba.pixels, rgba.to_rgba8());
+    assert_eq!(vec![0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00], rgba.to_rgb8(None));
+    assert_eq!(vec![0xFF, 0x00, 0x00, 0xFF, 0x7F, 0x7F], rgba.to_rgb8(Some([0xFF, 0xFF, 0xFF])));
+}
+
+#[test]
+fn test_bytes() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    let encoded = EncodedPIE::from_bytes(bytes).unwrap();
+    assert_eq!(8, encoded.width);
+    assert_eq!(PixelFormat::RGB, encoded.format);
+    assert_eq!(12, encoded.palette.as_ref().unwrap().colors.len());
+    assert_eq!(bytes.to_vec(), encoded.to_bytes());
+
+    let decoded: DecodedPIE = bytes.as_slice().try_into().unwrap();
+    assert_eq!(decode(bytes, None).unwrap(), decoded);
+    let header: Header = bytes.as_slice().try_into().unwrap();
+    assert_eq!(8, header.height);
+
+    let pixels: Vec<u8> = (0..200).flat_map(|i| [i, 0, 0, 0xFF]).collect();
+    let encoded = encode(200, 1, &pixels, false, None).unwrap();
+    let bytes = encoded.to_bytes();
+    assert_eq!(HEADER_SIZE + 400, bytes.len());
+    assert_eq!(FLAG_TRANSPARENCY, bytes[8]);
+    assert_eq!(encoded, EncodedPIE::from_bytes(&bytes).unwrap());
+}
+
+#[test]
+fn test_estimated_decoded_size() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    let decoded = decode(bytes, None).unwrap();
+    assert_eq!(Ok(decoded.pixels.len()), estimated_decoded_size(&bytes[..HEADER_SIZE]));
+    assert_eq!(Ok(3 * 2 * 3), estimated_decoded_size(encode(3, 2, [0; 18], false, None).unwrap().to_bytes()));
+    assert_eq!(Err(DecodeError::Truncated { expected: HEADER_SIZE, found: 4 }), estimated_decoded_size(&bytes[..4]));
+}
+
+#[test]
+fn test_header() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    assert_eq!(Ok((8, 8, PixelFormat::RGB)), dimensions(&bytes[..]));
+    assert_eq!(Ok((8, 8, PixelFormat::RGB)), dimensions(File::open("images/test_embedded_palette.pie").unwrap()));
+
+    let header = read_header("images/test_embedded_palette.pie").unwrap();
+    assert_eq!(1, header.version);
+    assert_eq!(23, header.runs);
+    assert!(header.has_palette());
+    assert!(!header.is_animated());
+    assert_eq!(4, decode_header(bytes).unwrap().colors);
+    assert_eq!((11..57, 57..57), (header.data_range(), header.palette_range()));
+    assert_eq!(57..69, decode_header(bytes).unwrap().palette_range());
+
+    assert_eq!(Err(DecodeError::Truncated { expected: 11, found: 8 }), dimensions(&bytes[..8]));
+    assert_eq!(Err(DecodeError::Truncated { expected: 11, found: 2 }), dimensions(&bytes[..2]));
+    assert_eq!(Err(DecodeError::BadMagic { found: *b"\x89PN" }), dimensions(&b"\x89PNG\r\n\x1a\n"[..]));
+    for version in [0, 3] {
+        let mut newer = bytes.to_vec();
+        newer[3] = version;
+        assert_eq!(Err(DecodeError::UnsupportedVersion { found: version, max_supported: VERSION }), decode(&newer, None).map(|_| ()));
+    }
+    assert_eq!(Err(DecodeError::Io(std::io::ErrorKind::NotFound).in_file("images/missing.pie")), read_header("images/missing.pie"));
+}
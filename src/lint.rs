@@ -0,0 +1,201 @@
+//! Checking files against the spec and for things that make them larger or less useful than they
+//! need to be, with a fix for the problems that can be fixed without changing the image.
+use crate::{
+    chunk::read_chunks, encode, expand_runs, rows::data_runs, section, write_image, DecodeError, Header, Palette, PixelFormat,
+    FLAG_ANIMATION, FLAG_PALETTE, FLAG_ROWS, FLAG_SERPENTINE, FLAG_TRANSPARENCY,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    /// The file breaks the spec and may not decode.
+    Error,
+    /// The file is valid but could be better.
+    Warning,
+}
+
+/// A problem [`lint`] found.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+    /// Whether [`fix`] can rewrite the file without it.
+    pub fixable: bool,
+}
+
+impl Issue {
+    fn error(message: String) -> Issue {
+        Issue { severity: Severity::Error, message, fixable: false }
+    }
+
+    fn warning(message: String, fixable: bool) -> Issue {
+        Issue { severity: Severity::Warning, message, fixable }
+    }
+}
+
+/// Every problem with the raw bytes of a PIE file. Palette checks need the palette to be
+/// embedded.
+pub fn lint(bytes: impl AsRef<[u8]>) -> Vec<Issue> {
+    let bytes = bytes.as_ref();
+    let header = match Header::parse(bytes) {
+        Ok(header) => header,
+        Err(error) => return vec![Issue::error(error.to_string())],
+    };
+
+    let mut issues = Vec::new();
+    let reserved = header.flags & !(FLAG_PALETTE | FLAG_TRANSPARENCY | FLAG_ANIMATION | FLAG_ROWS | FLAG_SERPENTINE);
+    if reserved > 0 {
+        issues.push(Issue::error(format!("reserved flag bits {:#010b} are set", reserved)));
+    }
+    if header.version < 2 && header.is_animated() {
+        issues.push(Issue::error("the frames flag is set but version 1 files cannot hold frames".to_string()));
+    }
+    if header.version < 2 && header.flags & (FLAG_ROWS | FLAG_SERPENTINE) > 0 {
+        issues.push(Issue::error("the row modes or serpentine flag is set but version 1 files cannot hold them".to_string()));
+    }
+
+    let runs = match data_runs(bytes, &header) {
+        Ok(runs) => runs,
+        Err(error) => {
+            issues.push(Issue::error(error.to_string()));
+            return issues;
+        }
+    };
+
+    let pixel_count = header.width as usize * header.height as usize;
+    let total: usize = runs.chunks_exact(2).map(|run| run[0] as usize).sum();
+    if total != pixel_count {
+        issues.push(Issue::error(format!("runs hold {} pixels but the image is {}x{}", total, header.width, header.height)));
+    }
+    let empty = runs.chunks_exact(2).filter(|run| run[0] == 0).count();
+    if empty > 0 {
+        issues.push(Issue::warning(format!("{} runs are empty", empty), true));
+    }
+    if runs.len() > pixel_count {
+        issues.push(Issue::warning(format!("{} bytes of runs is more than the {} bytes of one index per pixel", runs.len(), pixel_count), false));
+    }
+
+    if header.version >= 2 {
+        if let Err(error) = read_chunks(bytes, header.chunks_offset()) {
+            issues.push(Issue::error(error.to_string()));
+        }
+    }
+
+    if header.has_palette() {
+        issues.extend(lint_palette(bytes, &header, &runs));
+    }
+    issues
+}
+
+fn lint_palette(bytes: &[u8], header: &Header, runs: &[u8]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let palette = match embedded_palette(bytes, header) {
+        Ok(palette) => palette,
+        Err(error) => return vec![Issue::error(error.to_string())],
+    };
+
+    let stride = if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+    if header.version < 2 && !(bytes.len() - header.palette_offset()).is_multiple_of(header.stride()) && palette.format == header.format() {
+        issues.push(Issue::error(format!("the palette is {} bytes, not a whole number of {} byte colours", bytes.len() - header.palette_offset(), stride)));
+    }
+    if palette.format != header.format() {
+        issues.push(Issue::warning("the palette holds RGBA colours but the transparency flag is not set".to_string(), true));
+    }
+
+    let colors: Vec<&[u8]> = palette.colors.chunks_exact(stride).collect();
+    let mut used = vec![false; colors.len()];
+    for run in runs.chunks_exact(2) {
+        match used.get_mut(run[1] as usize) {
+            Some(used) => *used = true,
+            None => {
+                issues.push(Issue::error(format!("runs use colour {} but the palette has {}", run[1], colors.len())));
+                return issues;
+            }
+        }
+    }
+
+    let unused = used.iter().filter(|used| !**used).count();
+    if unused > 0 {
+        issues.push(Issue::warning(format!("{} of {} palette colours are unused", unused, colors.len()), true));
+    }
+    let duplicates = colors.iter().enumerate().filter(|(i, color)| colors[..*i].contains(color)).count();
+    if duplicates > 0 {
+        issues.push(Issue::warning(format!("{} palette colours are duplicates", duplicates), true));
+    }
+    if header.format() == PixelFormat::RGBA && colors.iter().all(|color| color[3] == 0xFF) {
+        issues.push(Issue::warning("the transparency flag is set but every colour is opaque".to_string(), true));
+    }
+
+    issues
+}
+
+/// The embedded palette, read as RGBA if [`legacy_rgba`] says so.
+fn embedded_palette(bytes: &[u8], header: &Header) -> Result<Palette, DecodeError> {
+    if legacy_rgba(bytes, header) {
+        return Ok(Palette { format: PixelFormat::RGBA, colors: bytes[header.palette_offset()..].to_vec() });
+    }
+    let colors = section(bytes, header.palette_offset()..header.chunks_offset())?;
+    Ok(Palette { format: header.format(), colors: colors.to_vec() })
+}
+
+/// Whether a version 1 file has an RGBA palette without the transparency flag, as older encoders
+/// wrote: the flags say RGB but only RGBA colours fit.
+pub(crate) fn legacy_rgba(bytes: &[u8], header: &Header) -> bool {
+    let length = bytes.len().saturating_sub(header.palette_offset());
+    header.version < 2 && header.has_palette() && header.format() == PixelFormat::RGB
+        && !length.is_multiple_of(3) && length.is_multiple_of(4)
+}
+
+/// Rewrite a single frame file with an embedded palette so that it only holds the colours it
+/// uses, with the right flags and no empty runs. The pixels and chunks are kept as they are.
+pub fn fix(bytes: impl AsRef<[u8]>) -> Result<Vec<u8>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if !header.has_palette() {
+        return Err(DecodeError::MissingPalette);
+    }
+    if header.is_animated() {
+        return Err(DecodeError::NotAnimated);
+    }
+
+    let palette = embedded_palette(bytes, &header)?;
+    let runs = data_runs(bytes, &header)?;
+    let mut pixels = expand_runs(&runs, header.size(), header.width as usize * header.height as usize, &palette)?;
+    if palette.format == PixelFormat::RGBA && pixels.chunks_exact(4).all(|pixel| pixel[3] == 0xFF) {
+        pixels = pixels.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+    }
+
+    let chunks = if header.version < 2 { Vec::new() } else { read_chunks(bytes, header.chunks_offset())? };
+    let chunks: Vec<([u8; 4], Vec<u8>)> = chunks.iter().map(|chunk| (chunk.kind, chunk.data.to_vec())).collect();
+
+    // Every colour comes from the palette, so encoding cannot fail.
+    let encoded = encode(header.width, header.height, &pixels, true, None).map_err(|_| DecodeError::MissingPalette)?;
+    let mut fixed = Vec::new();
+    write_image(&mut fixed, header.width, header.height, encoded.format, &encoded.indices, encoded.palette.as_ref().map(|palette| palette.colors.as_slice()), &chunks);
+    Ok(fixed)
+}
+
+#[test]
+fn test_lint() {
+    let clean = crate::encode(4, 1, [1, 2, 3, 1, 2, 3, 1, 2, 3, 4, 5, 6], true, None).unwrap().to_bytes();
+    assert!(lint(&clean).is_empty());
+
+    // An older encoder wrote RGBA colours without the transparency flag.
+    let strawberry = include_bytes!("../images/a-strawberry-dude-509249.pie");
+    let issues = lint(strawberry);
+    assert_eq!(vec!["the palette holds RGBA colours but the transparency flag is not set"], issues.iter().map(|issue| issue.message.as_str()).collect::<Vec<_>>());
+    let fixed = fix(strawberry).unwrap();
+    assert!(lint(&fixed).is_empty());
+    // Every colour is opaque, so the alpha channel goes too.
+    assert_eq!(PixelFormat::RGB, crate::decode(&fixed, None).unwrap().format);
+    assert!(fixed.len() < strawberry.len());
+
+    let mut unused = clean.clone();
+    unused.extend_from_slice(&[7, 8, 9, 1, 2, 3]);
+    unused[13] = 0;
+    let messages: Vec<String> = lint(&unused).into_iter().map(|issue| issue.message).collect();
+    assert_eq!(vec!["runs hold 3 pixels but the image is 4x1", "1 runs are empty", "2 of 4 palette colours are unused", "1 palette colours are duplicates"], messages);
+
+    let mut reserved = clean.clone();
+    reserved[8] |= 0x80;
+    assert_eq!(Severity::Error, lint(&reserved)[0].severity);
+}
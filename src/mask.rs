@@ -0,0 +1,126 @@
+//! Collision masks derived from transparency, so games can build hitboxes straight from sprites.
+use crate::{DecodedPIE, PixelFormat};
+
+/// One bit per pixel, set where the pixel is solid. Rows start on a byte boundary and bits run
+/// from the most significant bit of each byte.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BitMask {
+    pub width: u16,
+    pub height: u16,
+    pub bits: Vec<u8>,
+}
+
+/// An axis aligned rectangle in pixels.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl BitMask {
+    fn new(width: u16, height: u16, solid: impl Fn(usize) -> bool) -> BitMask {
+        let stride = (width as usize).div_ceil(8);
+        let mut bits = vec![0; stride * height as usize];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                if solid(y * width as usize + x) {
+                    bits[y * stride + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        BitMask { width, height, bits }
+    }
+
+    /// Whether the pixel at `x`, `y` is solid. Pixels outside the mask are not.
+    pub fn get(&self, x: u16, y: u16) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let stride = (self.width as usize).div_ceil(8);
+        self.bits[y as usize * stride + x as usize / 8] & (0x80 >> (x % 8)) > 0
+    }
+
+    /// Rectangles exactly covering the solid pixels. Horizontal spans are merged with identical
+    /// spans on the rows below, which keeps the list short for typical sprites.
+    pub fn rects(&self) -> Vec<Rect> {
+        let mut done: Vec<Rect> = Vec::new();
+        let mut open: Vec<Rect> = Vec::new();
+
+        for y in 0..self.height {
+            let mut spans = Vec::new();
+            let mut x = 0;
+            while x < self.width {
+                if self.get(x, y) {
+                    let start = x;
+                    while x < self.width && self.get(x, y) {
+                        x += 1;
+                    }
+                    spans.push((start, x - start));
+                } else {
+                    x += 1;
+                }
+            }
+
+            let mut next = Vec::with_capacity(spans.len());
+            for (x, width) in spans {
+                match open.iter().position(|rect| rect.x == x && rect.width == width) {
+                    Some(index) => {
+                        let mut rect = open.swap_remove(index);
+                        rect.height += 1;
+                        next.push(rect);
+                    }
+                    None => next.push(Rect { x, y, width, height: 1 }),
+                }
+            }
+            done.append(&mut open);
+            open = next;
+        }
+
+        done.append(&mut open);
+        done.sort_by_key(|rect| (rect.y, rect.x));
+        done
+    }
+}
+
+impl DecodedPIE {
+    /// A mask of the pixels with an alpha of at least `threshold`. Images without alpha are
+    /// entirely solid.
+    pub fn collision_mask(&self, threshold: u8) -> BitMask {
+        match self.format {
+            PixelFormat::RGBA => BitMask::new(self.width, self.height, |i| self.pixels[i * 4 + 3] >= threshold),
+            PixelFormat::RGB => BitMask::new(self.width, self.height, |_| true),
+        }
+    }
+
+    /// A mask of the pixels that are not the colour at `transparent` in the palette, for images
+    /// keyed on a colour rather than alpha.
+    pub fn collision_mask_keyed(&self, transparent: u8) -> BitMask {
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let start = transparent as usize * stride;
+        let key = self.palette.colors.get(start..start + stride);
+        BitMask::new(self.width, self.height, |i| Some(&self.pixels[i * stride..i * stride + stride]) != key)
+    }
+}
+
+#[test]
+fn test_collision_mask() {
+    let clear = [0, 0, 0, 0];
+    let solid = [0xFF, 0, 0, 0xFF];
+    let faint = [0xFF, 0, 0, 0x10];
+    let rows = [
+        [clear, solid, solid, clear, clear, clear, clear, clear, faint],
+        [clear, solid, solid, clear, clear, clear, clear, clear, solid],
+    ];
+    let pixels: Vec<u8> = rows.iter().flatten().flatten().copied().collect();
+    let image = crate::decode(crate::encode(9, 2, &pixels, true, None).unwrap().to_bytes(), None).unwrap();
+
+    let mask = image.collision_mask(0x80);
+    assert_eq!(vec![0b0110_0000, 0b0000_0000, 0b0110_0000, 0b1000_0000], mask.bits);
+    assert!(mask.get(1, 0) && !mask.get(8, 0) && mask.get(8, 1) && !mask.get(9, 1));
+    assert_eq!(vec![Rect { x: 1, y: 0, width: 2, height: 2 }, Rect { x: 8, y: 1, width: 1, height: 1 }], mask.rects());
+
+    // The first colour in the generated palette is the clear one.
+    assert_eq!(image.collision_mask(0x01).bits, image.collision_mask_keyed(0).bits);
+}
@@ -0,0 +1,131 @@
+//! Key/value text stored in a `META` chunk, for stamping files with things like a version or
+//! license without touching the image data.
+//!
+//! ```text
+//! META     u16    -- Key length (BE)
+//!          u8[]   -- Key as UTF-8
+//!          u32    -- Value length (BE)
+//!          u8[]   -- Value as UTF-8
+//!                 -- Repeated for every entry
+//! ```
+use crate::{chunk::{read_chunks, replace_chunk, Chunk}, DecodeError, Header};
+
+const CHUNK_METADATA: [u8; 4] = *b"META";
+
+/// Metadata entries in the order they were added. Keys are unique.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    /// Set `key` to `value`, replacing any existing value in place.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// Remove `key`, returning its value if it was set.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (key, value) in &self.entries {
+            bytes.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(value.as_bytes());
+        }
+        bytes
+    }
+
+    fn from_chunk(chunk: &Chunk) -> Result<Metadata, DecodeError> {
+        let mut metadata = Metadata::default();
+        let mut at = 0;
+        while at < chunk.data.len() {
+            let key_length = u16::from_be_bytes(chunk.take(&mut at, 2)?.try_into().unwrap()) as usize;
+            let key = text(chunk, &mut at, key_length)?;
+            let value_length = u32::from_be_bytes(chunk.take(&mut at, 4)?.try_into().unwrap()) as usize;
+            let value = text(chunk, &mut at, value_length)?;
+            metadata.set(&key, &value);
+        }
+        Ok(metadata)
+    }
+}
+
+fn text(chunk: &Chunk, at: &mut usize, length: usize) -> Result<String, DecodeError> {
+    let start = *at;
+    String::from_utf8(chunk.take(at, length)?.to_vec()).map_err(|_| chunk.invalid(start))
+}
+
+/// Read the metadata from the raw bytes of a PIE file. Files without any are empty.
+pub fn decode_metadata(bytes: impl AsRef<[u8]>) -> Result<Metadata, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(Metadata::default());
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    match chunks.iter().find(|chunk| chunk.kind == CHUNK_METADATA) {
+        Some(chunk) => Metadata::from_chunk(chunk),
+        None => Ok(Metadata::default()),
+    }
+}
+
+/// Replace the metadata in the raw bytes of a PIE file, returning the new file. The image data is
+/// copied as is rather than being re-encoded. Empty metadata removes the chunk entirely.
+pub fn set_metadata(bytes: impl AsRef<[u8]>, metadata: &Metadata) -> Result<Vec<u8>, DecodeError> {
+    let bytes = bytes.as_ref();
+    if metadata.is_empty() {
+        replace_chunk(bytes, CHUNK_METADATA, None)
+    } else {
+        replace_chunk(bytes, CHUNK_METADATA, Some(&metadata.to_bytes()))
+    }
+}
+
+#[test]
+fn test_metadata() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    assert!(decode_metadata(bytes).unwrap().is_empty());
+
+    let mut metadata = Metadata::default();
+    metadata.set("version", "1.2.0");
+    metadata.set("license", "CC0 ✓");
+    metadata.set("version", "1.3.0");
+
+    let stamped = set_metadata(bytes, &metadata).unwrap();
+    let decoded = decode_metadata(&stamped).unwrap();
+    assert_eq!(metadata, decoded);
+    assert_eq!(vec![("version", "1.3.0"), ("license", "CC0 ✓")], decoded.iter().collect::<Vec<_>>());
+    assert_eq!(crate::decode(bytes, None), crate::decode(&stamped, None));
+
+    metadata.remove("version");
+    metadata.remove("license");
+    let cleared = set_metadata(&stamped, &metadata).unwrap();
+    assert!(decode_metadata(&cleared).unwrap().is_empty());
+}
+
+#[test]
+fn test_invalid_metadata() {
+    let chunk = |data| Chunk { kind: CHUNK_METADATA, data, offset: 100 };
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_METADATA, offset: 102 }), Metadata::from_chunk(&chunk(&[0, 4, b'a'])));
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_METADATA, offset: 102 }), Metadata::from_chunk(&chunk(&[0, 1, 0xFF, 0, 0, 0, 0])));
+}
@@ -0,0 +1,79 @@
+//! Rewriting files as another version of the format without changing the image.
+use crate::{chunk::{read_chunks, write_chunk}, lint::legacy_rgba, section, DecodeError, Header, MigrateError, FLAG_ROWS, FLAG_SERPENTINE, FLAG_TRANSPARENCY, VERSION};
+
+/// Rewrite the raw bytes of a PIE file as `target_version`. Runs, palette and chunks are copied
+/// as they are, so nothing is decoded or re-encoded. Version 1 has no chunks and at most 65535
+/// runs, so files that need more cannot go back to it. Version 1 files with RGBA palettes but no
+/// transparency flag, as older encoders wrote, are given the flag.
+pub fn migrate(bytes: impl AsRef<[u8]>, target_version: u8) -> Result<Vec<u8>, MigrateError> {
+    let bytes = bytes.as_ref();
+    if !(1..=VERSION).contains(&target_version) {
+        return Err(MigrateError::UnsupportedVersion { target: target_version });
+    }
+
+    let mut header = Header::parse(bytes)?;
+    if legacy_rgba(bytes, &header) {
+        header.flags |= FLAG_TRANSPARENCY;
+        header.colors = ((bytes.len() - header.palette_offset()) / 4) as u16;
+    }
+    let runs = section(bytes, header.size()..header.palette_offset())?;
+    let palette = section(bytes, header.palette_offset()..header.chunks_offset())?;
+    let chunks = if header.version < 2 { Vec::new() } else { read_chunks(bytes, header.chunks_offset())? };
+
+    // Version 1 palettes run to the end of the file, so a stray byte would be lost when the
+    // palette is given a length.
+    if header.version < 2 && header.has_palette() && bytes.len() > header.chunks_offset() {
+        return Err(MigrateError::UnevenPalette { length: bytes.len() - header.palette_offset() });
+    }
+    if target_version < 2 {
+        let unsupported = header.flags & (FLAG_ROWS | FLAG_SERPENTINE);
+        if unsupported > 0 {
+            return Err(MigrateError::UnsupportedFlags { flags: unsupported });
+        }
+        if header.runs > u16::MAX as u32 {
+            return Err(MigrateError::TooManyRuns { runs: header.runs });
+        }
+        if let Some(chunk) = chunks.first() {
+            return Err(MigrateError::HasChunks { kind: chunk.kind });
+        }
+    }
+
+    header.version = target_version;
+    let mut migrated = Vec::with_capacity(bytes.len());
+    header.write(&mut migrated);
+    migrated.extend_from_slice(runs);
+    migrated.extend_from_slice(palette);
+    for chunk in &chunks {
+        write_chunk(&mut migrated, chunk.kind, chunk.data);
+    }
+
+    Ok(migrated)
+}
+
+impl From<DecodeError> for MigrateError {
+    fn from(error: DecodeError) -> MigrateError {
+        MigrateError::Decode(error)
+    }
+}
+
+#[test]
+fn test_migrate() {
+    let v1 = include_bytes!("../images/test_embedded_palette.pie");
+    let v2 = migrate(v1, 2).unwrap();
+    assert_eq!(2, v2[3]);
+    assert_eq!(crate::decode(v1, None).unwrap(), crate::decode(&v2, None).unwrap());
+    assert_eq!(v1.as_slice(), migrate(&v2, 1).unwrap());
+
+    let legacy = include_bytes!("../images/cubikism-023391.pie");
+    let fixed = migrate(legacy, 1).unwrap();
+    assert_eq!(legacy[8] | FLAG_TRANSPARENCY, fixed[8]);
+    assert!(crate::lint(&fixed).iter().all(|issue| issue.severity == crate::Severity::Warning));
+
+    let mut metadata = crate::Metadata::default();
+    metadata.set("author", "me");
+    let chunked = crate::set_metadata(&v2, &metadata).unwrap();
+    assert_eq!(Err(MigrateError::HasChunks { kind: *b"META" }), migrate(&chunked, 1));
+    assert_eq!(Err(MigrateError::UnsupportedVersion { target: 3 }), migrate(v1, 3));
+    assert_eq!(Err(MigrateError::UnevenPalette { length: 13 }), migrate([v1.as_slice(), &[0]].concat(), 2));
+    assert_eq!(Err(MigrateError::Decode(DecodeError::BadMagic { found: *b"PNG" })), migrate(b"PNG", 2));
+}
@@ -0,0 +1,33 @@
+//! PPM and PAM export. Nearly every image viewer and diff tool reads them, which makes them handy
+//! for checking what a decoder produced.
+use std::io::{self, Write};
+
+use crate::{DecodedPIE, PixelFormat};
+
+impl DecodedPIE {
+    /// Write the image as a binary PPM, which has no alpha channel so it is dropped.
+    pub fn to_ppm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.to_rgb8(None))
+    }
+
+    /// Write the image as a PAM, keeping the alpha channel of RGBA images.
+    pub fn to_pam(&self, mut writer: impl Write) -> io::Result<()> {
+        let (depth, tuple_type) = if self.format == PixelFormat::RGBA { (4, "RGB_ALPHA") } else { (3, "RGB") };
+        write!(writer, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n", self.width, self.height, depth, tuple_type)?;
+        writer.write_all(&self.pixels)
+    }
+}
+
+#[test]
+fn test_netpbm() {
+    let image = crate::decode(crate::encode(2, 1, [1, 2, 3, 4, 4, 5, 6, 0], true, None).unwrap().to_bytes(), None).unwrap();
+
+    let mut ppm = Vec::new();
+    image.to_ppm(&mut ppm).unwrap();
+    assert_eq!(b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".as_slice(), ppm);
+
+    let mut pam = Vec::new();
+    image.to_pam(&mut pam).unwrap();
+    assert_eq!(b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04\x04\x05\x06\x00".as_slice(), pam);
+}
@@ -0,0 +1,83 @@
+//! 9-slice insets for UI panels, stored in a `SLCE` chunk. The corners are drawn as they are,
+//! the edges stretch along one axis and the centre stretches along both.
+//!
+//! ```text
+//! SLCE     u16    -- Left inset in pixels (BE)
+//!          u16    -- Right inset in pixels (BE)
+//!          u16    -- Top inset in pixels (BE)
+//!          u16    -- Bottom inset in pixels (BE)
+//! ```
+use std::ops::Range;
+
+use crate::{chunk::{read_chunks, replace_chunk}, DecodeError, Header};
+
+pub(crate) const CHUNK_NINE_SLICE: [u8; 4] = *b"SLCE";
+
+/// How far in from each edge the stretchable centre of a panel starts, in pixels.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct NineSlice {
+    pub left: u16,
+    pub right: u16,
+    pub top: u16,
+    pub bottom: u16,
+}
+
+impl NineSlice {
+    /// Columns of an image `width` pixels wide that stretch horizontally. Empty if the insets
+    /// meet or overlap.
+    pub fn columns(&self, width: u16) -> Range<u16> {
+        self.left..width.saturating_sub(self.right).max(self.left)
+    }
+
+    /// Rows of an image `height` pixels tall that stretch vertically. Empty if the insets meet or
+    /// overlap.
+    pub fn rows(&self, height: u16) -> Range<u16> {
+        self.top..height.saturating_sub(self.bottom).max(self.top)
+    }
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        [self.left, self.right, self.top, self.bottom].iter().flat_map(|inset| inset.to_be_bytes()).collect()
+    }
+}
+
+/// Read the 9-slice insets from the raw bytes of a PIE file, if it has any.
+pub fn decode_nine_slice(bytes: impl AsRef<[u8]>) -> Result<Option<NineSlice>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(None);
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == CHUNK_NINE_SLICE) else {
+        return Ok(None);
+    };
+
+    let mut at = 0;
+    let mut inset = || chunk.take(&mut at, 2).map(|inset| u16::from_be_bytes([inset[0], inset[1]]));
+    Ok(Some(NineSlice { left: inset()?, right: inset()?, top: inset()?, bottom: inset()? }))
+}
+
+/// Replace the 9-slice insets in the raw bytes of a PIE file, returning the new file. None removes
+/// them.
+pub fn set_nine_slice(bytes: impl AsRef<[u8]>, nine_slice: Option<NineSlice>) -> Result<Vec<u8>, DecodeError> {
+    replace_chunk(bytes.as_ref(), CHUNK_NINE_SLICE, nine_slice.map(NineSlice::to_bytes).as_deref())
+}
+
+#[test]
+fn test_nine_slice() {
+    let nine_slice = NineSlice { left: 2, right: 3, top: 1, bottom: 1 };
+    assert_eq!(2..5, nine_slice.columns(8));
+    assert_eq!(1..7, nine_slice.rows(8));
+    assert!(nine_slice.columns(4).is_empty());
+
+    let pixels = [0x10, 0x20, 0x30].repeat(64);
+    let options = crate::EncodeOptions { embed_palette: true, nine_slice: Some(nine_slice), ..Default::default() };
+    let bytes = crate::encode_with_options(8, 8, &pixels, &options).unwrap();
+    assert_eq!(Some(nine_slice), decode_nine_slice(&bytes).unwrap());
+    assert_eq!(pixels.to_vec(), crate::decode(&bytes, None).unwrap().pixels);
+
+    let plain = include_bytes!("../images/test_embedded_palette.pie");
+    assert_eq!(None, decode_nine_slice(plain).unwrap());
+    assert_eq!(None, decode_nine_slice(set_nine_slice(&bytes, None).unwrap()).unwrap());
+}
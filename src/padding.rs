@@ -0,0 +1,89 @@
+//! Padding images out to sizes GPUs prefer. The size of the content before padding is stored in
+//! a `PADS` chunk, content always sits in the top left.
+//!
+//! ```text
+//! PADS     u16    -- Content width in pixels (BE)
+//!          u16    -- Content height in pixels (BE)
+//! ```
+use crate::{chunk::read_chunks, rle, rle_decode, DecodeError, EncodeError, Header};
+
+pub(crate) const CHUNK_PADDING: [u8; 4] = *b"PADS";
+
+/// What to pad the width and height of an image up to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Padding {
+    PowerOfTwo,
+    /// The next multiple of `n`, such as 4 for block compressed targets.
+    Multiple(u16),
+}
+
+impl Padding {
+    fn pad(self, size: u16) -> Option<u16> {
+        match self {
+            Padding::PowerOfTwo => size.checked_next_power_of_two(),
+            Padding::Multiple(n) => size.checked_next_multiple_of(n.max(1)),
+        }
+    }
+}
+
+/// Pad the runs of an image `width` by `height` with `index`, returning the padded size and runs.
+/// `colors` is the size of the palette, which `index` must be within.
+pub(crate) fn pad(width: u16, height: u16, runs: &[u8], padding: Padding, index: u8, colors: usize) -> Result<(u16, u16, Vec<u8>), EncodeError> {
+    let (Some(padded_width), Some(padded_height)) = (padding.pad(width), padding.pad(height)) else {
+        return Err(EncodeError::TooLarge { width: width as usize, height: height as usize });
+    };
+    if index as usize >= colors {
+        return Err(EncodeError::IndexOutOfPalette { index });
+    }
+
+    let indices = rle_decode(runs);
+    let mut padded = Vec::with_capacity(padded_width as usize * padded_height as usize);
+    for row in indices.chunks_exact(width as usize) {
+        padded.extend_from_slice(row);
+        padded.resize(padded.len() + (padded_width - width) as usize, index);
+    }
+    padded.resize(padded_width as usize * padded_height as usize, index);
+
+    Ok((padded_width, padded_height, rle(&padded, 255)))
+}
+
+/// Read the size of the content from the raw bytes of a padded PIE file. None if it was not
+/// padded.
+pub fn decode_content_size(bytes: impl AsRef<[u8]>) -> Result<Option<(u16, u16)>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(None);
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == CHUNK_PADDING) else {
+        return Ok(None);
+    };
+
+    let size = chunk.take(&mut 0, 4)?;
+    Ok(Some((u16::from_be_bytes([size[0], size[1]]), u16::from_be_bytes([size[2], size[3]]))))
+}
+
+#[test]
+fn test_padding() {
+    let pixels = [0x10, 0x20, 0x30].repeat(5 * 3);
+    let palette = crate::Palette { format: crate::PixelFormat::RGB, colors: vec![0, 0, 0, 0x10, 0x20, 0x30] };
+    let mut options = crate::EncodeOptions { embed_palette: true, palette: Some(&palette), padding: Some(Padding::PowerOfTwo), ..Default::default() };
+
+    let bytes = crate::encode_with_options(5, 3, &pixels, &options).unwrap();
+    let decoded = crate::decode(&bytes, None).unwrap();
+    assert_eq!((8, 4), (decoded.width, decoded.height));
+    assert_eq!(Some((5, 3)), decode_content_size(&bytes).unwrap());
+    assert_eq!(Some([0x10, 0x20, 0x30].as_slice()), decoded.color_at(4, 2));
+    assert_eq!(Some([0, 0, 0].as_slice()), decoded.color_at(5, 2));
+    assert_eq!(Some([0, 0, 0].as_slice()), decoded.color_at(0, 3));
+
+    options.padding = Some(Padding::Multiple(4));
+    let decoded = crate::decode(crate::encode_with_options(5, 3, &pixels, &options).unwrap(), None).unwrap();
+    assert_eq!((8, 4), (decoded.width, decoded.height));
+
+    options.padding_index = 2;
+    assert_eq!(Err(EncodeError::IndexOutOfPalette { index: 2 }), crate::encode_with_options(5, 3, &pixels, &options));
+    assert_eq!(Err(EncodeError::TooLarge { width: 40000, height: 1 }), pad(40000, 1, &[], Padding::PowerOfTwo, 0, 1));
+}
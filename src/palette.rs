@@ -0,0 +1,244 @@
+//! Building palettes from pixels, including median cut quantization for images with more colours
+//! than a palette can hold.
+use crate::{color_map::{pack, ColorMap}, EncodeError, Palette, PaletteError, PixelFormat};
+
+impl Palette {
+    /// Every colour used in `pixels`, in order of first appearance.
+    pub fn from_pixels(pixels: impl AsRef<[u8]>, format: PixelFormat) -> Result<Palette, PaletteError> {
+        span!(DEBUG, "build_palette", bytes = pixels.as_ref().len());
+        let (colors, _) = unique_colors(pixels.as_ref(), stride(format));
+        event!(DEBUG, colors = colors.len(), "built palette");
+        if colors.len() > 256 {
+            return Err(PaletteError::TooManyColors { count: colors.len() });
+        }
+
+        Ok(Palette { format, colors: colors.concat() })
+    }
+
+    /// Like [`Palette::from_pixels`], but if there are more than `max_colors` colours they are
+    /// reduced with median cut. `max_colors` is clamped to 1..=256. Use [`Palette::remap`] to
+    /// bring the pixels onto the reduced palette before encoding.
+    pub fn quantized(pixels: impl AsRef<[u8]>, format: PixelFormat, max_colors: usize) -> Palette {
+        let stride = stride(format);
+        let max_colors = max_colors.clamp(1, 256);
+        span!(DEBUG, "quantize", bytes = pixels.as_ref().len(), max_colors);
+        let (colors, counts) = unique_colors(pixels.as_ref(), stride);
+        event!(DEBUG, colors = colors.len(), "found colours");
+        if colors.len() <= max_colors {
+            return Palette { format, colors: colors.concat() };
+        }
+
+        let mut boxes = vec![colors.iter().zip(counts).map(|(color, count)| (color.as_slice(), count)).collect::<Vec<_>>()];
+        while boxes.len() < max_colors {
+            // Split the box covering the widest range of any channel at its weighted median.
+            let Some((index, channel, _)) = boxes.iter().enumerate()
+                .filter(|(_, entries)| entries.len() > 1)
+                .flat_map(|(index, entries)| (0..stride).map(move |channel| {
+                    let values = entries.iter().map(|(color, _)| color[channel]);
+                    (index, channel, values.clone().max().unwrap() - values.min().unwrap())
+                }))
+                .max_by_key(|(_, _, range)| *range) else { break };
+
+            let mut entries = boxes.swap_remove(index);
+            entries.sort_by_key(|(color, _)| color[channel]);
+
+            let total: u64 = entries.iter().map(|(_, count)| *count).sum();
+            let mut seen = 0;
+            let split = entries.iter().position(|(_, count)| {
+                seen += count;
+                seen * 2 >= total
+            }).unwrap().clamp(0, entries.len() - 2) + 1;
+
+            boxes.push(entries.split_off(split));
+            boxes.push(entries);
+        }
+
+        let colors = boxes.iter().flat_map(|entries| {
+            let total: u64 = entries.iter().map(|(_, count)| *count).sum();
+            (0..stride).map(move |channel| {
+                let sum: u64 = entries.iter().map(|(color, count)| color[channel] as u64 * count).sum();
+                ((sum + total / 2) / total) as u8
+            })
+        }).collect();
+
+        Palette { format, colors }
+    }
+
+    /// Index the pixels of each of `images` against this palette, keeping every colour it has at
+    /// the index it has and adding new ones after them in order of first appearance. Exporting a
+    /// changed frame of a sprite set against the palette of the last export so never moves the
+    /// indices the other frames use, which palette swaps and delta frames rely on. Start with an
+    /// empty palette of the images' format for the first export.
+    pub fn assign_stable(&self, images: &[impl AsRef<[u8]>]) -> Result<(Palette, Vec<Vec<u8>>), PaletteError> {
+        let stride = stride(self.format);
+        span!(DEBUG, "assign_stable", images = images.len());
+        let mut palette = self.clone();
+        let mut map = ColorMap::default();
+        for (index, color) in self.colors.chunks_exact(stride).enumerate() {
+            map.entry(pack(color)).or_insert(index);
+        }
+
+        let mut index_maps = Vec::with_capacity(images.len());
+        for image in images {
+            let indices = image.as_ref().chunks_exact(stride).map(|pixel| {
+                // Duplicate colours in the existing palette take up slots too, so the next index is
+                // where the colour is appended rather than how many colours are distinct.
+                let next = palette.colors.len() / stride;
+                *map.entry(pack(pixel)).or_insert_with(|| {
+                    palette.colors.extend_from_slice(pixel);
+                    next
+                }) as u8
+            }).collect();
+            index_maps.push(indices);
+        }
+
+        let count = palette.colors.len() / stride;
+        event!(DEBUG, added = count - self.colors.len() / stride, "assigned colours");
+        if count > 256 {
+            return Err(PaletteError::TooManyColors { count });
+        }
+        Ok((palette, index_maps))
+    }
+
+    /// Check that pixels of `format` can be encoded against this palette, which must hold colours
+    /// of the same format and no more than 256 of them.
+    pub(crate) fn check_encodable(&self, format: PixelFormat) -> Result<(), EncodeError> {
+        if self.format != format {
+            return Err(EncodeError::PaletteFormatMismatch { palette: self.format, pixels: format });
+        }
+        let count = self.colors.len() / stride(format);
+        if count > 256 {
+            return Err(EncodeError::TooManyColors { count });
+        }
+        Ok(())
+    }
+
+    /// Index of the palette colour closest to `color`.
+    pub fn nearest(&self, color: &[u8]) -> u8 {
+        self.colors.chunks_exact(stride(self.format)).enumerate()
+            .min_by_key(|(_, candidate)| candidate.iter().zip(color).map(|(a, b)| (*a as i32 - *b as i32).pow(2)).sum::<i32>())
+            .map_or(0, |(index, _)| index as u8)
+    }
+
+    /// Replace every pixel with the closest colour in the palette, so that pixels can be encoded
+    /// against a quantized palette.
+    pub fn remap(&self, pixels: &[u8]) -> Vec<u8> {
+        let stride = stride(self.format);
+        span!(DEBUG, "remap", bytes = pixels.len());
+        let mut cache: ColorMap<&[u8]> = ColorMap::default();
+        let mut remapped = Vec::with_capacity(pixels.len());
+
+        for pixel in pixels.chunks_exact(stride) {
+            let color = cache.entry(pack(pixel)).or_insert_with(|| {
+                let index = self.nearest(pixel) as usize * stride;
+                &self.colors[index..index + stride]
+            });
+            remapped.extend_from_slice(color);
+        }
+
+        remapped
+    }
+
+    /// Every colour used in `image`, as RGBA if it has an alpha channel and RGB otherwise.
+    #[cfg(feature = "image")]
+    pub fn from_image(image: &image::DynamicImage) -> Result<Palette, PaletteError> {
+        let (pixels, format) = image_pixels(image);
+        Palette::from_pixels(pixels, format)
+    }
+
+    /// Like [`Palette::from_image`], reducing the colours to `max_colors` with median cut.
+    #[cfg(feature = "image")]
+    pub fn from_image_quantized(image: &image::DynamicImage, max_colors: usize) -> Palette {
+        let (pixels, format) = image_pixels(image);
+        Palette::quantized(pixels, format, max_colors)
+    }
+}
+
+/// Index the pixels of each of `images` against `existing_palette`, keeping its colours where they
+/// are and appending new ones, see [`Palette::assign_stable`].
+pub fn assign_stable(images: &[impl AsRef<[u8]>], existing_palette: &Palette) -> Result<(Palette, Vec<Vec<u8>>), PaletteError> {
+    existing_palette.assign_stable(images)
+}
+
+#[cfg(feature = "image")]
+fn image_pixels(image: &image::DynamicImage) -> (Vec<u8>, PixelFormat) {
+    if image.color().has_alpha() {
+        (image.to_rgba8().into_raw(), PixelFormat::RGBA)
+    } else {
+        (image.to_rgb8().into_raw(), PixelFormat::RGB)
+    }
+}
+
+fn stride(format: PixelFormat) -> usize {
+    if format == PixelFormat::RGBA { 4 } else { 3 }
+}
+
+/// Unique colours in order of first appearance, and how many pixels use each.
+fn unique_colors(pixels: &[u8], stride: usize) -> (Vec<Vec<u8>>, Vec<u64>) {
+    let mut map = ColorMap::default();
+    let mut colors = Vec::new();
+    let mut counts = Vec::new();
+
+    for pixel in pixels.chunks_exact(stride) {
+        let index = *map.entry(pack(pixel)).or_insert_with(|| {
+            colors.push(pixel.to_vec());
+            counts.push(0);
+            colors.len() - 1
+        });
+        counts[index] += 1;
+    }
+
+    (colors, counts)
+}
+
+#[test]
+fn test_from_pixels() {
+    let pixels = [9, 9, 9, 1, 2, 3, 9, 9, 9];
+    assert_eq!(Ok(Palette { format: PixelFormat::RGB, colors: vec![9, 9, 9, 1, 2, 3] }), Palette::from_pixels(pixels, PixelFormat::RGB));
+
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0]).collect();
+    assert_eq!(Err(PaletteError::TooManyColors { count: 300 }), Palette::from_pixels(&many, PixelFormat::RGB));
+}
+
+#[test]
+fn test_assign_stable() {
+    let existing = Palette { format: PixelFormat::RGB, colors: vec![1, 1, 1, 2, 2, 2, 3, 3, 3] };
+    let frames = [[3, 3, 3, 9, 9, 9].to_vec(), [8, 8, 8, 1, 1, 1, 9, 9, 9].to_vec()];
+    let (palette, index_maps) = existing.assign_stable(&frames).unwrap();
+    assert_eq!(vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 9, 9, 9, 8, 8, 8], palette.colors);
+    assert_eq!(vec![vec![2, 3], vec![4, 0, 3]], index_maps);
+
+    // Assigning again against the result changes nothing.
+    assert_eq!(Ok((palette.clone(), index_maps)), palette.assign_stable(&frames));
+
+    // Duplicated entries keep their slots, so new colours go after them.
+    let duplicated = Palette { format: PixelFormat::RGB, colors: vec![0; 6] };
+    let (palette, index_maps) = assign_stable(&[[0, 0, 0, 9, 9, 9]], &duplicated).unwrap();
+    assert_eq!(vec![0, 0, 0, 0, 0, 0, 9, 9, 9], palette.colors);
+    assert_eq!(vec![vec![0, 2]], index_maps);
+
+    let empty = Palette { format: PixelFormat::RGB, colors: Vec::new() };
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0]).collect();
+    assert_eq!(Err(PaletteError::TooManyColors { count: 300 }), empty.assign_stable(&[many]));
+}
+
+#[test]
+fn test_quantized() {
+    let pixels = [0, 0, 0, 0, 0, 10, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0, 0xFF];
+    let palette = Palette::quantized(pixels, PixelFormat::RGB, 2);
+    assert_eq!(vec![0xFF, 0xF8, 0xFF, 0, 0, 5], palette.colors);
+    assert_eq!(vec![0, 0, 5, 0, 0, 5, 0xFF, 0xF8, 0xFF, 0xFF, 0xF8, 0xFF], palette.remap(&pixels));
+
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0, 0xFF]).collect();
+    let palette = Palette::quantized(&many, PixelFormat::RGBA, 256);
+    assert_eq!(256 * 4, palette.colors.len());
+    assert!(crate::encode(300, 1, palette.remap(&many), true, Some(&palette)).is_ok());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_from_image() {
+    let image = image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap());
+    assert_eq!(Ok(Palette { format: PixelFormat::RGB, colors: vec![1, 2, 3, 4, 5, 6] }), Palette::from_image(&image));
+    assert_eq!(3, Palette::from_image_quantized(&image, 1).colors.len());
+}
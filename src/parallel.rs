@@ -0,0 +1,112 @@
+//! Encoding large images across threads with rayon. Runs wrap from one row to the next, so rows
+//! are split into bands that are indexed in parallel, then run length encoded in one pass. The
+//! output is byte for byte the same as [`crate::encode`].
+use std::borrow::Cow;
+
+use rayon::prelude::*;
+
+use crate::{
+    color_map::{pack, ColorMap},
+    rle, EncodeError, EncodedPIE, Palette, PixelFormat,
+};
+
+/// Rows per band. Small enough to spread an atlas over every thread, large enough that the
+/// bookkeeping per band does not matter.
+const BAND_ROWS: usize = 64;
+
+/// Like [`crate::encode`], with the work of large images split across threads.
+pub fn encode_parallel(width: u16, height: u16, pixel_bytes: impl AsRef<[u8]>, embed_palette: bool, maybe_palette: Option<&Palette>) -> Result<EncodedPIE, EncodeError> {
+    let pixel_bytes = pixel_bytes.as_ref();
+    span!(DEBUG, "encode_parallel", width, height, embed_palette);
+    let pixel_count = width as usize * height as usize;
+    let chunk_size = if pixel_bytes.len() == pixel_count * 3 { 3 } else { 4 };
+    if pixel_bytes.len() != pixel_count * chunk_size {
+        return Err(EncodeError::wrong_pixel_count(pixel_count, pixel_bytes.len()));
+    }
+
+    let format = if chunk_size == 3 { PixelFormat::RGB } else { PixelFormat::RGBA };
+    let band_size = (width as usize * chunk_size * BAND_ROWS).max(chunk_size);
+
+    // Colours in order of first appearance within each band, merged in band order to give the
+    // same order as encoding on one thread.
+    let palette = match maybe_palette {
+        Some(palette) => Cow::Borrowed(palette),
+        None => {
+            span!(DEBUG, "build_palette");
+            let bands: Vec<Vec<u32>> = pixel_bytes.par_chunks(band_size).map(|band| {
+                let mut seen = ColorMap::default();
+                band.chunks(chunk_size).map(pack).filter(|color| seen.insert(*color, ()).is_none()).collect()
+            }).collect();
+
+            let mut seen = ColorMap::default();
+            let mut colors = Vec::new();
+            for color in bands.into_iter().flatten() {
+                if seen.insert(color, ()).is_none() {
+                    colors.extend_from_slice(&color.to_le_bytes()[..chunk_size]);
+                }
+            }
+            event!(DEBUG, colors = seen.len(), "built palette");
+            Cow::Owned(Palette { format, colors })
+        }
+    };
+
+    palette.check_encodable(format)?;
+
+    let mut map = ColorMap::default();
+    for (index, color) in palette.colors.chunks_exact(chunk_size).enumerate() {
+        map.insert(pack(color), index as u8);
+    }
+
+    let bands: Vec<Result<Vec<u8>, EncodeError>> = pixel_bytes.par_chunks(band_size).enumerate().map(|(band, pixels)| {
+        pixels.chunks(chunk_size).enumerate().map(|(i, chunk)| {
+            map.get(&pack(chunk)).copied().ok_or_else(|| EncodeError::color_not_in_palette(chunk, (band * band_size) / chunk_size + i, width))
+        }).collect()
+    }).collect();
+
+    let mut indices = Vec::with_capacity(pixel_count);
+    for band in bands {
+        indices.extend_from_slice(&band?);
+    }
+
+    let indices = rle(&indices, 255);
+    event!(DEBUG, runs = indices.len() / 2, "encoded");
+    Ok(EncodedPIE {
+        width, height, format, indices,
+        palette: embed_palette.then(|| palette.into_owned()),
+    })
+}
+
+#[test]
+fn test_encode_parallel() {
+    let (width, height) = (300, 200);
+    let pixels: Vec<u8> = (0..width * height).flat_map(|i| [(i / 500 % 7) as u8, (i / 3 % 5) as u8, 0, 0xFF]).collect();
+    let encoded = encode_parallel(width as u16, height as u16, &pixels, true, None).unwrap();
+    assert_eq!(crate::encode(width as u16, height as u16, &pixels, true, None).unwrap(), encoded);
+
+    let palette = encoded.palette.unwrap();
+    assert_eq!(crate::encode(width as u16, height as u16, &pixels, false, Some(&palette)), encode_parallel(width as u16, height as u16, &pixels, false, Some(&palette)));
+
+    let mut bad = pixels.clone();
+    bad[width * 150 * 4] = 0xAA;
+    assert_eq!(Err(EncodeError::ColorNotInPalette { color: [0xAA, 0, 0, 0xFF], x: 0, y: 150 }), encode_parallel(width as u16, height as u16, &bad, false, Some(&palette)));
+
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 6, found: 5 }), encode_parallel(2, 1, [0; 5], true, None));
+    assert_eq!(Err(EncodeError::WrongPixelCount { expected: 8, found: 9 }), encode_parallel(2, 1, [0; 9], true, None));
+}
+
+#[test]
+fn test_encode_parallel_too_many_colors() {
+    // Past 256 colours indices would wrap and alias earlier colours rather than fail.
+    let many: Vec<u8> = (0..300u32).flat_map(|i| [(i % 256) as u8, (i / 256) as u8, 0]).collect();
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), encode_parallel(30, 10, &many, true, None));
+    assert_eq!(crate::encode(30, 10, &many, true, None), encode_parallel(30, 10, &many, true, None));
+    let palette = Palette { format: PixelFormat::RGB, colors: many.clone() };
+    assert_eq!(Err(EncodeError::TooManyColors { count: 300 }), encode_parallel(30, 10, &many, false, Some(&palette)));
+    assert_eq!(crate::encode(30, 10, &many, false, Some(&palette)), encode_parallel(30, 10, &many, false, Some(&palette)));
+
+    // Palettes of the other format are rejected rather than read with the wrong stride.
+    let rgba = Palette { format: PixelFormat::RGBA, colors: vec![0; 200 * 4] };
+    let mismatch = Err(EncodeError::PaletteFormatMismatch { palette: PixelFormat::RGBA, pixels: PixelFormat::RGB });
+    assert_eq!(mismatch, encode_parallel(2, 1, [0; 6], false, Some(&rgba)));
+    assert_eq!(crate::encode(2, 1, [0; 6], false, Some(&rgba)), encode_parallel(2, 1, [0; 6], false, Some(&rgba)));
+}
@@ -0,0 +1,54 @@
+//! A palette on its own in a `.pip` file, to go with images that do not embed theirs.
+//!
+//! ```text
+//! magic    u8[3]  -- Magic bytes "PIP"
+//! flags    u8     -- 0b00000010 is whether colours are RGBA rather than RGB
+//! colors   u16    -- Number of colours (BE)
+//!          u8[]   -- Colours
+//! ```
+use std::fs;
+
+use crate::{bundle::{read_palette, write_palette}, read_file, section, DecodeError, EncodeError, Palette};
+
+const MAGIC: &[u8; 3] = b"PIP";
+
+impl Palette {
+    /// The palette as the bytes of a `.pip` file.
+    pub fn to_pip(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        write_palette(&mut bytes, self);
+        bytes
+    }
+
+    /// Parse the bytes of a `.pip` file.
+    pub fn from_pip(bytes: impl AsRef<[u8]>) -> Result<Palette, DecodeError> {
+        let bytes = bytes.as_ref();
+        let magic = section(bytes, 0..3)?;
+        if magic != MAGIC {
+            return Err(DecodeError::BadMagic { found: [magic[0], magic[1], magic[2]] });
+        }
+        read_palette(bytes, &mut 3)
+    }
+
+    /// Read a `.pip` file.
+    pub fn read_pip(path: &str) -> Result<Palette, DecodeError> {
+        Palette::from_pip(read_file(path)?).map_err(|error| error.in_file(path))
+    }
+
+    /// Write a `.pip` file.
+    pub fn write_pip(&self, path: &str) -> Result<(), EncodeError> {
+        fs::write(path, self.to_pip()).map_err(|error| EncodeError::Io(error.kind()).in_file(path))
+    }
+}
+
+#[test]
+fn test_pip() {
+    let palette = Palette { format: crate::PixelFormat::RGBA, colors: vec![1, 2, 3, 4, 5, 6, 7, 8] };
+    let bytes = palette.to_pip();
+    assert_eq!(b"PIP\x02\x00\x02\x01\x02\x03\x04\x05\x06\x07\x08", bytes.as_slice());
+    assert_eq!(Ok(palette), Palette::from_pip(&bytes));
+
+    assert_eq!(Err(DecodeError::BadMagic { found: *b"PIB" }), Palette::from_pip(b"PIB\x00\x00\x00"));
+    assert_eq!(Err(DecodeError::Truncated { expected: 14, found: 13 }), Palette::from_pip(&bytes[..13]));
+    assert_eq!(Err(DecodeError::InvalidPalette { offset: 4 }), Palette::from_pip(b"PIP\x00\x01\x01"));
+}
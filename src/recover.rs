@@ -0,0 +1,126 @@
+//! Decoding what can be saved of files that were cut short, such as by a failed download or a
+//! writer that crashed part way through.
+use std::borrow::Cow;
+
+use crate::{rle, rows::read_rows, scan::reverse_odd_rows, DecodeError, DecodedPIE, Header, Palette};
+
+/// How much of the image [`decode_partial`] could decode.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Recovered {
+    /// Pixels decoded from the file, from the top left in the order they are stored.
+    pub pixels: usize,
+    /// Pixels in the image, including those filled in.
+    pub total: usize,
+}
+
+impl Recovered {
+    /// Whether every pixel was decoded from the file.
+    pub fn is_complete(&self) -> bool {
+        self.pixels == self.total
+    }
+}
+
+/// Like [`crate::decode`], but decode as many whole runs as the file has and fill the rest of the
+/// image with `fill`, of which RGB images use the first three bytes. Runs using colours the palette
+/// lacks end the recovered pixels too. The header must be whole.
+/// * `palette` - Used if the file's palette is not embedded or was cut short. Without it, whatever
+///   colours of the embedded palette are left are used.
+pub fn decode_partial(bytes: impl AsRef<[u8]>, maybe_palette: Option<&Palette>, fill: [u8; 4]) -> Result<(DecodedPIE, Recovered), DecodeError> {
+    let bytes = bytes.as_ref();
+    span!(DEBUG, "decode_partial", bytes = bytes.len());
+    let header = Header::parse(bytes)?;
+
+    // Version 1 palettes run to the end of the file, so one that was cut short looks smaller, or
+    // empty if the runs were cut short too.
+    let whole_palette = header.colors > 0 && bytes.len() >= header.chunks_offset();
+    let palette = match maybe_palette {
+        Some(palette) if !header.has_palette() || !whole_palette => palette.clone(),
+        _ if header.has_palette() => {
+            let end = header.chunks_offset().min(bytes.len());
+            let colors = bytes.get(header.palette_offset()..end).unwrap_or_default();
+            Palette { format: header.format(), colors: colors[..colors.len() / header.stride() * header.stride()].to_vec() }
+        }
+        _ => return Err(DecodeError::MissingPalette),
+    };
+
+    let stride = header.stride();
+    let total = header.width as usize * header.height as usize;
+    let data = bytes.get(header.size()..header.palette_offset().min(bytes.len())).unwrap_or_default();
+    let runs = if header.has_row_modes() {
+        // Only whole rows are kept, so the error is where recovery stops.
+        let mut indices = Vec::with_capacity(total);
+        let _ = read_rows(data, header.size(), header.width as usize, header.height as usize, &mut indices);
+        Cow::Owned(rle(&indices, 255))
+    } else {
+        Cow::Borrowed(data)
+    };
+    let mut pixels = Vec::with_capacity(total * stride);
+
+    for run in runs.chunks_exact(2) {
+        let Some(color) = palette.colors.get(run[1] as usize * stride..(run[1] as usize + 1) * stride) else { break };
+        let count = (run[0] as usize).min(total - pixels.len() / stride);
+        for _ in 0..count {
+            pixels.extend_from_slice(color);
+        }
+    }
+
+    let recovered = Recovered { pixels: pixels.len() / stride, total };
+    pixels.resize(total * stride, 0);
+    for pixel in pixels[recovered.pixels * stride..].chunks_exact_mut(stride) {
+        pixel.copy_from_slice(&fill[..stride]);
+    }
+    if header.is_serpentine() {
+        reverse_odd_rows(&mut pixels, header.width as usize, stride);
+    }
+    event!(DEBUG, recovered = recovered.pixels, total, "recovered pixels");
+
+    Ok((DecodedPIE { width: header.width, height: header.height, format: palette.format, pixels, palette, chunks: Vec::new(), comments: Vec::new(), density: None }, recovered))
+}
+
+#[test]
+fn test_decode_partial() {
+    use crate::{decode_header, PixelFormat};
+
+    let palette = Palette { format: PixelFormat::RGB, colors: vec![1, 1, 1, 2, 2, 2] };
+    let pixels = [[1u8; 3], [2; 3], [2; 3], [1; 3], [1; 3], [1; 3]].concat();
+    let external = crate::encode(3, 2, &pixels, false, Some(&palette)).unwrap().to_bytes();
+
+    let (decoded, recovered) = decode_partial(&external, Some(&palette), [0; 4]).unwrap();
+    assert_eq!(crate::decode(&external, Some(&palette)).unwrap(), decoded);
+    assert!(recovered.is_complete());
+
+    // Runs of 1, 2 and 3 pixels, the last cut off.
+    let (decoded, recovered) = decode_partial(&external[..external.len() - 1], Some(&palette), [9; 4]).unwrap();
+    assert_eq!(Recovered { pixels: 3, total: 6 }, recovered);
+    assert_eq!([&pixels[..9], &[9; 9]].concat(), decoded.pixels);
+    assert_eq!(Err(DecodeError::MissingPalette), decode_partial(&external, None, [0; 4]).map(|_| ()));
+
+    // A version 1 file cut off in its palette falls back to the external one.
+    let embedded = crate::encode(3, 2, &pixels, true, None).unwrap().to_bytes();
+    let (decoded, recovered) = decode_partial(&embedded[..embedded.len() - 4], Some(&palette), [0; 4]).unwrap();
+    assert!(recovered.is_complete());
+    assert_eq!(pixels.to_vec(), decoded.pixels);
+
+    // Without one, the colours that are left are used until a run needs one that is not.
+    let (decoded, recovered) = decode_partial(&embedded[..embedded.len() - 3], None, [0; 4]).unwrap();
+    assert_eq!(Recovered { pixels: 1, total: 6 }, recovered);
+    assert_eq!([&pixels[..3], &[0; 15]].concat(), decoded.pixels);
+
+    // Files with per row modes recover whole rows.
+    let options = crate::EncodeOptions { row_modes: true, ..Default::default() };
+    let rows = crate::encode_with_options(3, 2, &pixels, &options).unwrap();
+    let (decoded, recovered) = decode_partial(&rows[..rows.len() - 1], Some(&palette), [0; 4]).unwrap();
+    assert_eq!(Recovered { pixels: 3, total: 6 }, recovered);
+    assert_eq!([&pixels[..9], &[0; 9]].concat(), decoded.pixels);
+
+    // Serpentine files fill in from the end of the last row they reach, right to left.
+    let options = crate::EncodeOptions { serpentine: true, ..Default::default() };
+    let pixels = [[1u8; 3], [2; 3], [2; 3], [1; 3], [1; 3], [2; 3]].concat();
+    let serpentine = crate::encode_with_options(3, 2, &pixels, &options).unwrap();
+    assert!(decode_header(&serpentine).unwrap().is_serpentine());
+    let (decoded, recovered) = decode_partial(&serpentine[..serpentine.len() - 2], Some(&palette), [0; 4]).unwrap();
+    assert_eq!(Recovered { pixels: 4, total: 6 }, recovered);
+    assert_eq!([&pixels[..9], &[0; 6], &pixels[15..]].concat(), decoded.pixels);
+
+    assert_eq!(Err(DecodeError::Truncated { expected: 11, found: 5 }), decode_partial(&embedded[..5], None, [0; 4]).map(|_| ()));
+}
@@ -0,0 +1,132 @@
+//! Resolving external palettes at runtime. Files say which palette they need with a `palette`
+//! metadata entry holding its name or [`Palette::fingerprint`], see [`PALETTE_KEY`].
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{decode, decode_metadata, read_file, DecodeError, DecodedPIE, Header, Palette, PaletteBundle, PixelFormat};
+
+/// Metadata key naming the external palette of a file.
+pub const PALETTE_KEY: &str = "palette";
+
+/// Palettes by name, for decoding files with external palettes without passing the palette to
+/// every call. Every palette can also be found by its fingerprint in hex.
+#[derive(Debug, Default, Clone)]
+pub struct PaletteRegistry {
+    names: HashMap<String, Palette>,
+    fingerprints: HashMap<String, String>,
+    default: Option<String>,
+}
+
+impl PaletteRegistry {
+    pub fn new() -> PaletteRegistry {
+        PaletteRegistry::default()
+    }
+
+    /// Add `palette` as `name`, replacing any palette with that name.
+    pub fn insert(&mut self, name: &str, palette: Palette) {
+        if let Some(previous) = self.names.get(name) {
+            let fingerprint = format!("{:016x}", previous.fingerprint());
+            if self.fingerprints.get(&fingerprint).is_some_and(|owner| owner == name) {
+                self.fingerprints.remove(&fingerprint);
+            }
+        }
+        self.fingerprints.insert(format!("{:016x}", palette.fingerprint()), name.to_string());
+        self.names.insert(name.to_string(), palette);
+    }
+
+    /// The palette for files that do not name theirs.
+    pub fn set_default(&mut self, name: &str) {
+        self.default = Some(name.to_string());
+    }
+
+    /// The palette called `key`, or with `key` as its fingerprint in hex.
+    pub fn get(&self, key: &str) -> Option<&Palette> {
+        self.names.get(key).or_else(|| self.fingerprints.get(key).and_then(|name| self.names.get(name)))
+    }
+
+    /// Add every `.pip` file in `dir`, named after the file, and every palette of every `.piep`
+    /// file, named as in the bundle.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), DecodeError> {
+        let dir = dir.as_ref();
+        let entries = fs::read_dir(dir).map_err(|error| DecodeError::Io(error.kind()).in_file(&dir.to_string_lossy()))?;
+        let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+        paths.sort();
+
+        for path in paths {
+            let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else { continue };
+            let name = path.to_string_lossy();
+            if extension == "pip" {
+                self.insert(&stem.to_string_lossy(), Palette::read_pip(&name)?);
+            } else if extension == "piep" {
+                for (name, palette) in PaletteBundle::read(&name)?.iter() {
+                    self.insert(name, palette.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The palette to decode the raw bytes of a PIE file with: None if it is embedded, otherwise
+    /// the one its metadata names or the default.
+    pub fn resolve(&self, bytes: impl AsRef<[u8]>) -> Result<Option<&Palette>, DecodeError> {
+        let bytes = bytes.as_ref();
+        if Header::parse(bytes)?.has_palette() {
+            return Ok(None);
+        }
+
+        let metadata = decode_metadata(bytes)?;
+        let Some(name) = metadata.get(PALETTE_KEY).or(self.default.as_deref()) else {
+            return Err(DecodeError::MissingPalette);
+        };
+        self.get(name).map(Some).ok_or_else(|| DecodeError::UnknownPalette { name: name.to_string() })
+    }
+}
+
+impl Palette {
+    /// A hash of the format and colours, to refer to a palette without a name. FNV-1a, so it is
+    /// the same everywhere.
+    pub fn fingerprint(&self) -> u64 {
+        let format = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        [format].iter().chain(&self.colors).fold(0xCBF29CE484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001B3))
+    }
+}
+
+/// Read a PIE file, finding its palette in `registry` if it is not embedded.
+pub fn read_with_registry(path: &str, registry: &PaletteRegistry) -> Result<DecodedPIE, DecodeError> {
+    span!(INFO, "read_with_registry", path);
+    let bytes = read_file(path)?;
+    let palette = registry.resolve(&bytes).map_err(|error| error.in_file(path))?;
+    decode(&bytes, palette).map_err(|error| error.in_file(path))
+}
+
+#[test]
+fn test_registry() {
+    let red = Palette { format: PixelFormat::RGB, colors: vec![0xFF, 0, 0, 0x80, 0, 0] };
+    let blue = Palette { format: PixelFormat::RGB, colors: vec![0, 0, 0xFF, 0, 0, 0x80] };
+    let mut registry = PaletteRegistry::new();
+    registry.insert("red", red.clone());
+    registry.insert("blue", blue.clone());
+    assert_eq!(Some(&blue), registry.get(&format!("{:016x}", blue.fingerprint())));
+
+    // Replacing a palette forgets the fingerprint of the old one.
+    let green = Palette { format: PixelFormat::RGB, colors: vec![0, 0xFF, 0] };
+    let mut replaced = registry.clone();
+    replaced.insert("blue", green.clone());
+    assert_eq!(None, replaced.get(&format!("{:016x}", blue.fingerprint())));
+    assert_eq!(Some(&green), replaced.get(&format!("{:016x}", green.fingerprint())));
+
+    let bytes = crate::encode(2, 1, [0xFF, 0, 0, 0x80, 0, 0], false, Some(&red)).unwrap().to_bytes();
+    assert_eq!(Err(DecodeError::MissingPalette), registry.resolve(&bytes));
+    registry.set_default("red");
+    assert_eq!(Ok(Some(&red)), registry.resolve(&bytes));
+
+    let mut metadata = crate::Metadata::default();
+    metadata.set(PALETTE_KEY, "blue");
+    let named = crate::set_metadata(&bytes, &metadata).unwrap();
+    assert_eq!(vec![0, 0, 0xFF, 0, 0, 0x80], decode(&named, registry.resolve(&named).unwrap()).unwrap().pixels);
+    metadata.set(PALETTE_KEY, "green");
+    let unknown = crate::set_metadata(&bytes, &metadata).unwrap();
+    assert_eq!(Err(DecodeError::UnknownPalette { name: "green".to_string() }), registry.resolve(&unknown));
+
+    let embedded = crate::encode(2, 1, [1, 2, 3, 4, 5, 6], true, None).unwrap().to_bytes();
+    assert_eq!(Ok(None), registry.resolve(&embedded));
+}
@@ -0,0 +1,117 @@
+//! Per row modes, where each row of the data section is stored either as runs or as raw indices,
+//! whichever is smaller, so images mixing flat areas and dithered noise do not pay for runs of one
+//! pixel everywhere. Files with them set the row modes flag and their header counts the bytes of
+//! the data section rather than runs.
+//!
+//! ```text
+//! mode     u8     -- 0 for runs, 1 for raw indices
+//! row      u8[]   -- Runs adding up to the width, or one index per pixel
+//!                 -- Repeated for every row
+//! ```
+use std::borrow::Cow;
+
+use crate::{rle, rle_decode, rle_into, scan::reverse_odd_rows, section, DecodeError, Header};
+
+const ROW_RUNS: u8 = 0;
+const ROW_RAW: u8 = 1;
+
+/// The data section for `runs` of an image `width` pixels wide, choosing the smaller mode for
+/// each row. Runs win ties.
+pub(crate) fn encode_rows(width: usize, runs: &[u8]) -> Vec<u8> {
+    span!(DEBUG, "encode_rows", width);
+    let indices = rle_decode(runs);
+    let mut data = Vec::with_capacity(indices.len() + indices.len() / width.max(1));
+    let mut row_runs = Vec::with_capacity(width * 2);
+
+    for row in indices.chunks(width.max(1)) {
+        row_runs.clear();
+        rle_into(row, 255, &mut row_runs);
+        if row_runs.len() <= row.len() {
+            data.push(ROW_RUNS);
+            data.extend_from_slice(&row_runs);
+        } else {
+            data.push(ROW_RAW);
+            data.extend_from_slice(row);
+        }
+    }
+
+    event!(DEBUG, runs = runs.len(), rows = data.len(), "encoded rows");
+    data
+}
+
+/// Append the indices of every whole row in `data` to `indices`, stopping at the first row that
+/// cannot be read. `offset` is where `data` starts in the file, for errors.
+pub(crate) fn read_rows(data: &[u8], offset: usize, width: usize, height: usize, indices: &mut Vec<u8>) -> Result<(), DecodeError> {
+    let mut at = 0;
+    for row in 0..height {
+        let row_offset = offset + at;
+        let invalid = || DecodeError::InvalidRow { row, offset: row_offset };
+        let mode = *data.get(at).ok_or_else(invalid)?;
+        at += 1;
+
+        match mode {
+            ROW_RUNS => {
+                let start = indices.len();
+                while indices.len() - start < width {
+                    let Some(run) = data.get(at..at + 2) else { break };
+                    indices.extend(std::iter::repeat_n(run[1], run[0] as usize));
+                    at += 2;
+                }
+                if indices.len() - start != width {
+                    indices.truncate(start);
+                    return Err(invalid());
+                }
+            }
+            ROW_RAW => {
+                indices.extend_from_slice(data.get(at..at + width).ok_or_else(invalid)?);
+                at += width;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    if at != data.len() {
+        return Err(DecodeError::InvalidRow { row: height, offset: offset + at });
+    }
+    Ok(())
+}
+
+/// The runs of the data section in the normal order, converted from rows if the file has per row
+/// modes.
+pub(crate) fn data_runs<'a>(bytes: &'a [u8], header: &Header) -> Result<Cow<'a, [u8]>, DecodeError> {
+    let data = section(bytes, header.data_range())?;
+    if !header.has_row_modes() && !header.is_serpentine() {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let mut indices = Vec::with_capacity(header.width as usize * header.height as usize);
+    if header.has_row_modes() {
+        read_rows(data, header.size(), header.width as usize, header.height as usize, &mut indices)?;
+    } else {
+        indices = rle_decode(data);
+    }
+    if header.is_serpentine() {
+        reverse_odd_rows(&mut indices, header.width as usize, 1);
+    }
+    Ok(Cow::Owned(rle(&indices, 255)))
+}
+
+#[test]
+fn test_rows() {
+    // A flat row, a noisy row and a row that costs the same either way.
+    let indices = [[0u8; 4], [0, 1, 0, 1], [2, 2, 3, 3]].concat();
+    let data = encode_rows(4, &rle(&indices, 255));
+    assert_eq!(vec![0, 4, 0, 1, 0, 1, 0, 1, 0, 2, 2, 2, 3], data);
+
+    let mut decoded = Vec::new();
+    assert_eq!(Ok(()), read_rows(&data, 15, 4, 3, &mut decoded));
+    assert_eq!(indices.to_vec(), decoded);
+
+    // Only whole rows are kept when one cannot be read.
+    let mut decoded = Vec::new();
+    assert_eq!(Err(DecodeError::InvalidRow { row: 2, offset: 23 }), read_rows(&data[..data.len() - 1], 15, 4, 3, &mut decoded));
+    assert_eq!(indices[..8].to_vec(), decoded);
+    assert_eq!(Err(DecodeError::InvalidRow { row: 0, offset: 15 }), read_rows(&[2], 15, 4, 1, &mut Vec::new()));
+    assert_eq!(Err(DecodeError::InvalidRow { row: 0, offset: 15 }), read_rows(&[0, 5, 0], 15, 4, 1, &mut Vec::new()));
+    assert_eq!(Err(DecodeError::InvalidRow { row: 1, offset: 18 }), read_rows(&[0, 4, 0, 9], 15, 4, 1, &mut Vec::new()));
+}
@@ -0,0 +1,69 @@
+//! The order pixels are stored in. Serpentine files reverse every other row, so runs carry on
+//! across row ends, which suits symmetric sprites and banded backgrounds.
+use crate::{rle, rle_decode, rows::encode_rows, EncodeOptions, FLAG_ROWS, FLAG_SERPENTINE};
+
+/// Reverse the pixels of every odd row, `width` pixels of `stride` bytes each. This both makes
+/// and undoes serpentine order. Images without width have no rows to reverse.
+pub(crate) fn reverse_odd_rows(pixels: &mut [u8], width: usize, stride: usize) {
+    if width == 0 {
+        return;
+    }
+    for row in pixels.chunks_mut(width * stride).skip(1).step_by(2) {
+        let pixels = row.len() / stride;
+        for i in 0..pixels / 2 {
+            let (left, right) = row.split_at_mut((pixels - 1 - i) * stride);
+            left[i * stride..(i + 1) * stride].swap_with_slice(&mut right[..stride]);
+        }
+    }
+}
+
+/// The data section for `runs` of an image `width` pixels wide with the flags describing it,
+/// with per row modes if `options` ask for them. With `options.serpentine` the smaller of the
+/// two orders is kept, the normal one if they are the same size.
+pub(crate) fn encode_data(width: usize, runs: &[u8], options: &EncodeOptions) -> (u8, Vec<u8>) {
+    let (mut flags, mut data) = if options.row_modes { (FLAG_ROWS, encode_rows(width, runs)) } else { (0, runs.to_vec()) };
+
+    if options.serpentine {
+        span!(DEBUG, "serpentine", width);
+        let mut indices = rle_decode(runs);
+        reverse_odd_rows(&mut indices, width, 1);
+        let serpentine = rle(&indices, 255);
+        let serpentine = if options.row_modes { encode_rows(width, &serpentine) } else { serpentine };
+        event!(DEBUG, normal = data.len(), serpentine = serpentine.len(), "compared scan orders");
+        if serpentine.len() < data.len() {
+            (flags, data) = (flags | FLAG_SERPENTINE, serpentine);
+        }
+    }
+
+    (flags, data)
+}
+
+#[test]
+fn test_reverse_odd_rows() {
+    let mut pixels = vec![1, 2, 3, 4, 5, 6, 7, 8, 9];
+    reverse_odd_rows(&mut pixels, 3, 1);
+    assert_eq!(vec![1, 2, 3, 6, 5, 4, 7, 8, 9], pixels);
+
+    let mut pixels = vec![1, 1, 2, 2, 3, 3, 4, 4, 5, 5];
+    reverse_odd_rows(&mut pixels, 2, 2);
+    assert_eq!(vec![1, 1, 2, 2, 4, 4, 3, 3, 5, 5], pixels);
+
+    let mut empty = Vec::new();
+    reverse_odd_rows(&mut empty, 0, 1);
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn test_serpentine_zero_width() {
+    use crate::{decode, encode_with_options, Header, FLAG_PALETTE};
+
+    let options = EncodeOptions { serpentine: true, embed_palette: true, ..Default::default() };
+    let plain = EncodeOptions { embed_palette: true, ..Default::default() };
+    assert_eq!(encode_with_options(0, 3, [], &plain), encode_with_options(0, 3, [], &options));
+
+    // A crafted file claiming serpentine order with no width decodes to no pixels.
+    let mut bytes = Vec::new();
+    Header { version: 2, width: 0, height: 3, flags: FLAG_SERPENTINE | FLAG_PALETTE, colors: 1, runs: 0 }.write(&mut bytes);
+    bytes.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(Vec::<u8>::new(), decode(&bytes, None).unwrap().pixels);
+}
@@ -0,0 +1,155 @@
+//! Loading PIE files and their external palettes from wherever a game keeps its assets, such as a
+//! directory, an in-memory map or a packed zip bundle, through one [`AssetSource`].
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use crate::{decode, decode_metadata, DecodeError, DecodedPIE, Header, Palette, PALETTE_KEY};
+
+/// Somewhere assets can be loaded from by path. Paths are relative and separated by `/`
+/// whatever the platform.
+pub trait AssetSource {
+    /// The bytes of the asset at `path`. Missing assets fail with
+    /// `DecodeError::Io(ErrorKind::NotFound)` in the file.
+    fn load(&self, path: &str) -> Result<Vec<u8>, DecodeError>;
+}
+
+/// Assets in a directory on disk.
+#[derive(Debug, Clone)]
+pub struct DirSource {
+    root: PathBuf,
+}
+
+impl DirSource {
+    pub fn new(root: impl Into<PathBuf>) -> DirSource {
+        DirSource { root: root.into() }
+    }
+}
+
+impl AssetSource for DirSource {
+    fn load(&self, path: &str) -> Result<Vec<u8>, DecodeError> {
+        span!(DEBUG, "load", path);
+        fs::read(self.root.join(path)).map_err(|error| DecodeError::Io(error.kind()).in_file(path))
+    }
+}
+
+/// Assets held in memory by path, such as ones embedded with `include_bytes!`.
+impl AssetSource for HashMap<String, Vec<u8>> {
+    fn load(&self, path: &str) -> Result<Vec<u8>, DecodeError> {
+        self.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+/// Assets in a zip archive held in memory. Entries must be stored or deflated.
+#[cfg(feature = "zip")]
+#[derive(Debug, Clone)]
+pub struct ZipSource {
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "zip")]
+impl ZipSource {
+    pub fn new(bytes: impl Into<Vec<u8>>) -> ZipSource {
+        ZipSource { bytes: bytes.into() }
+    }
+}
+
+#[cfg(feature = "zip")]
+impl AssetSource for ZipSource {
+    fn load(&self, path: &str) -> Result<Vec<u8>, DecodeError> {
+        span!(DEBUG, "load", path);
+        crate::zip::read_entry(&self.bytes, path, |offset| DecodeError::InvalidArchive { offset })
+            .map_err(|error| error.in_file(path))?
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+fn not_found(path: &str) -> DecodeError {
+    DecodeError::Io(std::io::ErrorKind::NotFound).in_file(path)
+}
+
+/// Like [`crate::read`], but load the file at `path` from `source`. Without `palette`, files with
+/// an external one load it from `source` too, from the path in their `palette` metadata entry
+/// relative to the file, see [`PALETTE_KEY`]. Paths without an extension are .pip files, and .act
+/// files are read too.
+pub fn read_from(source: &impl AssetSource, path: &str, palette: Option<&Palette>) -> Result<DecodedPIE, DecodeError> {
+    span!(INFO, "read_from", path);
+    let bytes = source.load(path)?;
+    if palette.is_some() || Header::parse(&bytes).map_err(|error| error.in_file(path))?.has_palette() {
+        return decode(&bytes, palette).map_err(|error| error.in_file(path));
+    }
+
+    let metadata = decode_metadata(&bytes).map_err(|error| error.in_file(path))?;
+    let name = metadata.get(PALETTE_KEY).ok_or_else(|| DecodeError::MissingPalette.in_file(path))?;
+    let palette_path = relative_path(path, name);
+    let palette_bytes = source.load(&palette_path)?;
+    let palette = if palette_path.ends_with(".act") { Palette::from_act(palette_bytes) } else { Palette::from_pip(palette_bytes) };
+    let palette = palette.map_err(|error| error.in_file(&palette_path))?;
+    decode(&bytes, Some(&palette)).map_err(|error| error.in_file(path))
+}
+
+/// `name` resolved against the directory of `path`, with `.` and `..` segments removed and .pip
+/// added if it has no extension.
+fn relative_path(path: &str, name: &str) -> String {
+    let mut segments: Vec<&str> = path.split('/').collect();
+    segments.pop();
+    for segment in name.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut resolved = segments.join("/");
+    if !segments.last().is_some_and(|file| file.contains('.')) {
+        resolved.push_str(".pip");
+    }
+    resolved
+}
+
+#[test]
+fn test_relative_path() {
+    assert_eq!("sprites/hero.pip", relative_path("sprites/hero.pie", "hero"));
+    assert_eq!("palettes/warm.act", relative_path("sprites/hero.pie", "../palettes/warm.act"));
+    assert_eq!("shared.pip", relative_path("hero.pie", "./shared.pip"));
+}
+
+#[test]
+fn test_read_from() {
+    let palette = Palette { format: crate::PixelFormat::RGB, colors: vec![1, 2, 3, 4, 5, 6] };
+    let pixels = [1, 2, 3, 4, 5, 6, 1, 2, 3];
+    let external = crate::encode(3, 1, pixels, false, Some(&palette)).unwrap().to_bytes();
+    let mut metadata = crate::Metadata::default();
+    metadata.set(PALETTE_KEY, "../palettes/shared");
+    let named = crate::set_metadata(&external, &metadata).unwrap();
+
+    let mut assets = HashMap::new();
+    assets.insert("sprites/hero.pie".to_string(), named);
+    assets.insert("sprites/plain.pie".to_string(), external.clone());
+    assets.insert("palettes/shared.pip".to_string(), palette.to_pip());
+    assets.insert("embedded.pie".to_string(), crate::encode(3, 1, pixels, true, None).unwrap().to_bytes());
+
+    assert_eq!(pixels.to_vec(), read_from(&assets, "sprites/hero.pie", None).unwrap().pixels);
+    assert_eq!(pixels.to_vec(), read_from(&assets, "embedded.pie", None).unwrap().pixels);
+    assert_eq!(pixels.to_vec(), read_from(&assets, "sprites/plain.pie", Some(&palette)).unwrap().pixels);
+    assert_eq!(Err(DecodeError::MissingPalette.in_file("sprites/plain.pie")), read_from(&assets, "sprites/plain.pie", None));
+    assert_eq!(Err(not_found("missing.pie")), read_from(&assets, "missing.pie", None));
+
+    assets.remove("palettes/shared.pip");
+    assert_eq!(Err(not_found("palettes/shared.pip")), read_from(&assets, "sprites/hero.pie", None));
+
+    let directory = DirSource::new("images");
+    assert_eq!(crate::read("images/test_embedded_palette.pie", None), read_from(&directory, "test_embedded_palette.pie", None));
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_zip_source() {
+    let pixels = [1, 2, 3, 4, 5, 6];
+    let image = crate::encode(2, 1, pixels, true, None).unwrap().to_bytes();
+    let archive = ZipSource::new(crate::zip::archive(&[("sprites/a.pie", 0, &image)]));
+    assert_eq!(pixels.to_vec(), read_from(&archive, "sprites/a.pie", None).unwrap().pixels);
+    assert_eq!(Err(not_found("sprites/b.pie")), read_from(&archive, "sprites/b.pie", None));
+    assert_eq!(Err(DecodeError::InvalidArchive { offset: 3 }.in_file("a.pie")), ZipSource::new(vec![0; 3]).load("a.pie"));
+}
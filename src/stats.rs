@@ -0,0 +1,94 @@
+//! Statistics about an encoded image, for pipelines that want to flag images that compress
+//! poorly.
+use std::fs;
+
+use crate::{encode, EncodeError, EncodedPIE, Palette, PixelFormat};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodeStats {
+    /// Unique colours in the image.
+    pub colors: usize,
+    pub runs: usize,
+    /// Average pixels per run.
+    pub average_run: f64,
+    /// Bytes of pixels given to the encoder, after any trimming.
+    pub bytes_in: usize,
+    /// Bytes of the PIE file, with the palette if it is embedded.
+    pub bytes_out: usize,
+    /// The format version written: 1 unless there are too many runs for its header or the
+    /// strategy needs version 2.
+    pub version: u8,
+    pub strategy: EncodeStrategy,
+}
+
+/// How the data section was laid out. Only [`crate::encode_with_stats`] tries anything but plain
+/// runs in order of first appearance.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct EncodeStrategy {
+    /// Each row is stored as runs or raw indices, whichever is smaller.
+    pub row_modes: bool,
+    /// Every other row is stored right to left, because it made the file smaller.
+    pub serpentine: bool,
+    /// The palette is ordered by how many pixels use each colour.
+    pub sorted_palette: bool,
+}
+
+impl EncodeStats {
+    /// Statistics for the file `bytes`, holding `runs` over `width` by `height` pixels encoded
+    /// from `bytes_in` bytes.
+    pub(crate) fn new(width: u16, height: u16, runs: &[u8], bytes_in: usize, bytes: &[u8], strategy: EncodeStrategy) -> EncodeStats {
+        let mut used = [false; 256];
+        for run in runs.chunks_exact(2) {
+            used[run[1] as usize] = true;
+        }
+
+        let pixels = width as usize * height as usize;
+        let runs = runs.len() / 2;
+        EncodeStats {
+            colors: used.iter().filter(|used| **used).count(),
+            runs,
+            average_run: if runs == 0 { 0.0 } else { pixels as f64 / runs as f64 },
+            bytes_in,
+            bytes_out: bytes.len(),
+            version: bytes[3],
+            strategy,
+        }
+    }
+}
+
+impl EncodedPIE {
+    pub fn stats(&self) -> EncodeStats {
+        let bytes_in = self.width as usize * self.height as usize * if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        EncodeStats::new(self.width, self.height, &self.indices, bytes_in, &self.to_bytes(), EncodeStrategy::default())
+    }
+}
+
+/// Like [`crate::write`], returning statistics about the file written.
+pub fn write_with_stats(path: &str, width: u16, height: u16, embed_palette: bool, maybe_palette: Option<&Palette>, pixels: impl AsRef<[u8]>) -> Result<EncodeStats, EncodeError> {
+    span!(INFO, "write", path);
+    let encoded = encode(width, height, pixels, embed_palette, maybe_palette).map_err(|error| error.in_file(path))?;
+    let stats = encoded.stats();
+
+    fs::write(path, encoded.to_bytes()).map_err(|error| EncodeError::Io(error.kind()).in_file(path))?;
+    event!(DEBUG, bytes = stats.bytes_out, "wrote file");
+    Ok(stats)
+}
+
+#[test]
+fn test_stats() {
+    let pixels = [[1, 2, 3].repeat(5), [4, 5, 6].repeat(3)].concat();
+    let stats = encode(4, 2, &pixels, true, None).unwrap().stats();
+    assert_eq!(EncodeStats { colors: 2, runs: 2, average_run: 4.0, bytes_in: 24, bytes_out: 11 + 4 + 6, version: 1, strategy: EncodeStrategy::default() }, stats);
+
+    let external = encode(4, 2, &pixels, false, None).unwrap().stats();
+    assert_eq!(11 + 4, external.bytes_out);
+
+    // Noise in one row and flat colour in the other is smaller as rows, and sorting puts the
+    // most used colour first.
+    let noisy = [[1, 2, 3, 4, 5, 6].repeat(2), [7, 8, 9].repeat(4)].concat();
+    let options = crate::EncodeOptions { embed_palette: true, row_modes: true, sort_palette: true, ..Default::default() };
+    let (bytes, stats) = crate::encode_with_stats(4, 2, &noisy, &options).unwrap();
+    assert_eq!(crate::encode_with_options(4, 2, &noisy, &options).unwrap(), bytes);
+    assert_eq!(EncodeStrategy { row_modes: true, serpentine: false, sorted_palette: true }, stats.strategy);
+    assert_eq!((3, 5, 2, bytes.len()), (stats.colors, stats.runs, stats.version, stats.bytes_out));
+}
@@ -0,0 +1,55 @@
+//! Square thumbnails, scaled by nearest neighbour so pixel art stays crisp.
+use crate::DecodedPIE;
+
+impl DecodedPIE {
+    /// RGBA pixels of the image scaled to fit a `size` square, centred on transparency.
+    pub fn thumbnail(&self, size: u16) -> Vec<u8> {
+        if self.width == 0 || self.height == 0 || size == 0 {
+            return vec![0; size as usize * size as usize * 4];
+        }
+        self.fit(size, (0, 0)).0
+    }
+
+    /// RGBA pixels of the image scaled to fill a `size` square, with whatever overhangs the
+    /// longer side cropped equally from both ends.
+    pub fn thumbnail_cropped(&self, size: u16) -> Vec<u8> {
+        let (width, height, size) = (self.width as usize, self.height as usize, size as usize);
+        if width == 0 || height == 0 {
+            return vec![0; size * size * 4];
+        }
+        // The square of the source that is kept.
+        let side = width.min(height);
+        let (left, top) = ((width - side) / 2, (height - side) / 2);
+
+        let rgba = self.to_rgba8();
+        let mut pixels = Vec::with_capacity(size * size * 4);
+        for y in 0..size {
+            for x in 0..size {
+                let from = ((top + y * side / size) * width + left + x * side / size) * 4;
+                pixels.extend_from_slice(&rgba[from..from + 4]);
+            }
+        }
+        pixels
+    }
+}
+
+#[test]
+fn test_thumbnail() {
+    let pixels = [
+        1, 0, 0, 2, 0, 0, 3, 0, 0, 4, 0, 0,
+        5, 0, 0, 6, 0, 0, 7, 0, 0, 8, 0, 0,
+    ];
+    let image = crate::decode(crate::encode(4, 2, pixels, true, None).unwrap().to_bytes(), None).unwrap();
+
+    assert_eq!(vec![
+        1, 0, 0, 0xFF, 3, 0, 0, 0xFF,
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ], image.thumbnail(2));
+    assert_eq!(vec![
+        2, 0, 0, 0xFF, 3, 0, 0, 0xFF,
+        6, 0, 0, 0xFF, 7, 0, 0, 0xFF,
+    ], image.thumbnail_cropped(2));
+    assert_eq!(4 * 4 * 4, image.thumbnail_cropped(4).len());
+    assert!(image.thumbnail(0).is_empty());
+    assert!(image.thumbnail_cropped(0).is_empty());
+}
@@ -0,0 +1,51 @@
+//! When a file was made, as UTC seconds since the Unix epoch in a `TIME` chunk, so asset
+//! pipelines can tell stale conversions apart without trusting file modification times, which
+//! checkouts and copies reset.
+//!
+//! ```text
+//! TIME     u64    -- Seconds since 1970-01-01 00:00:00 UTC (BE)
+//! ```
+use crate::{chunk::read_chunks, DecodeError, Header};
+
+pub(crate) const CHUNK_TIME: [u8; 4] = *b"TIME";
+
+/// Read when the file was made from the raw bytes of a PIE file, as UTC seconds since the Unix
+/// epoch. None if it was not stamped, see [`crate::EncodeOptions::timestamp`].
+pub fn decode_timestamp(bytes: impl AsRef<[u8]>) -> Result<Option<u64>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(None);
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == CHUNK_TIME) else {
+        return Ok(None);
+    };
+    if chunk.data.len() != 8 {
+        return Err(chunk.invalid(0));
+    }
+    Ok(Some(u64::from_be_bytes(chunk.data.try_into().unwrap())))
+}
+
+/// The current time as UTC seconds since the Unix epoch, for [`crate::EncodeOptions::timestamp`].
+pub fn current_timestamp() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[test]
+fn test_timestamp() {
+    use crate::{encode, encode_with_options, EncodeOptions};
+
+    let pixels = [1, 2, 3, 4, 5, 6];
+    let options = EncodeOptions { embed_palette: true, timestamp: Some(1_700_000_000), ..Default::default() };
+    let bytes = encode_with_options(2, 1, pixels, &options).unwrap();
+    assert_eq!(Ok(Some(1_700_000_000)), decode_timestamp(&bytes));
+    assert_eq!(pixels.to_vec(), crate::decode(&bytes, None).unwrap().pixels);
+    assert_eq!(Ok(None), decode_timestamp(encode(2, 1, pixels, true, None).unwrap().to_bytes()));
+    assert!(current_timestamp() > 1_700_000_000);
+
+    let short = crate::chunk::replace_chunk(&bytes, CHUNK_TIME, Some(&[0; 4])).unwrap();
+    let offset = short.len() - 4;
+    assert_eq!(Err(DecodeError::InvalidChunk { kind: CHUNK_TIME, offset }), decode_timestamp(&short));
+}
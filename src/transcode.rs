@@ -0,0 +1,185 @@
+//! Rewriting a PIE file with different options straight from its runs and palette, without
+//! expanding it to pixels and encoding them again.
+use crate::{
+    chunk::{read_chunks, write_chunk}, comment, padding, scan::encode_data, DecodeError, EncodeError, EncodeOptions, EncodedPIE, EncodedView, Header, PixelFormat,
+    TranscodeError, CHUNK_NINE_SLICE, CHUNK_PADDING, CHUNK_PHYS, CHUNK_TEXT, CHUNK_TIME, FLAG_PALETTE, FLAG_ROWS, FLAG_SERPENTINE, FLAG_TRANSPARENCY,
+};
+
+/// Rewrite the PIE file `bytes` with `options`, keeping its runs and any chunks the options do
+/// not replace.
+/// * `embed_palette` - Embed the palette, which is `options.palette` if the file's is external,
+///   or strip an embedded one.
+/// * `sort_palette`, `padding`, `row_modes` and `serpentine` - Applied to the runs. Animations
+///   cannot have any of them, as only their first frame would change. Without `row_modes` and
+///   `serpentine`, files that have them are converted back to runs in the normal order. Sorting
+///   fails on embedded palettes with more than 256 colours.
+/// * `nine_slice`, `timestamp`, `density` and `chunks` - Replace any of the same kind the file has.
+/// * `comments` - Replace any with the same keyword the file has.
+///
+/// Options that work on colours, `alpha_threshold`, `trim` and `flatten`, are ignored.
+pub fn transcode(bytes: impl AsRef<[u8]>, options: &EncodeOptions) -> Result<Vec<u8>, TranscodeError> {
+    let bytes = bytes.as_ref();
+    span!(DEBUG, "transcode", bytes = bytes.len());
+    let view = EncodedView::new(bytes)?;
+    let header = view.header;
+    let kept_chunks = if header.version < 2 { Vec::new() } else { read_chunks(bytes, header.chunks_offset())? };
+    if header.is_animated() && (options.sort_palette || options.padding.is_some() || options.row_modes || options.serpentine) {
+        return Err(TranscodeError::Animated);
+    }
+
+    let mut encoded = EncodedPIE::from(view);
+    encoded.palette = match (options.embed_palette, encoded.palette) {
+        (false, _) => None,
+        (true, Some(palette)) => Some(palette),
+        (true, None) => Some(options.palette.ok_or(DecodeError::MissingPalette)?.clone()),
+    };
+    if let Some(palette) = &encoded.palette {
+        encoded.format = palette.format;
+    }
+    if options.sort_palette {
+        // Runs cannot index colours past 256, so a palette with more has nothing to sort them by.
+        if let Some(palette) = &encoded.palette {
+            let count = palette.colors.len() / if palette.format == PixelFormat::RGBA { 4 } else { 3 };
+            if count > 256 {
+                return Err(EncodeError::TooManyColors { count }.into());
+            }
+        }
+        encoded.sort_palette_by_usage();
+    }
+
+    options.check_chunks()?;
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = kept_chunks.iter()
+        .filter(|chunk| !(chunk.kind == CHUNK_NINE_SLICE && options.nine_slice.is_some()))
+        .filter(|chunk| !(chunk.kind == CHUNK_TIME && options.timestamp.is_some()))
+        .filter(|chunk| !(chunk.kind == CHUNK_PHYS && options.density.is_some()))
+        .filter(|chunk| !options.chunks.iter().any(|(kind, _)| *kind == chunk.kind))
+        .filter(|chunk| chunk.kind != CHUNK_TEXT || !comment::from_bytes(chunk.data).is_some_and(|(keyword, _)| options.comments.iter().any(|(k, _)| *k == keyword)))
+        .map(|chunk| (chunk.kind, chunk.data.to_vec()))
+        .collect();
+    if let Some(nine_slice) = options.nine_slice {
+        chunks.push((CHUNK_NINE_SLICE, nine_slice.to_bytes()));
+    }
+    if let Some(timestamp) = options.timestamp {
+        chunks.push((CHUNK_TIME, timestamp.to_be_bytes().to_vec()));
+    }
+    if let Some(density) = options.density {
+        chunks.push((CHUNK_PHYS, density.to_bytes()));
+    }
+    chunks.extend(options.comments.iter().map(|(keyword, text)| (CHUNK_TEXT, comment::to_bytes(keyword, text))));
+    chunks.extend(options.chunks.iter().cloned());
+
+    let (mut width, mut height) = (encoded.width, encoded.height);
+    if let Some(padding) = options.padding {
+        let colors = match (&encoded.palette, options.palette) {
+            (Some(palette), _) | (None, Some(palette)) => palette.colors.len() / if palette.format == PixelFormat::RGBA { 4 } else { 3 },
+            (None, None) => 256,
+        };
+        let (padded_width, padded_height, runs) = padding::pad(width, height, &encoded.indices, padding, options.padding_index, colors)?;
+        // Padding again keeps the size of the original content.
+        if !chunks.iter().any(|(kind, _)| *kind == CHUNK_PADDING) {
+            chunks.push((CHUNK_PADDING, [width.to_be_bytes(), height.to_be_bytes()].concat()));
+        }
+        (width, height, encoded.indices) = (padded_width, padded_height, runs);
+    }
+
+    let (data_flags, data) = encode_data(width as usize, &encoded.indices, options);
+    let runs = if data_flags & FLAG_ROWS > 0 { data.len() } else { data.len() / 2 } as u32;
+    let mut rewritten = Header {
+        version: if chunks.is_empty() && runs <= u16::MAX as u32 && data_flags == 0 { header.version } else { 2 },
+        width, height,
+        flags: header.flags & !(FLAG_PALETTE | FLAG_TRANSPARENCY | FLAG_ROWS | FLAG_SERPENTINE) | data_flags,
+        colors: 0,
+        runs,
+    };
+    if encoded.format == PixelFormat::RGBA {
+        rewritten.flags |= FLAG_TRANSPARENCY;
+    }
+    if let Some(palette) = &encoded.palette {
+        rewritten.flags |= FLAG_PALETTE;
+        rewritten.colors = (palette.colors.len() / rewritten.stride()) as u16;
+    }
+
+    let mut transcoded = Vec::with_capacity(bytes.len());
+    rewritten.write(&mut transcoded);
+    transcoded.extend_from_slice(&data);
+    if let Some(palette) = &encoded.palette {
+        transcoded.extend_from_slice(&palette.colors);
+    }
+    for (kind, data) in &chunks {
+        write_chunk(&mut transcoded, *kind, data);
+    }
+    Ok(transcoded)
+}
+
+impl From<DecodeError> for TranscodeError {
+    fn from(error: DecodeError) -> TranscodeError {
+        TranscodeError::Decode(error)
+    }
+}
+
+impl From<EncodeError> for TranscodeError {
+    fn from(error: EncodeError) -> TranscodeError {
+        TranscodeError::Encode(error)
+    }
+}
+
+#[test]
+fn test_transcode() {
+    let palette = crate::Palette { format: PixelFormat::RGB, colors: vec![1, 1, 1, 2, 2, 2, 3, 3, 3] };
+    let pixels = [[1, 1, 1], [2, 2, 2], [2, 2, 2], [3, 3, 3]].concat();
+    let embedded = crate::encode(4, 1, &pixels, true, Some(&palette)).unwrap().to_bytes();
+    let external = crate::encode(4, 1, &pixels, false, Some(&palette)).unwrap().to_bytes();
+
+    let strip = EncodeOptions::default();
+    assert_eq!(external, transcode(&embedded, &strip).unwrap());
+    let embed = EncodeOptions { embed_palette: true, palette: Some(&palette), ..strip.clone() };
+    assert_eq!(embedded, transcode(&external, &embed).unwrap());
+    assert_eq!(Err(TranscodeError::Decode(DecodeError::MissingPalette)), transcode(&external, &EncodeOptions { palette: None, ..embed.clone() }));
+
+    let sorted = transcode(&embedded, &EncodeOptions { sort_palette: true, ..embed.clone() }).unwrap();
+    assert_eq!(crate::encode_with_options(4, 1, &pixels, &EncodeOptions { palette: None, sort_palette: true, ..embed.clone() }).unwrap(), sorted);
+
+    let padded = transcode(&embedded, &EncodeOptions { padding: Some(crate::Padding::Multiple(3)), ..embed.clone() }).unwrap();
+    assert_eq!(crate::encode_with_options(4, 1, &pixels, &EncodeOptions { padding: Some(crate::Padding::Multiple(3)), ..embed.clone() }).unwrap(), padded);
+    assert_eq!(padded, transcode(&padded, &EncodeOptions { padding: Some(crate::Padding::Multiple(3)), ..embed.clone() }).unwrap());
+
+    let rows = transcode(&embedded, &EncodeOptions { row_modes: true, ..embed.clone() }).unwrap();
+    assert_eq!(crate::encode_with_options(4, 1, &pixels, &EncodeOptions { palette: None, row_modes: true, ..embed.clone() }).unwrap(), rows);
+    assert_eq!(crate::decode(&embedded, None), crate::decode(transcode(&rows, &embed).unwrap(), None));
+    let serpentine = transcode(&rows, &EncodeOptions { serpentine: true, ..embed.clone() }).unwrap();
+    assert_eq!(crate::decode(&embedded, None), crate::decode(&serpentine, None));
+
+    let mut tagged = embed.clone();
+    tagged.add_chunk(*b"hint", [1, 2]).add_chunk(*b"note", *b"hi");
+    let tagged = transcode(&embedded, &tagged).unwrap();
+    let mut retagged = EncodeOptions { embed_palette: true, ..strip.clone() };
+    retagged.add_chunk(*b"hint", [3]);
+    assert_eq!(vec![(*b"note", b"hi".to_vec()), (*b"hint", vec![3])], crate::decode(transcode(&tagged, &retagged).unwrap(), None).unwrap().chunks);
+
+    let mut commented = embed.clone();
+    commented.set_comment("Author", "Ada").set_comment("Title", "Grass");
+    let commented = transcode(&embedded, &commented).unwrap();
+    let mut recommented = EncodeOptions { embed_palette: true, ..strip.clone() };
+    recommented.set_comment("Author", "Grace");
+    let expected = vec![("Title".to_string(), "Grass".to_string()), ("Author".to_string(), "Grace".to_string())];
+    assert_eq!(expected, crate::decode(transcode(&commented, &recommented).unwrap(), None).unwrap().comments);
+
+    let stamped = transcode(&embedded, &EncodeOptions { timestamp: Some(10), ..embed.clone() }).unwrap();
+    assert_eq!(Ok(Some(10)), crate::decode_timestamp(&stamped));
+    assert_eq!(Ok(Some(10)), crate::decode_timestamp(transcode(&stamped, &embed).unwrap()));
+    assert_eq!(Ok(Some(20)), crate::decode_timestamp(transcode(&stamped, &EncodeOptions { timestamp: Some(20), ..embed.clone() }).unwrap()));
+
+    let frames = [pixels.clone(), pixels];
+    let animation = crate::encode_animation(4, 1, &frames, 100, true, None).unwrap().to_bytes().unwrap();
+    let stripped = transcode(&animation, &strip).unwrap();
+    assert_eq!(frames[1], crate::decode_animation(stripped, Some(&palette)).unwrap().frames[1].pixels);
+    assert_eq!(Err(TranscodeError::Animated), transcode(&animation, &EncodeOptions { sort_palette: true, ..strip.clone() }));
+
+    // Version 2 files can embed more colours than a palette should hold.
+    let colors: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0]).collect();
+    let mut wide = Vec::new();
+    crate::write_image(&mut wide, 3, 1, PixelFormat::RGB, &[1, 200, 2, 5], Some(&colors), &[(*b"hint", vec![1])]);
+    assert!(crate::decode(&wide, None).is_ok());
+    let sort = EncodeOptions { embed_palette: true, sort_palette: true, ..Default::default() };
+    assert_eq!(Err(TranscodeError::Encode(EncodeError::TooManyColors { count: 300 })), transcode(&wide, &sort));
+}
@@ -0,0 +1,100 @@
+//! Cropping fully transparent borders on encode. Where the kept pixels sat in the original image
+//! is stored in a `TRIM` chunk so engines can put trimmed sprites back in place.
+//!
+//! ```text
+//! TRIM     u16    -- Column of the original image the file starts at (BE)
+//!          u16    -- Row of the original image the file starts at (BE)
+//!          u16    -- Original width in pixels (BE)
+//!          u16    -- Original height in pixels (BE)
+//! ```
+use crate::{chunk::read_chunks, DecodeError, Header};
+
+pub(crate) const CHUNK_TRIM: [u8; 4] = *b"TRIM";
+
+/// Where a trimmed image sat in the image it was cut from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Trim {
+    /// Offset of the trimmed image from the left of the original.
+    pub x: u16,
+    /// Offset of the trimmed image from the top of the original.
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Trim {
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        [self.x, self.y, self.width, self.height].iter().flat_map(|value| value.to_be_bytes()).collect()
+    }
+}
+
+/// Crop the fully transparent borders of RGBA `pixels`, returning where the rest was and the
+/// cropped pixels with their size. None if there is nothing to crop. Fully transparent images
+/// keep their top left pixel, as a file needs at least one.
+pub(crate) fn trim(width: u16, height: u16, pixels: &[u8]) -> Option<(Trim, u16, u16, Vec<u8>)> {
+    if pixels.len() != width as usize * height as usize * 4 {
+        return None;
+    }
+
+    let opaque = |x: u16, y: u16| pixels[(y as usize * width as usize + x as usize) * 4 + 3] > 0;
+    let rows: Vec<u16> = (0..height).filter(|&y| (0..width).any(|x| opaque(x, y))).collect();
+    let columns: Vec<u16> = (0..width).filter(|&x| (0..height).any(|y| opaque(x, y))).collect();
+    let (top, bottom, left, right) = match (rows.first(), rows.last(), columns.first(), columns.last()) {
+        (Some(top), Some(bottom), Some(left), Some(right)) => (*top, *bottom, *left, *right),
+        _ => (0, 0, 0, 0),
+    };
+
+    let (trimmed_width, trimmed_height) = (right - left + 1, bottom - top + 1);
+    if (trimmed_width, trimmed_height) == (width, height) {
+        return None;
+    }
+
+    let mut cropped = Vec::with_capacity(trimmed_width as usize * trimmed_height as usize * 4);
+    for y in top..=bottom {
+        let start = (y as usize * width as usize + left as usize) * 4;
+        cropped.extend_from_slice(&pixels[start..start + trimmed_width as usize * 4]);
+    }
+
+    Some((Trim { x: left, y: top, width, height }, trimmed_width, trimmed_height, cropped))
+}
+
+/// Read where a trimmed image sat in the original from the raw bytes of a PIE file. None if it
+/// was not trimmed.
+pub fn decode_trim(bytes: impl AsRef<[u8]>) -> Result<Option<Trim>, DecodeError> {
+    let bytes = bytes.as_ref();
+    let header = Header::parse(bytes)?;
+    if header.version < 2 {
+        return Ok(None);
+    }
+
+    let chunks = read_chunks(bytes, header.chunks_offset())?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == CHUNK_TRIM) else {
+        return Ok(None);
+    };
+
+    let mut at = 0;
+    let mut value = || chunk.take(&mut at, 2).map(|value| u16::from_be_bytes([value[0], value[1]]));
+    Ok(Some(Trim { x: value()?, y: value()?, width: value()?, height: value()? }))
+}
+
+#[test]
+fn test_trim() {
+    let clear = [0, 0, 0, 0];
+    let solid = [0xFF, 0, 0, 0xFF];
+    let rows = [
+        [clear, clear, clear, clear],
+        [clear, solid, clear, clear],
+        [clear, solid, solid, clear],
+    ];
+    let pixels: Vec<u8> = rows.iter().flatten().flatten().copied().collect();
+
+    let options = crate::EncodeOptions { embed_palette: true, trim: true, ..Default::default() };
+    let bytes = crate::encode_with_options(4, 3, &pixels, &options).unwrap();
+    let decoded = crate::decode(&bytes, None).unwrap();
+    assert_eq!((2, 2), (decoded.width, decoded.height));
+    assert_eq!([solid, clear, solid, solid].concat(), decoded.pixels);
+    assert_eq!(Some(Trim { x: 1, y: 1, width: 4, height: 3 }), decode_trim(&bytes).unwrap());
+
+    assert_eq!(None, trim(2, 2, &solid.repeat(4)));
+    assert_eq!(Some((Trim { x: 0, y: 0, width: 2, height: 1 }, 1, 1, clear.to_vec())), trim(2, 1, &clear.repeat(2)));
+}
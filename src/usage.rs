@@ -0,0 +1,128 @@
+//! How much of the palette an encoded image uses, and dropping what it does not.
+use crate::{EncodedPIE, PixelFormat};
+
+impl EncodedPIE {
+    /// Pixels using each palette index. There is an entry for every colour of an embedded
+    /// palette, and otherwise up to the highest index used.
+    pub fn palette_usage(&self) -> Vec<u32> {
+        let mut usage = vec![0; self.palette_len()];
+        for run in self.indices.chunks_exact(2) {
+            let index = run[1] as usize;
+            if index >= usage.len() {
+                usage.resize(index + 1, 0);
+            }
+            usage[index] += run[0] as u32;
+        }
+        usage
+    }
+
+    /// Drop embedded palette colours no pixel uses, moving the indices of the rest down to
+    /// match. Returns how many were dropped. External palettes are left alone, as other images
+    /// may use the colours.
+    pub fn prune_unused_colors(&mut self) -> usize {
+        let usage = self.palette_usage();
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let Some(palette) = self.palette.as_mut() else { return 0 };
+
+        // Version 2 palettes can hold more colours than runs can index, which are never used.
+        let mut remap = vec![0u8; usage.len().max(256)];
+        let mut kept = 0;
+        let mut colors = Vec::with_capacity(palette.colors.len());
+        for (index, color) in palette.colors.chunks_exact(stride).enumerate() {
+            if usage[index] > 0 {
+                remap[index] = kept;
+                colors.extend_from_slice(color);
+                kept = kept.wrapping_add(1);
+            }
+        }
+
+        let dropped = (palette.colors.len() - colors.len()) / stride;
+        palette.colors = colors;
+        for run in self.indices.chunks_exact_mut(2) {
+            run[1] = remap[run[1] as usize];
+        }
+        dropped
+    }
+
+    /// Reorder embedded palette colours by how many pixels use each, most first, with ties kept
+    /// in their order. Low indices for common colours compress better and read more easily.
+    /// External palettes are left alone, as other images may use the colours.
+    pub fn sort_palette_by_usage(&mut self) {
+        let usage = self.palette_usage();
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let Some(palette) = self.palette.as_mut() else { return };
+
+        let mut order: Vec<usize> = (0..palette.colors.len() / stride).collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(usage[index]));
+
+        // Colours past 256 are never used, so they sort last and their wrapped indices never
+        // come up.
+        let mut remap = vec![0u8; order.len().max(256)];
+        let mut colors = Vec::with_capacity(palette.colors.len());
+        for (new, &old) in order.iter().enumerate() {
+            remap[old] = new as u8;
+            colors.extend_from_slice(&palette.colors[old * stride..(old + 1) * stride]);
+        }
+
+        palette.colors = colors;
+        for run in self.indices.chunks_exact_mut(2) {
+            run[1] = remap[run[1] as usize];
+        }
+    }
+
+    fn palette_len(&self) -> usize {
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        self.palette.as_ref().map_or(0, |palette| palette.colors.len() / stride)
+    }
+}
+
+#[test]
+fn test_palette_usage() {
+    let palette = crate::Palette { format: PixelFormat::RGB, colors: vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4] };
+    let pixels = [[2, 2, 2].repeat(3), [4, 4, 4].repeat(2)].concat();
+    let mut encoded = crate::encode(5, 1, &pixels, true, Some(&palette)).unwrap();
+    assert_eq!(vec![0, 3, 0, 2], encoded.palette_usage());
+
+    assert_eq!(2, encoded.prune_unused_colors());
+    assert_eq!(vec![3, 2], encoded.palette_usage());
+    assert_eq!(pixels, crate::decode(encoded.to_bytes(), None).unwrap().pixels);
+
+    let mut external = crate::encode(5, 1, &pixels, false, Some(&palette)).unwrap();
+    assert_eq!(vec![0, 3, 0, 2], external.palette_usage());
+    assert_eq!(0, external.prune_unused_colors());
+}
+
+#[test]
+fn test_sort_palette_by_usage() {
+    let pixels = [[1, 1, 1], [2, 2, 2], [2, 2, 2], [3, 3, 3], [3, 3, 3], [4, 4, 4]].concat();
+    let mut encoded = crate::encode(6, 1, &pixels, true, None).unwrap();
+    encoded.sort_palette_by_usage();
+    assert_eq!(vec![2, 2, 2, 3, 3, 3, 1, 1, 1, 4, 4, 4], encoded.palette.as_ref().unwrap().colors);
+    assert_eq!(vec![2, 2, 1, 1], encoded.palette_usage());
+    assert_eq!(vec![1, 2, 2, 0, 2, 1, 1, 3], encoded.indices);
+    assert_eq!(pixels, crate::decode(encoded.to_bytes(), None).unwrap().pixels);
+
+    let options = crate::EncodeOptions { embed_palette: true, sort_palette: true, ..Default::default() };
+    assert_eq!(encoded.to_bytes(), crate::encode_with_options(6, 1, &pixels, &options).unwrap());
+}
+
+#[test]
+fn test_palette_past_256_colors() {
+    let colors: Vec<u8> = (0..300u32).flat_map(|i| [i as u8, (i >> 8) as u8, 0]).collect();
+    let wide = || EncodedPIE {
+        width: 3, height: 1, format: PixelFormat::RGB,
+        indices: vec![1, 200, 2, 5],
+        palette: Some(crate::Palette { format: PixelFormat::RGB, colors: colors.clone() }),
+    };
+    let pixels = crate::decode(wide().to_bytes(), None).unwrap().pixels;
+
+    let mut sorted = wide();
+    sorted.sort_palette_by_usage();
+    assert_eq!(vec![1, 1, 2, 0], sorted.indices);
+    assert_eq!(pixels, crate::decode(sorted.to_bytes(), None).unwrap().pixels);
+
+    let mut pruned = wide();
+    assert_eq!(298, pruned.prune_unused_colors());
+    assert_eq!(vec![1, 1, 2, 0], pruned.indices);
+    assert_eq!(pixels, crate::decode(pruned.to_bytes(), None).unwrap().pixels);
+}
@@ -0,0 +1,105 @@
+//! A borrowed view over the sections of a PIE file, for tools that want the runs or palette
+//! without copying them or expanding pixels.
+use std::{borrow::Cow, ops::Range};
+
+use crate::{rows::data_runs, section, DecodeError, EncodedPIE, Header, Palette};
+
+/// The sections of a PIE file, borrowed from its bytes. Sections are checked to be in bounds
+/// when the view is made, the runs themselves are not checked. Rows are checked for files with
+/// per row modes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EncodedView<'a> {
+    pub header: Header,
+    bytes: &'a [u8],
+}
+
+impl<'a> EncodedView<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<EncodedView<'a>, DecodeError> {
+        let header = Header::parse(bytes)?;
+        section(bytes, 0..header.chunks_offset())?;
+        if header.has_row_modes() {
+            data_runs(bytes, &header)?;
+        }
+
+        Ok(EncodedView { header, bytes })
+    }
+
+    /// Runs `(count, index)` of the data section. For animations this is the first frame. For files
+    /// with per row modes this is the rows as they are, see [`EncodedView::runs`].
+    pub fn indices(&self) -> &'a [u8] {
+        &self.bytes[self.data_range()]
+    }
+
+    /// Runs `(count, index)` of the data section, converted from rows if the file has per row
+    /// modes.
+    pub fn runs(&self) -> Cow<'a, [u8]> {
+        match data_runs(self.bytes, &self.header) {
+            Ok(runs) => runs,
+            // Rows were checked when the view was made.
+            Err(_) => Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Where the data section is in the file, see [`Header::data_range`].
+    pub fn data_range(&self) -> Range<usize> {
+        self.header.data_range()
+    }
+
+    /// Where the embedded palette is in the file, empty if it is external.
+    pub fn palette_range(&self) -> Range<usize> {
+        self.header.palette_range()
+    }
+
+    /// Where everything after the palette is in the file, see [`EncodedView::trailer`].
+    pub fn trailer_range(&self) -> Range<usize> {
+        self.header.chunks_offset()..self.bytes.len()
+    }
+
+    /// Colours of the embedded palette, if there is one.
+    pub fn palette_colors(&self) -> Option<&'a [u8]> {
+        self.header.has_palette().then(|| &self.bytes[self.palette_range()])
+    }
+
+    /// The embedded palette, if there is one.
+    pub fn palette(&self) -> Option<Palette> {
+        self.palette_colors().map(|colors| Palette { format: self.header.format(), colors: colors.to_vec() })
+    }
+
+    /// Everything after the palette, which is the chunks of version 2 files.
+    pub fn trailer(&self) -> &'a [u8] {
+        &self.bytes[self.trailer_range()]
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for EncodedView<'a> {
+    type Error = DecodeError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<EncodedView<'a>, DecodeError> {
+        EncodedView::new(bytes)
+    }
+}
+
+impl From<EncodedView<'_>> for EncodedPIE {
+    fn from(view: EncodedView<'_>) -> EncodedPIE {
+        EncodedPIE {
+            width: view.header.width,
+            height: view.header.height,
+            format: view.header.format(),
+            indices: view.runs().into_owned(),
+            palette: view.palette(),
+        }
+    }
+}
+
+#[test]
+fn test_view() {
+    let bytes = include_bytes!("../images/test_embedded_palette.pie");
+    let view: EncodedView = bytes.as_slice().try_into().unwrap();
+    assert_eq!(46, view.indices().len());
+    assert_eq!(Some(&bytes[57..]), view.palette_colors());
+    assert!(view.trailer().is_empty());
+    assert_eq!((11..57, 57..69, 69..69), (view.data_range(), view.palette_range(), view.trailer_range()));
+    assert_eq!(EncodedPIE::from_bytes(bytes).unwrap(), EncodedPIE::from(view));
+
+    assert_eq!(Err(DecodeError::Truncated { expected: 57, found: 40 }), EncodedView::try_from(&bytes[..40]));
+}
@@ -0,0 +1,166 @@
+//! Reload a PIE file whenever it changes on disk, so games can pick up sprites while artists
+//! work on them.
+use std::{
+    error::Error,
+    fmt,
+    path::Path,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, MutexGuard},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{read, DecodeError, DecodedPIE, Palette};
+
+type Callback = Box<dyn FnMut(&DecodedPIE) + Send>;
+
+#[derive(Debug)]
+pub enum WatchError {
+    /// The first read of the file failed.
+    Decode(DecodeError),
+    /// The file could not be watched.
+    Notify(notify::Error),
+}
+
+/// A decoded PIE file that is read again every time it changes. The directory holding the file is
+/// watched rather than the file, as many editors save by replacing the file.
+pub struct Watched {
+    shared: Arc<Shared>,
+    // Dropping the watcher stops watching.
+    _watcher: RecommendedWatcher,
+}
+
+struct Shared {
+    path: String,
+    palette: Option<Palette>,
+    image: Mutex<DecodedPIE>,
+    error: Mutex<Option<DecodeError>>,
+    changed: AtomicBool,
+    callback: Mutex<Option<Callback>>,
+}
+
+impl Watched {
+    /// Read the file at `path` and start watching it. `palette` is required if the palette is not
+    /// embedded in the file.
+    pub fn new(path: &str, palette: Option<Palette>) -> Result<Watched, WatchError> {
+        let image = read(path, palette.as_ref()).map_err(WatchError::Decode)?;
+        let shared = Arc::new(Shared {
+            path: path.to_string(),
+            palette,
+            image: Mutex::new(image),
+            error: Mutex::new(None),
+            changed: AtomicBool::new(false),
+            callback: Mutex::new(None),
+        });
+
+        let handler = Arc::clone(&shared);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                let ours = event.paths.iter().any(|changed| changed.file_name() == Path::new(&handler.path).file_name());
+                if ours && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    handler.reload();
+                }
+            }
+        }).map_err(WatchError::Notify)?;
+
+        let directory = match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        watcher.watch(directory, RecursiveMode::NonRecursive).map_err(WatchError::Notify)?;
+
+        Ok(Watched { shared, _watcher: watcher })
+    }
+
+    /// Call `callback` with the new image every time the file is reloaded. It runs on the
+    /// watcher's thread.
+    pub fn on_change(self, callback: impl FnMut(&DecodedPIE) + Send + 'static) -> Watched {
+        *self.shared.callback.lock().unwrap() = Some(Box::new(callback));
+        self
+    }
+
+    /// The latest successfully decoded image. Holding on to it blocks reloading.
+    pub fn image(&self) -> MutexGuard<'_, DecodedPIE> {
+        self.shared.image.lock().unwrap()
+    }
+
+    /// Whether the file has been reloaded since this was last called.
+    pub fn changed(&self) -> bool {
+        self.shared.changed.swap(false, Ordering::AcqRel)
+    }
+
+    /// The error from the last reload if it failed, such as when the file is read half written.
+    /// The previous image is kept until a reload succeeds.
+    pub fn take_error(&self) -> Option<DecodeError> {
+        self.shared.error.lock().unwrap().take()
+    }
+}
+
+impl Shared {
+    fn reload(&self) {
+        span!(DEBUG, "reload", path = self.path);
+        match read(&self.path, self.palette.as_ref()) {
+            Ok(image) => {
+                let mut latest = self.image.lock().unwrap();
+                *latest = image;
+                *self.error.lock().unwrap() = None;
+                self.changed.store(true, Ordering::Release);
+
+                if let Some(callback) = self.callback.lock().unwrap().as_mut() {
+                    callback(&latest);
+                }
+            }
+            Err(error) => {
+                event!(DEBUG, %error, "reload failed");
+                *self.error.lock().unwrap() = Some(error);
+            }
+        }
+    }
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::Decode(error) => write!(f, "{}", error),
+            WatchError::Notify(error) => write!(f, "could not watch: {}", error),
+        }
+    }
+}
+
+impl Error for WatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WatchError::Decode(error) => Some(error),
+            WatchError::Notify(error) => Some(error),
+        }
+    }
+}
+
+#[test]
+fn test_watched() {
+    use std::{fs, sync::mpsc, time::Duration};
+
+    let directory = std::env::temp_dir().join(format!("pie_watch_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("sprite.pie");
+    let path = path.to_str().unwrap();
+
+    let red = [0xFF, 0x00, 0x00].repeat(4);
+    let green = [0x00, 0xFF, 0x00].repeat(4);
+    crate::write(path, 2, 2, true, None, &red).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let watched = Watched::new(path, None).unwrap().on_change(move |image| {
+        let _ = sender.send(image.pixels.clone());
+    });
+    assert_eq!(red, watched.image().pixels);
+    assert!(!watched.changed());
+
+    crate::write(path, 2, 2, true, None, &green).unwrap();
+    let reloaded = receiver.recv_timeout(Duration::from_secs(5));
+    let pixels = watched.image().pixels.clone();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(reloaded.is_ok());
+    assert_eq!(green, pixels);
+    assert!(watched.changed());
+}
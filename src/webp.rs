@@ -0,0 +1,381 @@
+//! Lossless WebP export, written directly rather than through another crate. The image is stored
+//! as its palette with the colour indexing transform, and the indices are compressed with
+//! backward references to the pixel to the left or above and Huffman codes.
+use std::io::{self, Write};
+
+use crate::{color_map::{pack, ColorMap}, DecodedPIE, PixelFormat};
+
+/// Size of the green alphabet: literals then length prefixes, with no colour cache.
+const GREEN_SYMBOLS: usize = 256 + 24;
+const DISTANCE_SYMBOLS: usize = 40;
+const MAX_LENGTH: usize = 4096;
+/// Order code length code lengths are written in.
+const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+impl DecodedPIE {
+    /// Write the image as a lossless WebP file. Lossless is the only kind that keeps pixel art
+    /// exact, so there is no lossy option. Images larger than 16384 pixels on a side do not fit.
+    /// Images with more than 256 colours, such as flattened layers, are written without a palette.
+    pub fn to_webp(&self, mut writer: impl Write) -> io::Result<()> {
+        span!(DEBUG, "to_webp", width = self.width, height = self.height);
+        if self.width as usize > 16384 || self.height as usize > 16384 || self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "WebP images are 1 to 16384 pixels on a side"));
+        }
+
+        let stride = if self.format == PixelFormat::RGBA { 4 } else { 3 };
+        let mut map: ColorMap<usize> = ColorMap::default();
+        let mut palette = Vec::new();
+        let indices: Vec<usize> = self.pixels.chunks_exact(stride).map(|pixel| *map.entry(pack(pixel)).or_insert_with(|| {
+            palette.push(argb(pixel));
+            palette.len() - 1
+        })).collect();
+
+        let mut bits = BitWriter::default();
+        bits.write(0x2F, 8);
+        bits.write(self.width as u32 - 1, 14);
+        bits.write(self.height as u32 - 1, 14);
+        bits.write((self.format == PixelFormat::RGBA) as u32, 1);
+        bits.write(0, 3);
+
+        let width = self.width as usize;
+        if palette.len() > 256 {
+            // Too many colours for the colour indexing transform, so write them as they are.
+            event!(DEBUG, colors = palette.len(), "writing without a palette");
+            bits.write(0, 1);
+            let pixels: Vec<u32> = indices.iter().map(|index| palette[*index]).collect();
+            write_image(&mut bits, &pixels, width, true);
+        } else {
+            // Colour indexing transform, with the table delta coded as a one row image.
+            bits.write(1, 1);
+            bits.write(3, 2);
+            bits.write(palette.len() as u32 - 1, 8);
+            let deltas: Vec<u32> = palette.iter().enumerate().map(|(i, color)| match i {
+                0 => *color,
+                _ => subtract(*color, palette[i - 1]),
+            }).collect();
+            write_image(&mut bits, &deltas, palette.len(), false);
+            bits.write(0, 1);
+
+            // Fewer colours pack several indices into each pixel.
+            let width_bits = match palette.len() {
+                0..=2 => 3,
+                3..=4 => 2,
+                5..=16 => 1,
+                _ => 0,
+            };
+            let per_pixel = 1 << width_bits;
+            let bits_per_index = 8 >> width_bits;
+            let packed_width = width.div_ceil(per_pixel);
+            let packed: Vec<u32> = indices.chunks_exact(width).flat_map(|row| row.chunks(per_pixel).map(|chunk| {
+                let code = chunk.iter().enumerate().fold(0, |code, (i, index)| code | (*index as u32) << (i * bits_per_index));
+                0xFF000000 | code << 8
+            })).collect();
+            write_image(&mut bits, &packed, packed_width, true);
+        }
+
+        let data = bits.finish();
+        let chunk_length = data.len() as u32;
+        let mut file = Vec::with_capacity(data.len() + 21);
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&(4 + 8 + chunk_length + chunk_length % 2).to_le_bytes());
+        file.extend_from_slice(b"WEBPVP8L");
+        file.extend_from_slice(&chunk_length.to_le_bytes());
+        file.extend_from_slice(&data);
+        if chunk_length % 2 == 1 {
+            file.push(0);
+        }
+        writer.write_all(&file)
+    }
+}
+
+fn argb(pixel: &[u8]) -> u32 {
+    let alpha = if pixel.len() == 4 { pixel[3] } else { 0xFF };
+    u32::from_be_bytes([alpha, pixel[0], pixel[1], pixel[2]])
+}
+
+/// Per channel difference, wrapping.
+fn subtract(a: u32, b: u32) -> u32 {
+    let (a, b) = (a.to_be_bytes(), b.to_be_bytes());
+    u32::from_be_bytes([0, 1, 2, 3].map(|i| a[i].wrapping_sub(b[i])))
+}
+
+enum Token {
+    Literal(u32),
+    Copy { length: usize, distance_code: usize },
+}
+
+/// Write an image of ARGB `pixels` `width` wide with no colour cache. The main image also says
+/// it has no meta prefix codes, which sub-images leave out.
+fn write_image(bits: &mut BitWriter, pixels: &[u32], width: usize, main: bool) {
+    bits.write(0, 1);
+    if main {
+        bits.write(0, 1);
+    }
+
+    let tokens = tokenize(pixels, width);
+    let mut counts = [vec![0u32; GREEN_SYMBOLS], vec![0; 256], vec![0; 256], vec![0; 256], vec![0; DISTANCE_SYMBOLS]];
+    for token in &tokens {
+        match *token {
+            Token::Literal(color) => {
+                let [a, r, g, b] = color.to_be_bytes();
+                counts[0][g as usize] += 1;
+                counts[1][r as usize] += 1;
+                counts[2][b as usize] += 1;
+                counts[3][a as usize] += 1;
+            }
+            Token::Copy { length, distance_code } => {
+                counts[0][256 + prefix(length).0 as usize] += 1;
+                counts[4][prefix(distance_code).0 as usize] += 1;
+            }
+        }
+    }
+
+    let codes: Vec<Vec<(u32, u8)>> = counts.iter().map(|counts| write_code(bits, counts)).collect();
+    for token in &tokens {
+        match *token {
+            Token::Literal(color) => {
+                let [a, r, g, b] = color.to_be_bytes();
+                for (code, symbol) in [(0, g), (1, r), (2, b), (3, a)] {
+                    let (bits_, length) = codes[code][symbol as usize];
+                    bits.write(bits_, length);
+                }
+            }
+            Token::Copy { length, distance_code } => {
+                for (code, value, offset) in [(0, length, 256), (4, distance_code, 0)] {
+                    let (symbol, extra_bits, extra) = prefix(value);
+                    let (bits_, length) = codes[code][offset + symbol as usize];
+                    bits.write(bits_, length);
+                    bits.write(extra, extra_bits);
+                }
+            }
+        }
+    }
+}
+
+/// Greedy backward references copying the pixel to the left or the row above, whichever is
+/// longer. Distance codes 2 and 1 are the pixel to the left and above.
+fn tokenize(pixels: &[u32], width: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < pixels.len() {
+        let matching = |distance: usize| {
+            if i < distance {
+                return 0;
+            }
+            (i..pixels.len().min(i + MAX_LENGTH)).take_while(|&j| pixels[j] == pixels[j - distance]).count()
+        };
+        let (left, above) = (matching(1), matching(width));
+        let (length, distance_code) = if left >= above { (left, 2) } else { (above, 1) };
+
+        if length >= 3 {
+            tokens.push(Token::Copy { length, distance_code });
+            i += length;
+        } else {
+            tokens.push(Token::Literal(pixels[i]));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// The prefix symbol, extra bit count and extra bits for a length or distance code.
+fn prefix(value: usize) -> (u32, u8, u32) {
+    let d = value as u32 - 1;
+    if d < 4 {
+        return (d, 0, 0);
+    }
+    let high = 31 - d.leading_zeros();
+    let second = (d >> (high - 1)) & 1;
+    (2 * high + second, (high - 1) as u8, d & ((1 << (high - 1)) - 1))
+}
+
+/// Write a prefix code for symbols with `counts`, returning the bits and length of each
+/// symbol's code.
+fn write_code(bits: &mut BitWriter, counts: &[u32]) -> Vec<(u32, u8)> {
+    let used: Vec<usize> = (0..counts.len()).filter(|&symbol| counts[symbol] > 0).collect();
+
+    // Up to two symbols below 256 fit a simple code, with no bits at all for one.
+    if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+        let mut codes = vec![(0, 0); counts.len()];
+        let symbols = if used.is_empty() { vec![0] } else { used };
+        bits.write(1, 1);
+        bits.write(symbols.len() as u32 - 1, 1);
+        bits.write((symbols[0] > 1) as u32, 1);
+        bits.write(symbols[0] as u32, if symbols[0] > 1 { 8 } else { 1 });
+        if let Some(&second) = symbols.get(1) {
+            bits.write(second as u32, 8);
+            codes[symbols[0]] = (0, 1);
+            codes[second] = (1, 1);
+        }
+        return codes;
+    }
+
+    let lengths = huffman_lengths(counts, 15);
+    // Zero runs use 17 and 18, every other length is written as itself.
+    let mut symbols = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let zeros = lengths[i..].iter().take_while(|length| **length == 0).count().min(138);
+        if zeros >= 11 {
+            symbols.push((18, 7, zeros as u32 - 11));
+            i += zeros;
+        } else if zeros >= 3 {
+            symbols.push((17, 3, zeros as u32 - 3));
+            i += zeros;
+        } else {
+            symbols.push((lengths[i] as usize, 0, 0));
+            i += 1;
+        }
+    }
+
+    let mut length_counts = [0u32; 19];
+    for (symbol, _, _) in &symbols {
+        length_counts[*symbol] += 1;
+    }
+    let length_lengths = huffman_lengths(&length_counts, 7);
+    let length_codes = canonical(&length_lengths);
+    let written = CODE_LENGTH_ORDER.iter().rposition(|&symbol| length_lengths[symbol] > 0).unwrap_or(0).max(3) + 1;
+
+    bits.write(0, 1);
+    bits.write(written as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..written] {
+        bits.write(length_lengths[symbol] as u32, 3);
+    }
+    bits.write(0, 1);
+    for (symbol, extra_bits, extra) in symbols {
+        let (code, length) = length_codes[symbol];
+        bits.write(code, length);
+        bits.write(extra, extra_bits);
+    }
+
+    canonical(&lengths)
+}
+
+/// Huffman code lengths no longer than `limit` for symbols with `counts`. At least two symbols
+/// get a length so that the code is a complete tree.
+fn huffman_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    let used = counts.iter().filter(|count| **count > 0).count();
+    for count in counts.iter_mut().filter(|count| **count == 0).take(2usize.saturating_sub(used)) {
+        *count = 1;
+    }
+
+    loop {
+        let lengths = unlimited_lengths(&counts);
+        if lengths.iter().all(|length| *length <= limit) {
+            return lengths;
+        }
+        // Flatten the counts until the tree is shallow enough.
+        for count in counts.iter_mut().filter(|count| **count > 0) {
+            *count = (*count >> 1).max(1);
+        }
+    }
+}
+
+fn unlimited_lengths(counts: &[u32]) -> Vec<u8> {
+    // Nodes are (count, children), leaves being symbols.
+    let mut nodes: Vec<(u64, Vec<usize>)> = counts.iter().enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(symbol, count)| (*count as u64, vec![symbol]))
+        .collect();
+    let mut lengths = vec![0u8; counts.len()];
+
+    while nodes.len() > 1 {
+        nodes.sort_by_key(|node| std::cmp::Reverse(node.0));
+        let (a, b) = (nodes.pop().unwrap(), nodes.pop().unwrap());
+        for &symbol in a.1.iter().chain(&b.1) {
+            lengths[symbol] += 1;
+        }
+        nodes.push((a.0 + b.0, [a.1, b.1].concat()));
+    }
+    lengths
+}
+
+/// Canonical codes for `lengths`, bit reversed as they are read least significant bit first.
+fn canonical(lengths: &[u8]) -> Vec<(u32, u8)> {
+    let mut codes = vec![(0, 0); lengths.len()];
+    let mut code = 0u32;
+    for length in 1..=15 {
+        for (symbol, _) in lengths.iter().enumerate().filter(|(_, l)| **l == length) {
+            codes[symbol] = (code.reverse_bits() >> (32 - length as u32), length);
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// Bits packed least significant first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u8) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits as u32;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Decode `webp` with a decoder written independently of this one, as RGBA.
+#[cfg(test)]
+fn read_webp(webp: &[u8]) -> (u32, u32, Vec<u8>) {
+    let mut decoder = image_webp::WebPDecoder::new(io::Cursor::new(webp)).unwrap();
+    let (width, height) = decoder.dimensions();
+    let mut pixels = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_image(&mut pixels).unwrap();
+    if !decoder.has_alpha() {
+        pixels = pixels.chunks_exact(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF]).collect();
+    }
+    (width, height, pixels)
+}
+
+#[test]
+fn test_to_webp() {
+    let decoded = crate::decode(include_bytes!("../images/test_embedded_palette.pie"), None).unwrap();
+    let mut webp = Vec::new();
+    decoded.to_webp(&mut webp).unwrap();
+    assert_eq!(b"RIFF", &webp[..4]);
+    assert_eq!(webp.len() - 8, u32::from_le_bytes(webp[4..8].try_into().unwrap()) as usize);
+    assert_eq!(b"WEBPVP8L", &webp[8..16]);
+    assert_eq!((8, 8, decoded.to_rgba8()), read_webp(&webp));
+
+    // Each size of packed indices, then too many colours to index at all. Odd widths leave the
+    // last packed pixel of each row partly empty.
+    for colors in [1, 2, 3, 5, 16, 17, 256, 300] {
+        for format in [PixelFormat::RGB, PixelFormat::RGBA] {
+            let (width, height) = (37, 11);
+            let pixels: Vec<u8> = (0..width * height).map(|i: u32| {
+                // Runs of a colour, repeated rows and noise, for every kind of backward reference.
+                let color = if i / width % 3 == 1 { (i / 5) % colors } else { (i * 7 + i / width) % colors };
+                [color as u8, (color >> 8) as u8 * 90, 0x40, 0xFF - (color % 2) as u8 * 0x80]
+            }).flat_map(|pixel| pixel.into_iter().take(if format == PixelFormat::RGBA { 4 } else { 3 })).collect();
+            let image = DecodedPIE {
+                width: width as u16, height: height as u16, format, pixels,
+                palette: crate::Palette { format, colors: Vec::new() },
+                chunks: Vec::new(), comments: Vec::new(), density: None,
+            };
+
+            let mut webp = Vec::new();
+            image.to_webp(&mut webp).unwrap();
+            assert_eq!((width, height, image.to_rgba8()), read_webp(&webp), "{} colours, {:?}", colors, format);
+        }
+    }
+
+    assert_eq!(vec![(0, 0, 0), (3, 0, 0), (4, 1, 0), (4, 1, 1), (5, 1, 0), (6, 2, 0), (23, 10, 1023)], [1, 4, 5, 6, 7, 9, 4096].map(prefix).to_vec());
+}
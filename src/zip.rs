@@ -0,0 +1,105 @@
+//! Reading entries of zip archives, for Krita palettes and [`crate::ZipSource`] asset bundles.
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+
+use crate::{section, DecodeError};
+
+fn u16_le(bytes: &[u8], at: usize) -> Result<usize, DecodeError> {
+    let b = section(bytes, at..at + 2)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]) as usize)
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Result<usize, DecodeError> {
+    let b = section(bytes, at..at + 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+}
+
+/// Where the end of central directory record of the zip archive `bytes` starts, if it has one.
+pub(crate) fn directory_end(bytes: &[u8]) -> Option<usize> {
+    // The record is 22 bytes, followed by a comment of up to 64KiB.
+    (0..bytes.len().saturating_sub(21)).rev().take(22 + 0xFFFF).find(|&at| bytes[at..].starts_with(&[0x50, 0x4B, 0x05, 0x06]))
+}
+
+/// The uncompressed contents of the entry called `name` in the zip archive `bytes`, or None if
+/// it has none. Only stored and deflated entries are supported, which is what most tools write.
+/// Archives that cannot be read fail with `invalid` at the offset of the problem.
+pub(crate) fn read_entry(bytes: &[u8], name: &str, invalid: impl Fn(usize) -> DecodeError) -> Result<Option<Vec<u8>>, DecodeError> {
+    let end = directory_end(bytes).ok_or(invalid(bytes.len()))?;
+    let entries = u16_le(bytes, end + 10)?;
+    let mut at = u32_le(bytes, end + 16)?;
+
+    for _ in 0..entries {
+        if !section(bytes, at..at + 4)?.starts_with(&[0x50, 0x4B, 0x01, 0x02]) {
+            return Err(invalid(at));
+        }
+        let method = u16_le(bytes, at + 10)?;
+        let compressed = u32_le(bytes, at + 20)?;
+        let uncompressed = u32_le(bytes, at + 24)?;
+        let name_length = u16_le(bytes, at + 28)?;
+        let skip = u16_le(bytes, at + 30)? + u16_le(bytes, at + 32)?;
+        let local = u32_le(bytes, at + 42)?;
+        let entry_name = section(bytes, at + 46..at + 46 + name_length)?;
+        at += 46 + name_length + skip;
+
+        if entry_name != name.as_bytes() {
+            continue;
+        }
+        if !section(bytes, local..local + 4)?.starts_with(&[0x50, 0x4B, 0x03, 0x04]) {
+            return Err(invalid(local));
+        }
+        let start = local + 30 + u16_le(bytes, local + 26)? + u16_le(bytes, local + 28)?;
+        let data = section(bytes, start..start.saturating_add(compressed))?;
+        return match method {
+            0 => Ok(Some(data.to_vec())),
+            8 => {
+                // The size is only a hint from the archive. Deflate expands at most 1032 times, so
+                // a larger one is a lie that could ask for gigabytes.
+                let mut inflated = Vec::with_capacity(uncompressed.min(data.len().saturating_mul(1032)));
+                DeflateDecoder::new(data).read_to_end(&mut inflated).map_err(|_| invalid(start))?;
+                Ok(Some(inflated))
+            }
+            _ => Err(invalid(local + 8)),
+        };
+    }
+
+    Ok(None)
+}
+
+/// A zip archive of `(name, method, data)` entries, where `data` is already compressed.
+#[cfg(test)]
+pub(crate) fn archive(entries: &[(&str, u16, &[u8])]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, method, data) in entries {
+        let header = |signature: &[u8]| {
+            let mut header = signature.to_vec();
+            header.extend_from_slice(&[20, 0, 0, 0]);
+            header.extend_from_slice(&method.to_le_bytes());
+            header.extend_from_slice(&[0; 8]);
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0, 0]);
+            header
+        };
+        directory.extend_from_slice(&[0x50, 0x4B, 0x01, 0x02, 20, 0]);
+        directory.extend_from_slice(&header(&[])[..24]);
+        directory.extend_from_slice(&[0; 12]);
+        directory.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        bytes.extend_from_slice(&header(&[0x50, 0x4B, 0x03, 0x04]));
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    let offset = bytes.len() as u32;
+    bytes.extend_from_slice(&directory);
+    bytes.extend_from_slice(&[0x50, 0x4B, 0x05, 0x06, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes().repeat(2));
+    bytes.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&offset.to_le_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    bytes
+}
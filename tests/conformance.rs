@@ -0,0 +1,61 @@
+//! Every .pie in images/ must decode to exactly the pixels of the .png beside it. Run with
+//! `PIE_UPDATE_GOLDEN=1` to re-encode the .pie files from the PNGs after a deliberate change to
+//! the encoder. Without it the bundled files are only read, never written.
+use std::{env, fs::{self, File}, path::Path};
+
+use pie_format::{decode, dimensions, encode, fix, PixelFormat};
+
+/// The pixels of a PNG as RGB or RGBA, depending on whether it has alpha.
+fn load_png(path: &Path) -> (u16, u16, Vec<u8>) {
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|va| [va[0], va[0], va[0], va[1]]).collect(),
+        _ => buf,
+    };
+    (info.width as u16, info.height as u16, pixels)
+}
+
+#[test]
+fn test_conformance() {
+    let update = env::var_os("PIE_UPDATE_GOLDEN").is_some();
+    let mut pngs: Vec<_> = fs::read_dir("images").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+        .collect();
+    pngs.sort();
+    assert!(!pngs.is_empty());
+
+    for png in pngs {
+        let golden = png.with_extension("pie");
+        let (width, height, pixels) = load_png(&png);
+        if update {
+            fs::write(&golden, encode(width, height, &pixels, true, None).unwrap().to_bytes()).unwrap();
+        }
+
+        let mut bytes = fs::read(&golden).unwrap();
+        // An older encoder wrote the RGBA colours of PNGs with alpha without setting the
+        // transparency flag. Decode those the way readers are expected to repair them.
+        let rgba = pixels.len() == width as usize * height as usize * 4;
+        if rgba && dimensions(bytes.as_slice()).unwrap().2 == PixelFormat::RGB {
+            bytes = fix(&bytes).unwrap();
+        }
+
+        let decoded = decode(&bytes, None).unwrap();
+        assert_eq!((width, height), (decoded.width, decoded.height), "{}", golden.display());
+        // Compare as RGBA so that an opaque RGB file matches an RGBA PNG with no transparency.
+        let expected = if pixels.len() == width as usize * height as usize * 3 {
+            pixels.chunks_exact(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF]).collect()
+        } else {
+            pixels
+        };
+        assert!(expected == decoded.to_rgba8(), "{} does not match its PNG", golden.display());
+    }
+}
@@ -0,0 +1,37 @@
+//! Deliberately broken files in tests/corpus, each with the exact error decoding must give.
+use pie_format::{decode, decode_header, decode_metadata, DecodeError};
+
+fn corpus(name: &str) -> Vec<u8> {
+    std::fs::read(format!("tests/corpus/{}", name)).unwrap()
+}
+
+#[test]
+fn test_corpus() {
+    let cases = [
+        ("empty.pie", DecodeError::Truncated { expected: 11, found: 0 }),
+        ("bad_magic.pie", DecodeError::BadMagic { found: *b"PNG" }),
+        ("truncated_header.pie", DecodeError::Truncated { expected: 11, found: 7 }),
+        ("unknown_version.pie", DecodeError::UnsupportedVersion { found: 9, max_supported: 2 }),
+        ("truncated_runs.pie", DecodeError::Truncated { expected: 11 + 0xFFFF * 2, found: 69 }),
+        ("truncated_palette.pie", DecodeError::Truncated { expected: 91, found: 73 }),
+        ("runs_overflow.pie", DecodeError::WrongPixelCount { expected: 64, found: 65 }),
+        ("index_past_palette.pie", DecodeError::IndexOutOfPalette { index: 9, offset: 56 }),
+    ];
+
+    for (name, error) in cases {
+        assert_eq!(Err(error), decode(corpus(name), None), "{}", name);
+    }
+}
+
+#[test]
+fn test_corpus_header() {
+    assert_eq!(Err(DecodeError::UnsupportedVersion { found: 9, max_supported: 2 }), decode_header(corpus("unknown_version.pie")));
+    assert!(decode_header(corpus("runs_overflow.pie")).is_ok());
+}
+
+#[test]
+fn test_corpus_chunks() {
+    let bytes = corpus("truncated_chunk.pie");
+    assert!(decode(&bytes, None).is_ok());
+    assert_eq!(Err(DecodeError::Truncated { expected: 181, found: 83 }), decode_metadata(&bytes));
+}
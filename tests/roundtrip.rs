@@ -0,0 +1,52 @@
+//! Random images through encode and decode with every combination of options, checking the
+//! pixels come back byte for byte.
+use pie_format::{decode, encode, EncodedPIE, Palette, PixelFormat};
+use proptest::{collection::{hash_set, vec}, prelude::*};
+
+/// A palette of 1 to 256 unique colours and an image indexing into it.
+fn image() -> impl Strategy<Value = (u16, u16, Palette, Vec<u8>)> {
+    (any::<bool>(), hash_set(any::<[u8; 4]>(), 1..=256), 1..48u16, 1..48u16).prop_flat_map(|(alpha, colors, width, height)| {
+        let format = if alpha { PixelFormat::RGBA } else { PixelFormat::RGB };
+        let stride = if alpha { 4 } else { 3 };
+
+        // Dropping alpha can make colours collide, which is fine as encoding finds either.
+        let palette = Palette { format, colors: colors.iter().flat_map(|color| color[..stride].to_vec()).collect() };
+        let count = colors.len();
+        vec(0..count, width as usize * height as usize).prop_map(move |indices| {
+            let pixels = indices.iter().flat_map(|index| palette.colors[index * stride..index * stride + stride].to_vec()).collect();
+            (width, height, palette.clone(), pixels)
+        })
+    })
+}
+
+proptest! {
+    #[test]
+    fn roundtrip((width, height, palette, pixels) in image(), embed in any::<bool>(), external in any::<bool>()) {
+        let given = external.then_some(&palette);
+        let encoded = encode(width, height, &pixels, embed, given).unwrap();
+        let bytes = encoded.to_bytes();
+        prop_assert_eq!(&encoded, &EncodedPIE::from_bytes(&bytes).unwrap());
+
+        // Without an embedded palette the decoder needs the one encoding used.
+        let generated;
+        let palette_for_decode = match (embed, given) {
+            (true, _) => None,
+            (false, Some(palette)) => Some(palette),
+            (false, None) => {
+                generated = encode(width, height, &pixels, true, None).unwrap().palette.unwrap();
+                Some(&generated)
+            }
+        };
+
+        let decoded = decode(&bytes, palette_for_decode).unwrap();
+        prop_assert_eq!((width, height, palette.format), (decoded.width, decoded.height, decoded.format));
+        prop_assert_eq!(pixels, decoded.pixels);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_matches((width, height, palette, pixels) in image(), embed in any::<bool>(), external in any::<bool>()) {
+        let given = external.then_some(&palette);
+        prop_assert_eq!(encode(width, height, &pixels, embed, given), pie_format::encode_parallel(width, height, &pixels, embed, given));
+    }
+}
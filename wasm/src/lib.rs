@@ -0,0 +1,87 @@
+//! Plain C ABI exports of the decoder for WebAssembly, so the browser wrapper in pkg/ needs no
+//! bindings generator. JavaScript copies bytes into memory from [`pie_alloc`], calls
+//! [`pie_decode`] and reads the result back through the `pie_*` getters.
+//!
+//! Build with `cargo build -p pie-wasm --release --target wasm32-unknown-unknown` and copy
+//! `pie_wasm.wasm` into pkg/.
+use std::cell::RefCell;
+
+use pie_format::{decode, Palette, PixelFormat};
+
+/// The last decoded image as RGBA, or the error message if decoding failed.
+struct Output {
+    width: u16,
+    height: u16,
+    bytes: Vec<u8>,
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Output> = const { RefCell::new(Output { width: 0, height: 0, bytes: Vec::new() }) };
+}
+
+/// Reserve `length` bytes for JavaScript to write into. Give them back with [`pie_free`].
+#[no_mangle]
+pub extern "C" fn pie_alloc(length: usize) -> *mut u8 {
+    let mut bytes = vec![0u8; length];
+    let pointer = bytes.as_mut_ptr();
+    std::mem::forget(bytes);
+    pointer
+}
+
+/// Free bytes from [`pie_alloc`].
+///
+/// # Safety
+///
+/// `pointer` and `length` must be exactly what was given to and returned by [`pie_alloc`].
+#[no_mangle]
+pub unsafe extern "C" fn pie_free(pointer: *mut u8, length: usize) {
+    drop(Vec::from_raw_parts(pointer, length, length));
+}
+
+/// Decode the file in `bytes`, with `palette` as the external palette if `palette_length` is not
+/// 0. `palette_rgba` says whether its stride is 4 rather than 3. Returns whether decoding
+/// succeeded: the RGBA pixels are then at [`pie_output`], otherwise the error message is.
+///
+/// # Safety
+///
+/// Both pointers must point to that many readable bytes, such as ones from [`pie_alloc`].
+#[no_mangle]
+pub unsafe extern "C" fn pie_decode(bytes: *const u8, length: usize, palette: *const u8, palette_length: usize, palette_rgba: bool) -> bool {
+    let bytes = std::slice::from_raw_parts(bytes, length);
+    let palette = (palette_length > 0).then(|| Palette {
+        format: if palette_rgba { PixelFormat::RGBA } else { PixelFormat::RGB },
+        colors: std::slice::from_raw_parts(palette, palette_length).to_vec(),
+    });
+
+    let (output, ok) = match decode(bytes, palette.as_ref()) {
+        Ok(decoded) => (Output { width: decoded.width, height: decoded.height, bytes: decoded.to_rgba8() }, true),
+        Err(error) => (Output { width: 0, height: 0, bytes: error.to_string().into_bytes() }, false),
+    };
+    OUTPUT.with(|cell| *cell.borrow_mut() = output);
+    ok
+}
+
+/// Width of the last image from [`pie_decode`].
+#[no_mangle]
+pub extern "C" fn pie_width() -> u32 {
+    OUTPUT.with(|cell| cell.borrow().width as u32)
+}
+
+/// Height of the last image from [`pie_decode`].
+#[no_mangle]
+pub extern "C" fn pie_height() -> u32 {
+    OUTPUT.with(|cell| cell.borrow().height as u32)
+}
+
+/// The RGBA pixels, or UTF-8 error message, from the last [`pie_decode`]. Valid until the next
+/// call.
+#[no_mangle]
+pub extern "C" fn pie_output() -> *const u8 {
+    OUTPUT.with(|cell| cell.borrow().bytes.as_ptr())
+}
+
+/// Length in bytes of [`pie_output`].
+#[no_mangle]
+pub extern "C" fn pie_output_length() -> usize {
+    OUTPUT.with(|cell| cell.borrow().bytes.len())
+}